@@ -1,3 +1,4 @@
+; expect stack_top=0x5 sp=0x1
 .org 20
 0x2
 0x3
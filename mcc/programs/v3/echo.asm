@@ -1,4 +1,6 @@
 # echos a line of text and exits when reading a lf
+; setup stdin="hi\n"
+; expect stdout="hi"
 
 .org 20 # data
 &&chardev
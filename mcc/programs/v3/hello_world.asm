@@ -1,3 +1,4 @@
+; expect stdout="Hello World!!!\n"
 
 .org 20 # data
 &&data_chunk1
@@ -9,6 +10,8 @@ psi
 mdp
 
 loop:
+; dp walks through the data chunks
+; mccasm: allow(dp-unknown)
 psi
 psi
 psi
@@ -1,11 +1,14 @@
 # VERSION v3
 # multiplies a and b together and leaves the result on the stack when the program exits
+; expect stack_top=0x9 sp=0x1
 
 .org 20 # data
 &&data_ret
 &&ret
-0x3 # a
-0x3 # b
+a: ; mccasm: allow(unused-label)
+0x3
+b: ; mccasm: allow(unused-label)
+0x3
 &&mul
 &&mul_data
 0x0 # tmp
@@ -34,7 +37,7 @@ psi # mul_data0
 psi # mul_data1
 mdp
 jmp
-ret:
+ret: ; ( data_ret0 data_ret1 -- awnser )
 psd # push awnser on the stack
 nop ; break
 di ; move a 2 places back
@@ -51,7 +54,7 @@ psi
 jmp
 
 
-mul:
+mul: ; ( ret0 ret1 a b -- )
   poi # write b to memory
   di # dp: loop addr
 .org 50 ; needed so we can jump back to loop
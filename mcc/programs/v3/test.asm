@@ -1,3 +1,4 @@
+; expect ip=0xff dp=0x23 sp=0x0
 .org 20
 0x0
 data_move_to_me: &&end
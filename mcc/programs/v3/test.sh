@@ -0,0 +1,18 @@
+#!/bin/bash
+# USAGE: test.sh [-O] [project]
+# Runs the programs and checks the '; expect' comments in their source, -O assembles them with mccasm -O
+
+flags=()
+if [[ "$1" == "-O" ]] ; then
+  flags=(-O)
+  shift
+fi
+
+all=(mul test add_test hello_world echo)
+
+if [[ -z $1 ]] || [[ "$1" == "all" ]] ; then
+  mcctest ${flags[@]} ${all[@]/%/.asm}
+  exit $?
+fi
+
+mcctest ${flags[@]} $1.asm
@@ -8,10 +8,17 @@ fi
 if [ "$1" = "-u" ] ; then
   sudo rm -f /usr/local/bin/mccasm
   sudo rm -f /usr/local/bin/mccemu
+  sudo rm -f /usr/local/bin/mcctest
+  sudo rm -f /usr/local/bin/mcctrace
+  sudo rm -f /usr/local/bin/mccls
+  sudo rm -f /usr/local/bin/mccprop
+  sudo rm -f /usr/local/bin/mccsym
+  sudo rm -f /usr/local/bin/mccequiv
+  sudo rm -f /usr/local/bin/mccopt
   exit 0
 fi
 
-if ! cargo build --release --bin mccemu --bin mccasm ; then
+if ! cargo build --release --bin mccemu --bin mccasm --bin mcctest --bin mcctrace --bin mccls --bin mccprop --bin mccsym --bin mccequiv --bin mccopt ; then
   echo "Build failed"
   exit 1
 fi
@@ -22,6 +29,27 @@ fi
 if ! sudo cp target/release/mccemu /usr/local/bin/mccemu ; then
   exit 1
 fi
+if ! sudo cp target/release/mcctest /usr/local/bin/mcctest ; then
+  exit 1
+fi
+if ! sudo cp target/release/mcctrace /usr/local/bin/mcctrace ; then
+  exit 1
+fi
+if ! sudo cp target/release/mccls /usr/local/bin/mccls ; then
+  exit 1
+fi
+if ! sudo cp target/release/mccprop /usr/local/bin/mccprop ; then
+  exit 1
+fi
+if ! sudo cp target/release/mccsym /usr/local/bin/mccsym ; then
+  exit 1
+fi
+if ! sudo cp target/release/mccequiv /usr/local/bin/mccequiv ; then
+  exit 1
+fi
+if ! sudo cp target/release/mccopt /usr/local/bin/mccopt ; then
+  exit 1
+fi
 
 if ! sudo chmod +x /usr/local/bin/mccasm ; then
   exit 1
@@ -29,3 +57,24 @@ fi
 if ! sudo chmod +x /usr/local/bin/mccemu ; then
   exit 1
 fi
+if ! sudo chmod +x /usr/local/bin/mcctest ; then
+  exit 1
+fi
+if ! sudo chmod +x /usr/local/bin/mcctrace ; then
+  exit 1
+fi
+if ! sudo chmod +x /usr/local/bin/mccls ; then
+  exit 1
+fi
+if ! sudo chmod +x /usr/local/bin/mccprop ; then
+  exit 1
+fi
+if ! sudo chmod +x /usr/local/bin/mccsym ; then
+  exit 1
+fi
+if ! sudo chmod +x /usr/local/bin/mccequiv ; then
+  exit 1
+fi
+if ! sudo chmod +x /usr/local/bin/mccopt ; then
+  exit 1
+fi
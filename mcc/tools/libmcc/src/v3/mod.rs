@@ -19,6 +19,20 @@ pub enum Instruction {
     Mul,
 }
 
+///Reads an image written with `mccasm --format bin` (2 nibbles per byte, the low one first),
+///None when it isn't 128 bytes
+pub fn from_bin_packed(data: &[u8]) -> Option<[u4; 256]> {
+    if data.len() != 128 {
+        return None;
+    }
+    let mut out = [u4::ZERO; 256];
+    for (i, byte) in data.iter().enumerate() {
+        out[i * 2] = u4::from_low(*byte);
+        out[i * 2 + 1] = u4::from_high(*byte);
+    }
+    Some(out)
+}
+
 impl Instruction {
     pub fn into_u4(self) -> u4 {
         u4::from_low(self as u8)
@@ -26,6 +40,28 @@ impl Instruction {
     pub fn from_u4(val: u4) -> Self {
         unsafe { std::mem::transmute(val) }
     }
+    pub fn as_str(self) -> &'static str {
+        use Instruction::*;
+        match self {
+            Nop => "nop",
+            Psi => "psi",
+            Psd => "psd",
+            Poi => "poi",
+            Pod => "pod",
+            Swp => "swp",
+            Mdp => "mdp",
+            Di => "di",
+            Dd => "dd",
+            Jmp => "jmp",
+            Jnz => "jnz",
+
+            Inc => "inc",
+            Dec => "dec",
+            Add => "add",
+            Sub => "sub",
+            Mul => "mul",
+        }
+    }
     pub fn try_from_str(string: &str) -> Option<Self> {
         use Instruction::*;
         match string {
@@ -50,3 +86,23 @@ impl Instruction {
         }
     }
 }
+
+///Instructions separated by whitespace like `swp di swp`
+pub fn parse_sequence(text: &str) -> Result<Vec<Instruction>, String> {
+    text.split_whitespace()
+        .map(|word| {
+            Instruction::try_from_str(word).ok_or_else(|| format!("unknown instruction '{}'", word))
+        })
+        .collect()
+}
+
+///The words of a sequence separated by spaces, `(nothing)` for an empty one
+pub fn format_sequence(code: &[Instruction]) -> String {
+    match code.is_empty() {
+        true => "(nothing)".into(),
+        false => {
+            let words: Vec<_> = code.iter().map(|inst| inst.as_str()).collect();
+            words.join(" ")
+        }
+    }
+}
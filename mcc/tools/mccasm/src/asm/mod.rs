@@ -1,25 +1,34 @@
-use libmcc::u4;
-use std::fmt::Display;
+use std::{collections::HashMap, error::Error, fmt::Display, ops::Range};
+
+use v3::lint::{Level, Lint};
 //pub mod v2;
 pub mod v3;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Stage {
     Lex,
+    Parse,
     CodeGen,
+    Lint(Lint),
 }
 impl Display for Stage {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Stage::Lex => fmt.write_str("LEX"),
+            Stage::Parse => fmt.write_str("PARSE"),
             Stage::CodeGen => fmt.write_str("CGEN"),
+            Stage::Lint(lint) => fmt.write_fmt(format_args!("LINT({})", lint)),
         }
     }
 }
+#[derive(Debug, Clone)]
 pub struct AsmError {
-    linenum: Option<usize>,
-    code_snip: Box<str>,
-    message: Box<str>,
-    stage: Stage,
+    pub linenum: Option<usize>,
+    ///Byte range in the source
+    pub span: Option<Range<usize>>,
+    pub code_snip: Box<str>,
+    pub message: Box<str>,
+    pub stage: Stage,
 }
 impl Display for AsmError {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -37,9 +46,72 @@ impl Display for AsmError {
     }
 }
 
-pub fn assemble(input: String) -> Result<[u4; 256], AsmError> {
-    let lexed = v3::lexing::lex(input)?;
-    let code = v3::codegen::gencode(lexed)?;
-    //println!("{:?}", code);
-    Ok(code)
+///All errors of a source that failed to assemble
+#[derive(Debug, Clone)]
+pub struct Diagnostics(pub Vec<AsmError>);
+impl Display for Diagnostics {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, err) in self.0.iter().enumerate() {
+            if i != 0 {
+                fmt.write_str("\n")?;
+            }
+            err.fmt(fmt)?;
+        }
+        Ok(())
+    }
+}
+impl Error for Diagnostics {}
+
+///Settings for `assemble`, the defaults match the mccasm binary
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    ///Levels that replace the default level of a lint
+    pub lints: HashMap<Lint, Level>,
+    ///Apply the peephole rewrites, the changes end up in `Assembly::optimizations`
+    pub optimize: bool,
+}
+
+///Assembles v3 source, every parse error is reported but codegen stops at its first error.
+///Lints at the deny level fail the assembly, lints at the warn level end up in `Assembly::warnings`
+pub fn assemble(source: &str, options: &Options) -> Result<v3::codegen::Assembly, Diagnostics> {
+    let ast = v3::parser::parse(source);
+    let lines = v3::ast::LineIndex::new(source);
+    if !ast.errors.is_empty() {
+        let errors = ast
+            .errors
+            .iter()
+            .map(|err| AsmError {
+                linenum: Some(lines.linenum(err.span.start)),
+                span: Some(err.span.clone()),
+                code_snip: source[err.span.clone()].into(),
+                message: err.message.clone(),
+                stage: Stage::Parse,
+            })
+            .collect();
+        return Err(Diagnostics(errors));
+    }
+    let mut assembly = v3::codegen::gencode(source, &ast).map_err(|err| Diagnostics(vec![err]))?;
+    let mut optimizations = Vec::new();
+    let ast = if options.optimize {
+        let (optimized, changes) = v3::peephole::optimize(source, &ast, &assembly);
+        assembly =
+            v3::codegen::gencode(source, &optimized).map_err(|err| Diagnostics(vec![err]))?;
+        optimizations = changes;
+        optimized
+    } else {
+        ast
+    };
+    let (errors, warnings): (Vec<_>, Vec<_>) =
+        v3::lint::check(source, &ast, &assembly, &options.lints)
+            .into_iter()
+            .partition(|(level, _)| *level == Level::Deny);
+    if !errors.is_empty() {
+        return Err(Diagnostics(
+            errors.into_iter().map(|(_, err)| err).collect(),
+        ));
+    }
+    assembly.warnings = warnings.into_iter().map(|(_, err)| err).collect();
+    assembly.optimizations = optimizations;
+    Ok(assembly)
 }
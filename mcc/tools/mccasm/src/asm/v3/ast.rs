@@ -0,0 +1,155 @@
+use std::ops::Range;
+
+use libmcc::{u4, v3::Instruction};
+
+use super::tokenizer::Token;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemKind {
+    ///`.org XX`
+    Org(u8),
+    ///`.dp XX`, tells the dp analysis the value of dp at this point
+    Dp(u8),
+    ///`name:`
+    LabelDef(Box<str>),
+    ///`&name` or `&&name` when wide
+    LabelRef {
+        name: Box<str>,
+        wide: bool,
+    },
+    Instruction(Instruction),
+    ///`0xA`
+    HexLiteral(u4),
+    ///`"text"`, every byte is written as 2 nibbles with the high nibble first
+    String(Vec<u8>),
+    ///Source that failed to parse, the message is in `Ast::errors`
+    Error,
+}
+
+impl ItemKind {
+    ///Amount of nibbles the item writes
+    pub fn size(&self) -> usize {
+        match self {
+            ItemKind::Org(_) | ItemKind::Dp(_) | ItemKind::LabelDef(_) | ItemKind::Error => 0,
+            ItemKind::LabelRef { wide, .. } => 1 + *wide as usize,
+            ItemKind::Instruction(_) | ItemKind::HexLiteral(_) => 1,
+            ItemKind::String(bytes) => bytes.len() * 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub kind: ItemKind,
+    ///Byte range in the source
+    pub span: Range<usize>,
+    ///Whitespace, newlines and comments before the item
+    pub trivia: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: Box<str>,
+    pub span: Range<usize>,
+}
+
+///Syntax tree of a source file
+///
+///The trivia and spans of the items followed by `trailing_trivia` cover the whole source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ast {
+    pub items: Vec<Item>,
+    pub trailing_trivia: Vec<Token>,
+    pub errors: Vec<ParseError>,
+}
+
+///Converts byte offsets to lines and columns
+pub struct LineIndex {
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(source.match_indices('\n').map(|(pos, _)| pos + 1));
+        Self {
+            starts,
+            len: source.len(),
+        }
+    }
+
+    ///Line of the offset starting at 0
+    pub fn line(&self, offset: usize) -> usize {
+        self.starts.partition_point(|start| *start <= offset) - 1
+    }
+
+    ///Line of the offset starting at 1 like in error messages
+    pub fn linenum(&self, offset: usize) -> usize {
+        self.line(offset) + 1
+    }
+
+    pub fn col(&self, offset: usize) -> usize {
+        offset - self.starts[self.line(offset)]
+    }
+
+    ///Offset of the end of a line before its line feed
+    fn line_end(&self, line: usize) -> usize {
+        self.starts.get(line + 1).map_or(self.len, |next| next - 1)
+    }
+
+    ///Offset of a line and column, clamped to the source and the end of the line
+    pub fn offset(&self, line: usize, col: usize) -> usize {
+        let line = line.min(self.starts.len() - 1);
+        (self.starts[line] + col).min(self.line_end(line))
+    }
+
+    ///Column in UTF-16 code units like the language server protocol counts them
+    pub fn col_utf16(&self, source: &str, offset: usize) -> usize {
+        let start = self.starts[self.line(offset)];
+        source[start..offset].encode_utf16().count()
+    }
+
+    ///Like `offset` with the column in UTF-16 code units, never lands inside a character
+    pub fn offset_utf16(&self, source: &str, line: usize, col: usize) -> usize {
+        let line = line.min(self.starts.len() - 1);
+        let (start, end) = (self.starts[line], self.line_end(line));
+        let mut units = 0;
+        for (pos, char) in source[start..end].char_indices() {
+            if units >= col {
+                return start + pos;
+            }
+            units += char.len_utf16();
+        }
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineIndex;
+
+    #[test]
+    fn offsets_are_clamped_to_the_line() {
+        let source = "psi\ndi\n";
+        let index = LineIndex::new(source);
+        assert_eq!(index.offset(1, 1), 5);
+        assert_eq!(index.offset(0, 10), 3);
+        assert_eq!(index.offset(1, 10), 6);
+        assert_eq!(index.offset(10, 10), source.len());
+    }
+
+    #[test]
+    fn utf16_columns() {
+        let source = "# é😀 x\npsi";
+        let index = LineIndex::new(source);
+        let x = source.find('x').unwrap();
+        assert_eq!(index.col(x), 9);
+        assert_eq!(index.col_utf16(source, x), 6);
+        assert_eq!(index.offset_utf16(source, 0, 6), x);
+        // inside the surrogate pair of the emoji
+        assert_eq!(index.offset_utf16(source, 0, 4), x - 1);
+        assert_eq!(index.offset_utf16(source, 0, 99), x + 1);
+        assert_eq!(index.offset_utf16(source, 1, 2), source.len() - 1);
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use libmcc::{u4, v3::Instruction};
+
+use super::{
+    codegen::Labels,
+    dp::{self, DpAnalysis},
+    stack::ENTRY,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    ///Execution continues at the next address
+    Fall,
+    Jmp,
+    Jnz,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    ///None when the target is computed from values that aren't known
+    pub target: Option<u8>,
+}
+
+///A run of instructions that is only entered at its first address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u8,
+    ///Address of the last instruction
+    pub end: u8,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    image: [u4; 256],
+    ///dp before every reached instruction
+    dp: BTreeMap<u8, Option<u8>>,
+}
+
+///Builds the graph of every instruction the dp analysis reached.
+///
+///A block starts at the entry point, at every jump target, after every jmp/jnz and at the addresses in `leaders`
+pub fn build(image: &[u4; 256], analysis: &DpAnalysis, leaders: &[u8]) -> Cfg {
+    let reached: BTreeSet<u8> = analysis.dp.keys().copied().collect();
+    let inst = |addr: u8| Instruction::from_u4(image[addr as usize]);
+
+    let mut starts: BTreeSet<u8> = leaders.iter().copied().collect();
+    starts.insert(ENTRY);
+    for (addr, successors) in analysis.successors.iter() {
+        let branches = matches!(inst(*addr), Instruction::Jmp | Instruction::Jnz)
+            || analysis.indirect.contains(addr);
+        for target in successors {
+            if branches || Some(*target) != addr.checked_add(1) {
+                starts.insert(*target);
+            }
+        }
+        if branches {
+            starts.extend(addr.checked_add(1));
+        }
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for addr in reached.iter().copied() {
+        let continues = blocks.last().is_some_and(|block| {
+            Some(addr) == block.end.checked_add(1)
+                && !starts.contains(&addr)
+                && analysis.successors.get(&block.end) == Some(&BTreeSet::from([addr]))
+        });
+        match blocks.last_mut() {
+            Some(block) if continues => block.end = addr,
+            _ => blocks.push(Block {
+                start: addr,
+                end: addr,
+                edges: Vec::new(),
+            }),
+        }
+    }
+
+    for block in blocks.iter_mut() {
+        let last = inst(block.end);
+        let kind = |target: Option<u8>| match last {
+            Instruction::Jmp => EdgeKind::Jmp,
+            Instruction::Jnz if target != block.end.checked_add(1) => EdgeKind::Jnz,
+            _ => EdgeKind::Fall,
+        };
+        for target in analysis.successors.get(&block.end).into_iter().flatten() {
+            block.edges.push(Edge {
+                kind: kind(Some(*target)),
+                target: Some(*target),
+            });
+        }
+        if analysis.indirect.contains(&block.end) {
+            block.edges.push(Edge {
+                kind: kind(None),
+                target: None,
+            });
+        }
+    }
+
+    Cfg {
+        blocks,
+        image: *image,
+        dp: analysis.dp.clone(),
+    }
+}
+
+///The graph of a bare image, every cell is trusted to hold its value from the image
+pub fn from_image(image: &[u4; 256]) -> Cfg {
+    let analysis = dp::analyze_image(image, &[true; 256], &BTreeMap::new());
+    build(image, &analysis, &[])
+}
+
+impl Cfg {
+    ///Graphviz source with one box per block, jumps with an unknown target point to a `?` node
+    pub fn to_dot(&self, labels: &Labels) -> String {
+        let mut names: BTreeMap<u8, Vec<&str>> = BTreeMap::new();
+        for (name, addr) in labels {
+            names.entry(*addr).or_default().push(name);
+        }
+
+        let mut output = String::from("digraph cfg {\n");
+        output.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks.iter() {
+            let mut label = String::new();
+            for addr in block.start..=block.end {
+                let mut names = names.get(&addr).cloned().unwrap_or_default();
+                names.sort();
+                for name in names {
+                    label.push_str(&format!("{}:\\l", name));
+                }
+                let inst = Instruction::from_u4(self.image[addr as usize]);
+                label.push_str(&format!("{:02x} {}", addr, inst.as_str()));
+                if let (Some(_), Some(Some(cell))) = (dp::access(inst), self.dp.get(&addr)) {
+                    label.push_str(&format!(" [{:02x}]", cell));
+                }
+                label.push_str("\\l");
+            }
+            if block.end == 0xFF {
+                label.push_str("halt\\l");
+            }
+            output.push_str(&format!(
+                "    b{:02x} [label=\"{}\"];\n",
+                block.start, label
+            ));
+
+            for edge in block.edges.iter() {
+                let mut attrs = match edge.kind {
+                    EdgeKind::Fall => Vec::new(),
+                    EdgeKind::Jmp => vec!["label=\"jmp\""],
+                    EdgeKind::Jnz => vec!["label=\"jnz\""],
+                };
+                let target = match edge.target {
+                    Some(target) => format!("b{:02x}", target),
+                    None => {
+                        output.push_str(&format!(
+                            "    indirect{:02x} [label=\"?\", shape=diamond];\n",
+                            block.end
+                        ));
+                        attrs.push("style=dashed");
+                        format!("indirect{:02x}", block.end)
+                    }
+                };
+                let attrs = match attrs.is_empty() {
+                    true => String::new(),
+                    false => format!(" [{}]", attrs.join(", ")),
+                };
+                output.push_str(&format!(
+                    "    b{:02x} -> {}{};\n",
+                    block.start, target, attrs
+                ));
+            }
+        }
+        output.push_str("}\n");
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, from_image, EdgeKind};
+    use crate::asm::v3::{codegen::gencode, parser::parse};
+
+    ///Counts down from 3, dp stays on the cell holding the low nibble of `loop`
+    const LOOP: &str = ".org 20\n0x3\n&loop\n.org 30\npsi\nloop: dec\njnz\npod\njmp";
+
+    #[test]
+    fn jnz_loop() {
+        let assembly = gencode(LOOP, &parse(LOOP)).unwrap();
+        let cfg = build(&assembly.image, &assembly.dp, &[]);
+        let blocks: Vec<_> = cfg
+            .blocks
+            .iter()
+            .map(|block| (block.start, block.end))
+            .collect();
+        assert_eq!(blocks, [(0x30, 0x30), (0x31, 0x32), (0x33, 0x34)]);
+        let edges: Vec<_> = cfg.blocks[1]
+            .edges
+            .iter()
+            .map(|edge| (edge.kind, edge.target))
+            .collect();
+        assert_eq!(
+            edges,
+            [(EdgeKind::Jnz, Some(0x31)), (EdgeKind::Fall, Some(0x33))]
+        );
+
+        let dot = cfg.to_dot(&assembly.symbols);
+        for line in [
+            "    b30 [label=\"30 psi [20]\\l\"];",
+            "    b30 -> b31;",
+            "    b31 [label=\"loop:\\l31 dec\\l32 jnz [21]\\l\"];",
+            "    b31 -> b31 [label=\"jnz\"];",
+            "    b31 -> b33;",
+            "    b33 [label=\"33 pod [21]\\l34 jmp\\l\"];",
+            "    indirect34 [label=\"?\", shape=diamond];",
+            "    b33 -> indirect34 [label=\"jmp\", style=dashed];",
+        ] {
+            assert!(dot.lines().any(|dot| dot == line), "{}\n{}", line, dot);
+        }
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn bare_images_trust_every_cell() {
+        let assembly = gencode(LOOP, &parse(LOOP)).unwrap();
+        let cfg = from_image(&assembly.image);
+        assert_eq!(cfg.blocks.len(), 3);
+        assert_eq!(cfg.blocks[1].edges.len(), 2);
+    }
+}
@@ -1,12 +1,18 @@
 use libmcc::{u4, v3::Instruction};
 use log::*;
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    ops::Range,
+};
 
 use crate::asm::Stage;
 
 use super::{
     super::AsmError,
-    lexing::{LexToken, TokenLineNumPair},
+    ast::{Ast, ItemKind, LineIndex},
+    dp::{self, DpAnalysis},
+    peephole::Change,
 };
 
 struct LabelRef {
@@ -14,13 +20,15 @@ struct LabelRef {
     addr: u8,
     wide: bool,
     linenum: usize,
+    span: Range<usize>,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 struct Org {
     start_addr: u8,
-    size: u8,
+    size: usize,
     linenum: Option<usize>,
+    span: Option<Range<usize>>,
 }
 impl Display for Org {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -31,37 +39,42 @@ impl Display for Org {
     }
 }
 impl Org {
-    pub fn end_addr(self) -> u8 {
-        self.start_addr + self.size
+    pub fn end_addr(&self) -> usize {
+        self.start_addr as usize + self.size
     }
 
-    pub fn overlap(self, other: Org) -> bool {
-        if other.start_addr < self.start_addr && other.end_addr() < self.start_addr {
-            false
-        } else if other.start_addr > self.end_addr() {
-            false
-        } else {
-            true
-        }
+    pub fn overlap(&self, other: &Org) -> bool {
+        !((other.start_addr < self.start_addr && other.end_addr() < self.start_addr as usize)
+            || other.start_addr as usize > self.end_addr())
     }
 }
 
 #[inline]
 fn write_org(
     mut org: Org,
-    data: &Vec<u4>,
+    data: &[u4],
     output: &mut [u4; 256],
     orgs: &[Org],
 ) -> Result<Org, AsmError> {
     let size = data.len();
-    org.size = size as u8;
+    if org.start_addr as usize + size > 256 {
+        return Err(AsmError {
+            linenum: org.linenum,
+            span: org.span.clone(),
+            message: format!("{} nibbles don't fit in memory", size).into(),
+            code_snip: format!(".org {:#04x}", org.start_addr).into(),
+            stage: Stage::CodeGen,
+        });
+    }
+    org.size = size;
     for other_org in orgs {
         if other_org.start_addr == org.start_addr {
             continue;
         }
-        if other_org.overlap(org) {
+        if other_org.overlap(&org) {
             return Err(AsmError {
                 linenum: org.linenum,
+                span: org.span.clone(),
                 message: format!("is overlapping '{}'", other_org).into(),
                 code_snip: format!("{}", org).into(),
                 stage: Stage::CodeGen,
@@ -83,53 +96,208 @@ fn write_org(
     Ok(org)
 }
 
-pub fn gencode(mut input: Vec<TokenLineNumPair>) -> Result<[u4; 256], AsmError> {
+pub type Labels = HashMap<Box<str>, u8>;
+///Source line of every address that was written
+pub type Lines = BTreeMap<u8, usize>;
+
+///Memory filled by one `.org`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub start: u8,
+    ///Size in nibbles
+    pub size: usize,
+    ///Span of the `.org`, None for code before the first `.org`
+    pub span: Option<Range<usize>>,
+}
+
+///Everything the assembler produced for a source
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub image: [u4; 256],
+    ///Address of every label
+    pub symbols: Labels,
+    ///Sections in source order
+    pub sections: Vec<Section>,
+    pub lines: Lines,
+    ///Span of the item that wrote every address
+    pub spans: BTreeMap<u8, Range<usize>>,
+    ///Addresses written by hex literals, label references and strings
+    pub data: BTreeSet<u8>,
+    ///Lints at the warn level
+    pub warnings: Vec<AsmError>,
+    ///The cell every reached instruction touches
+    pub dp: DpAnalysis,
+    ///Peephole rewrites when assembled with `Options::optimize`
+    pub optimizations: Vec<Change>,
+}
+
+///The sign of an anonymous label reference (`-`, `--`, `+`, ...)
+fn anonymous_sign(name: &str) -> Option<char> {
+    let sign = name
+        .chars()
+        .next()
+        .filter(|sign| matches!(sign, '-' | '+'))?;
+    name.chars().all(|char| char == sign).then_some(sign)
+}
+
+///Anonymous labels are named after their sign and position, `#` can't appear in a label in the source
+fn is_anonymous(name: &str) -> bool {
+    name.contains('#')
+}
+
+///The name every label definition and reference resolves to, None for all other items
+///
+///A local label `.name` belongs to the last global label before it and resolves to `global.name`.
+///`&-` refers to the closest `-:` before it and `&+` to the closest `+:` after it, `&--` and `&++` skip one more
+pub fn label_names(ast: &Ast) -> Vec<Option<Box<str>>> {
+    let anonymous: Vec<(usize, char)> = ast
+        .items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| match &item.kind {
+            ItemKind::LabelDef(name) if matches!(&**name, "-" | "+") => {
+                Some((i, name.chars().next()?))
+            }
+            _ => None,
+        })
+        .collect();
+    let anonymous_name = |pos: usize| -> Box<str> {
+        let (_, sign) = anonymous[pos];
+        format!("{}#{}", sign, pos).into()
+    };
+
+    let mut scope: Option<&str> = None;
+    let mut names = Vec::with_capacity(ast.items.len());
+    for (i, item) in ast.items.iter().enumerate() {
+        let name = match &item.kind {
+            ItemKind::LabelDef(name) if matches!(&**name, "-" | "+") => anonymous
+                .iter()
+                .position(|(def, _)| *def == i)
+                .map(anonymous_name),
+            ItemKind::LabelRef { name, .. } if anonymous_sign(name).is_some() => {
+                let sign = anonymous_sign(name).unwrap();
+                let count = name.len();
+                let candidates = anonymous
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, def_sign))| *def_sign == sign);
+                let found = if sign == '-' {
+                    candidates
+                        .filter(|(_, (def, _))| *def < i)
+                        .nth_back(count - 1)
+                } else {
+                    candidates.filter(|(_, (def, _))| *def > i).nth(count - 1)
+                };
+                Some(found.map_or_else(|| name.clone(), |(pos, _)| anonymous_name(pos)))
+            }
+            ItemKind::LabelDef(name) | ItemKind::LabelRef { name, .. } => {
+                if name.starts_with('.') {
+                    Some(format!("{}{}", scope.unwrap_or_default(), name).into())
+                } else {
+                    if matches!(item.kind, ItemKind::LabelDef(_)) {
+                        scope = Some(name);
+                    }
+                    Some(name.clone())
+                }
+            }
+            _ => None,
+        };
+        names.push(name);
+    }
+    names
+}
+
+///`label` or `label+offset` for the closest label at or before the address
+pub fn describe_addr(labels: &Labels, addr: u8) -> Option<String> {
+    let (name, label_addr) = labels
+        .iter()
+        .filter(|(_, label_addr)| **label_addr <= addr)
+        .max_by(|(a_name, a_addr), (b_name, b_addr)| a_addr.cmp(b_addr).then(b_name.cmp(a_name)))?;
+    if *label_addr == addr {
+        Some(name.to_string())
+    } else {
+        Some(format!("{}+{}", name, addr - label_addr))
+    }
+}
+
+///The address every item is assembled to
+pub fn item_addrs(ast: &Ast) -> Vec<u8> {
+    let mut addr = 0u8;
+    let mut addrs = Vec::with_capacity(ast.items.len());
+    for item in &ast.items {
+        if let ItemKind::Org(start) = item.kind {
+            addr = start;
+        }
+        addrs.push(addr);
+        addr = addr.wrapping_add(item.kind.size() as u8);
+    }
+    addrs
+}
+
+///Items that failed to parse are skipped
+pub fn gencode(source: &str, ast: &Ast) -> Result<Assembly, AsmError> {
+    let index = LineIndex::new(source);
     let mut output = [u4::ZERO; 256];
     let mut data = Vec::new();
     let mut current_org: Org = Org {
         linenum: None,
+        span: None,
         start_addr: 0,
         size: 0,
     };
 
     let mut labels = HashMap::new();
+    let mut lines = BTreeMap::new();
+    let mut spans = BTreeMap::new();
+    let mut data_addrs = BTreeSet::new();
     let mut label_refs: Vec<LabelRef> = Vec::new();
     let mut orgs = Vec::new();
 
-    for token in input.drain(..) {
-        let linenum = token.linenum;
-        let token = token.token;
-        match token {
-            LexToken::Org(start_addr) => {
+    let names = label_names(ast);
+    for (item, label_name) in ast.items.iter().zip(names) {
+        let linenum = index.linenum(item.span.start);
+        let data_start = data.len();
+        match &item.kind {
+            ItemKind::Org(start_addr) => {
                 let org = write_org(current_org, &data, &mut output, &orgs)?;
                 current_org = Org {
-                    start_addr,
+                    start_addr: *start_addr,
                     size: 0,
                     linenum: Some(linenum),
+                    span: Some(item.span.clone()),
                 };
                 orgs.push(org);
                 data.clear();
             }
-            LexToken::Instruction(inst) => {
+            ItemKind::Instruction(inst) => {
                 data.push(inst.into_u4());
             }
-            LexToken::HexLiteral(val) => {
-                data.push(val.into());
+            ItemKind::HexLiteral(val) => {
+                data.push(*val);
+            }
+            ItemKind::String(bytes) => {
+                for byte in bytes {
+                    data.push(u4::from_high(*byte));
+                    data.push(u4::from_low(*byte));
+                }
             }
-            LexToken::LabelRef { name, wide } => {
+            ItemKind::LabelRef { wide, .. } => {
                 label_refs.push(LabelRef {
-                    name,
-                    addr: current_org.start_addr + data.len() as u8,
-                    wide,
+                    name: label_name.unwrap_or_default(),
+                    addr: current_org.start_addr.wrapping_add(data.len() as u8),
+                    wide: *wide,
                     linenum,
+                    span: item.span.clone(),
                 });
                 data.push(u4::ZERO);
-                if wide {
+                if *wide {
                     data.push(u4::ZERO);
                 }
             }
-            LexToken::LabelDef(name) => {
-                let addr = current_org.start_addr + data.len() as u8;
+            ItemKind::Dp(_) | ItemKind::Error => {}
+            ItemKind::LabelDef(raw_name) => {
+                let name = label_name.unwrap_or_default();
+                let addr = current_org.start_addr.wrapping_add(data.len() as u8);
                 trace!(
                     "{}: addr: {:#04x} data: {:#04x} {:?}   org: {:#04x}, data_len: {:#04x}",
                     name,
@@ -139,21 +307,66 @@ pub fn gencode(mut input: Vec<TokenLineNumPair>) -> Result<[u4; 256], AsmError>
                     current_org.start_addr,
                     data.len()
                 );
+                // redefined local labels are reported by the duplicate-label lint
+                if labels.contains_key(&name) && !raw_name.starts_with('.') {
+                    return Err(AsmError {
+                        linenum: Some(linenum),
+                        span: Some(item.span.clone()),
+                        message: "label already defined".into(),
+                        code_snip: name,
+                        stage: Stage::CodeGen,
+                    });
+                }
                 labels.insert(name, addr);
             }
         }
+        let is_data = matches!(
+            item.kind,
+            ItemKind::HexLiteral(_) | ItemKind::LabelRef { .. } | ItemKind::String(_)
+        );
+        for i in data_start..data.len() {
+            let addr = current_org.start_addr.wrapping_add(i as u8);
+            lines.insert(addr, linenum);
+            spans.insert(addr, item.span.clone());
+            if is_data {
+                data_addrs.insert(addr);
+            }
+        }
     }
     //write last org
-    write_org(current_org, &data, &mut output, &orgs)?;
+    let org = write_org(current_org, &data, &mut output, &orgs)?;
+    orgs.push(org);
 
-    resolve_labels(&mut output, labels, label_refs)?;
+    resolve_labels(&mut output, &labels, label_refs)?;
+    labels.retain(|name, _| !is_anonymous(name));
 
-    Ok(output)
+    let sections = orgs
+        .into_iter()
+        .filter(|org| org.span.is_some() || org.size != 0)
+        .map(|org| Section {
+            start: org.start_addr,
+            size: org.size,
+            span: org.span,
+        })
+        .collect();
+    let mut assembly = Assembly {
+        image: output,
+        symbols: labels,
+        sections,
+        lines,
+        spans,
+        data: data_addrs,
+        warnings: Vec::new(),
+        dp: DpAnalysis::default(),
+        optimizations: Vec::new(),
+    };
+    assembly.dp = dp::analyze(ast, &assembly);
+    Ok(assembly)
 }
 
 fn resolve_labels(
     output: &mut [u4; 256],
-    labels: HashMap<Box<str>, u8>,
+    labels: &Labels,
     label_refs: Vec<LabelRef>,
 ) -> Result<(), AsmError> {
     trace!("resolving labels");
@@ -162,6 +375,7 @@ fn resolve_labels(
         addr,
         wide,
         linenum,
+        span,
     } in label_refs
     {
         if wide {
@@ -171,6 +385,7 @@ fn resolve_labels(
         }
         let label_addr = labels.get(&name).ok_or_else(|| AsmError {
             linenum: Some(linenum),
+            span: Some(span),
             message: "label not defined".into(),
             code_snip: name,
             stage: Stage::CodeGen,
@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use libmcc::{u4, v3::Instruction};
+
+use super::{
+    ast::{Ast, ItemKind},
+    codegen::{item_addrs, Assembly},
+    stack::ENTRY,
+};
+
+///dp when the vm starts
+pub const DP_START: u8 = 0x20;
+const DP_ADDR0: u8 = 0x02;
+const DP_ADDR1: u8 = 0x03;
+///First address after the register cells
+const REG_END: u8 = 0x05;
+///Cells the chardev extension reads input into (docs/v3/ext_chardev_ascii.md)
+const CHARDEV: [u8; 2] = [0xF0, 0xF1];
+///Steps after which the analysis gives up, the lattice is finite so this is only a safety net
+const MAX_STEPS: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+///How an instruction uses the cell at dp
+pub fn access(inst: Instruction) -> Option<Access> {
+    use Instruction::*;
+    match inst {
+        Psi | Psd | Jnz => Some(Access::Read),
+        Poi | Pod => Some(Access::Write),
+        _ => None,
+    }
+}
+
+///Why dp isn't known after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lost {
+    ///mdp popped an address that isn't known while dp still was
+    Mdp,
+    ///Paths with a different dp meet
+    Join(u8, u8),
+    ///poi/pod wrote to the dp registers
+    Write,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DpAnalysis {
+    ///dp before every reached instruction, None when it isn't known
+    pub dp: BTreeMap<u8, Option<u8>>,
+    ///Instructions where dp stops being known
+    pub lost: Vec<(u8, Lost)>,
+    ///Every address the vm can continue at after a reached instruction
+    pub successors: BTreeMap<u8, BTreeSet<u8>>,
+    ///jmp and jnz instructions that can jump to an address that isn't known
+    pub indirect: BTreeSet<u8>,
+}
+
+impl DpAnalysis {
+    ///The cell a reached instruction touches, None when dp isn't known there
+    pub fn cell(&self, addr: u8) -> Option<u8> {
+        self.dp.get(&addr).copied().flatten()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    dp: Option<u8>,
+    ///Known values on the stack, only the top is tracked when paths with a different depth meet
+    stack: Vec<Option<u4>>,
+    ///Cells written on the way here
+    mem: BTreeMap<u8, Option<u4>>,
+    ///A cell was written while dp wasn't known, every cell that isn't in `mem` is unknown
+    clobbered: bool,
+}
+
+struct Analyzer<'a> {
+    image: &'a [u4; 256],
+    known: &'a [bool; 256],
+    directives: BTreeMap<u8, u8>,
+    states: Vec<Option<State>>,
+    work: Vec<(u8, State)>,
+    result: DpAnalysis,
+}
+
+impl Analyzer<'_> {
+    ///Known cells hold their value from the image until something writes them, other cells can be anything
+    ///(they might belong to an extension like the chardev)
+    fn base(&self, addr: u8) -> Option<u4> {
+        (addr >= REG_END && !CHARDEV.contains(&addr) && self.known[addr as usize])
+            .then(|| self.image[addr as usize])
+    }
+
+    fn read(&self, state: &State, addr: u8) -> Option<u4> {
+        match state.mem.get(&addr) {
+            Some(value) => *value,
+            None if state.clobbered => None,
+            None => self.base(addr),
+        }
+    }
+
+    fn lose(&mut self, addr: u8, lost: Lost) {
+        let same = |(other_addr, other): &(u8, Lost)| {
+            *other_addr == addr && std::mem::discriminant(other) == std::mem::discriminant(&lost)
+        };
+        if !self.result.lost.iter().any(same) {
+            self.result.lost.push((addr, lost));
+        }
+    }
+
+    fn merge(&mut self, addr: u8, a: &State, b: &State) -> State {
+        let dp = match (a.dp, b.dp) {
+            (Some(a), Some(b)) if a == b => Some(a),
+            (Some(a), Some(b)) => {
+                self.lose(addr, Lost::Join(a, b));
+                None
+            }
+            _ => None,
+        };
+        let depth = a.stack.len().min(b.stack.len());
+        let stack = a.stack[a.stack.len() - depth..]
+            .iter()
+            .zip(&b.stack[b.stack.len() - depth..])
+            .map(|(a, b)| if a == b { *a } else { None })
+            .collect();
+        let clobbered = a.clobbered || b.clobbered;
+        let mut mem = BTreeMap::new();
+        for cell in a.mem.keys().chain(b.mem.keys()) {
+            let (a, b) = (self.read(a, *cell), self.read(b, *cell));
+            mem.insert(*cell, if a == b { a } else { None });
+        }
+        State {
+            dp,
+            stack,
+            mem,
+            clobbered,
+        }
+    }
+
+    fn visit(&mut self, addr: u8, mut state: State) {
+        if let Some(dp) = self.directives.get(&addr) {
+            state.dp = Some(*dp);
+        }
+        if let Some(existing) = self.states[addr as usize].clone() {
+            let merged = self.merge(addr, &existing, &state);
+            if merged == existing {
+                return;
+            }
+            state = merged;
+        }
+        self.states[addr as usize] = Some(state.clone());
+        self.result.dp.insert(addr, state.dp);
+        self.step(addr, state);
+    }
+
+    fn step(&mut self, addr: u8, mut state: State) {
+        let pop = |state: &mut State| state.stack.pop().flatten();
+        let unary = |state: &mut State, op: fn(u4) -> u4| {
+            let value = pop(state);
+            state.stack.push(value.map(op));
+        };
+        let binary = |state: &mut State, op: fn(u4, u4) -> u4| {
+            let (a, b) = (pop(state), pop(state));
+            state.stack.push(a.zip(b).map(|(a, b)| op(a, b)));
+        };
+        let inc_dp = |state: &mut State| state.dp = state.dp.map(|dp| dp.wrapping_add(1));
+        let dec_dp = |state: &mut State| state.dp = state.dp.map(|dp| dp.wrapping_sub(1));
+        let cell = state.dp.and_then(|dp| self.read(&state, dp));
+        // the vm halts after the instruction at ff
+        let next = (addr != 0xFF).then(|| addr + 1);
+        let mut targets = Vec::new();
+
+        use Instruction::*;
+        let inst = Instruction::from_u4(self.image[addr as usize]);
+        match inst {
+            Nop => {}
+            Psi => {
+                state.stack.push(cell);
+                inc_dp(&mut state);
+            }
+            Psd => {
+                state.stack.push(cell);
+                dec_dp(&mut state);
+            }
+            Poi | Pod => {
+                let value = pop(&mut state);
+                match state.dp {
+                    Some(DP_ADDR0 | DP_ADDR1) => {
+                        self.lose(addr, Lost::Write);
+                        state.dp = None;
+                    }
+                    Some(dp) => {
+                        state.mem.insert(dp, value);
+                    }
+                    None => {
+                        state.mem.clear();
+                        state.clobbered = true;
+                    }
+                }
+                if inst == Poi {
+                    inc_dp(&mut state);
+                } else {
+                    dec_dp(&mut state);
+                }
+            }
+            Swp => {
+                let (a, b) = (pop(&mut state), pop(&mut state));
+                state.stack.push(a);
+                state.stack.push(b);
+            }
+            Mdp => {
+                let (low, high) = (pop(&mut state), pop(&mut state));
+                // when dp already wasn't known the address was most likely read through it
+                let known = state.dp.is_some();
+                state.dp = low
+                    .zip(high)
+                    .map(|(low, high)| high.into_high() | low.into_low());
+                if known && state.dp.is_none() {
+                    self.lose(addr, Lost::Mdp);
+                }
+            }
+            Di => inc_dp(&mut state),
+            Dd => dec_dp(&mut state),
+            Jmp => {
+                let (low, high) = (pop(&mut state), pop(&mut state));
+                match low.zip(high) {
+                    Some((low, high)) => targets.push(high.into_high() | low.into_low()),
+                    None => {
+                        self.result.indirect.insert(addr);
+                    }
+                }
+            }
+            Jnz => {
+                // jnz only replaces the low nibble of ip with the cell at dp
+                let top = state.stack.last().copied().flatten();
+                match (top != Some(u4::ZERO), cell) {
+                    (true, Some(cell)) => targets.push(addr & 0xF0 | cell.into_low()),
+                    (true, None) => {
+                        self.result.indirect.insert(addr);
+                    }
+                    _ => {}
+                }
+            }
+            Inc => unary(&mut state, |a| a.overflowing_add(u4::ONE)),
+            Dec => unary(&mut state, |a| a.overflowing_sub(u4::ONE)),
+            Add => binary(&mut state, u4::overflowing_add),
+            Sub => binary(&mut state, u4::overflowing_sub),
+            Mul => binary(&mut state, u4::overflowing_mul),
+        }
+        let falls_through = match inst {
+            Jmp => false,
+            Jnz => !matches!(state.stack.last(), Some(Some(top)) if *top != u4::ZERO),
+            _ => true,
+        };
+        if falls_through {
+            targets.extend(next);
+        }
+        let successors = self.result.successors.entry(addr).or_default();
+        for target in targets {
+            successors.insert(target);
+            self.work.push((target, state.clone()));
+        }
+    }
+
+    fn run(&mut self, addr: u8, state: State) {
+        self.work.push((addr, state));
+        let mut steps = 0;
+        while let Some((addr, state)) = self.work.pop() {
+            steps += 1;
+            if steps > MAX_STEPS {
+                break;
+            }
+            self.visit(addr, state);
+        }
+    }
+}
+
+///Follows dp from 0x20 at the entry point through every path the vm can take.
+///
+///Values on the stack and in memory are tracked as far as they are known so `mdp`, `jmp` and `jnz`
+///can follow addresses loaded from `&&label` data. A `.dp XX` directive sets dp where it is reached,
+///code that isn't reached from the entry point starts at a `.dp` directive with an unknown stack
+pub fn analyze(ast: &Ast, assembly: &Assembly) -> DpAnalysis {
+    let directives = ast
+        .items
+        .iter()
+        .zip(item_addrs(ast))
+        .filter_map(|(item, addr)| match item.kind {
+            ItemKind::Dp(dp) => Some((addr, dp)),
+            _ => None,
+        })
+        .collect::<BTreeMap<_, _>>();
+    // cells the assembler didn't write might belong to an extension
+    let mut known = [false; 256];
+    for addr in assembly.spans.keys() {
+        known[*addr as usize] = true;
+    }
+    analyze_image(&assembly.image, &known, &directives)
+}
+
+///Like [analyze] for an image without a source, only the cells in `known` are trusted to hold their value
+pub fn analyze_image(
+    image: &[u4; 256],
+    known: &[bool; 256],
+    directives: &BTreeMap<u8, u8>,
+) -> DpAnalysis {
+    let mut analyzer = Analyzer {
+        image,
+        known,
+        directives: directives.clone(),
+        states: vec![None; 256],
+        work: Vec::new(),
+        result: DpAnalysis::default(),
+    };
+    let state = |dp| State {
+        dp: Some(dp),
+        stack: Vec::new(),
+        mem: BTreeMap::new(),
+        clobbered: false,
+    };
+    analyzer.run(ENTRY, state(DP_START));
+    for (addr, dp) in directives {
+        if analyzer.states[*addr as usize].is_none() {
+            analyzer.run(*addr, state(*dp));
+        }
+    }
+    analyzer.result.lost.sort_by_key(|(addr, _)| *addr);
+    analyzer.result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DpAnalysis, Lost};
+    use crate::asm::v3::{codegen::gencode, parser::parse};
+
+    fn analysis(source: &str) -> DpAnalysis {
+        gencode(source, &parse(source)).unwrap().dp
+    }
+
+    #[test]
+    fn follows_dp_through_mdp() {
+        let source = ".org 20\n&&target\n.org 30\npsi\npsi\nmdp\npsi\npoi\n.org 50\ntarget: 0x7";
+        let analysis = analysis(source);
+        assert_eq!(analysis.cell(0x30), Some(0x20));
+        assert_eq!(analysis.cell(0x31), Some(0x21));
+        assert_eq!(analysis.cell(0x33), Some(0x50));
+        // poi writes the value psi read back to the next cell
+        assert_eq!(analysis.cell(0x34), Some(0x51));
+        assert_eq!(analysis.lost, []);
+    }
+
+    #[test]
+    fn mdp_from_unknown_cells() {
+        // nothing was assembled at 0x20, the cells might hold anything
+        let analysis = analysis(".org 30\npsi\npsi\nmdp\npsi\npsi\npsi\njmp");
+        assert_eq!(analysis.cell(0x32), Some(0x22));
+        assert_eq!(analysis.cell(0x33), None);
+        assert_eq!(analysis.lost, [(0x32, Lost::Mdp)]);
+        assert!(analysis.indirect.contains(&0x36));
+        assert_eq!(analysis.successors[&0x36].len(), 0);
+    }
+
+    #[test]
+    fn jumps_through_data() {
+        let source = ".org 20\n&&target\n.org 30\npsi\npsi\njmp\n.org 60\ntarget: psi";
+        let analysis = analysis(source);
+        assert_eq!(
+            analysis.successors[&0x32].iter().collect::<Vec<_>>(),
+            [&0x60]
+        );
+        assert_eq!(analysis.cell(0x60), Some(0x22));
+        assert!(analysis.indirect.is_empty());
+    }
+
+    #[test]
+    fn paths_with_a_different_dp() {
+        // the jnz to 0x35 skips the di
+        let analysis = analysis(".org 21\n0x5\n.org 30\npsi\njnz\ndi\nnop\nnop\npsi");
+        assert_eq!(analysis.successors[&0x31].len(), 2);
+        assert_eq!(analysis.cell(0x35), None);
+        assert!(matches!(analysis.lost[..], [(0x35, Lost::Join(..))]));
+    }
+
+    #[test]
+    fn writes_to_the_dp_registers() {
+        let analysis = analysis(".org 30\n.dp 02\npsi\ndd\npoi\npsi");
+        assert_eq!(analysis.cell(0x32), Some(0x02));
+        assert_eq!(analysis.cell(0x33), None);
+        assert_eq!(analysis.lost, [(0x32, Lost::Write)]);
+    }
+
+    #[test]
+    fn dp_directives_start_unreached_code() {
+        let analysis = analysis(".org 30\njmp\n.org 40\n.dp 80\npsi");
+        assert_eq!(analysis.cell(0x40), Some(0x80));
+        assert_eq!(analysis.cell(0x41), Some(0x81));
+    }
+}
@@ -0,0 +1,297 @@
+use super::{
+    ast::ItemKind,
+    parser::parse,
+    tokenizer::{tokenize, Token, TokenKind},
+};
+
+///Indentation of code under a label
+const INDENT: &str = "  ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CodeKind {
+    Org,
+    ///Starts with a label definition
+    Label,
+    Code,
+}
+
+enum Line {
+    Blank,
+    Comment(String),
+    Code {
+        kind: CodeKind,
+        code: String,
+        comment: Option<String>,
+    },
+}
+
+#[derive(Default)]
+struct LineBuilder {
+    kind: Option<CodeKind>,
+    pieces: Vec<String>,
+    comment: Option<String>,
+}
+
+impl LineBuilder {
+    fn push(&mut self, kind: CodeKind, piece: String) {
+        self.kind.get_or_insert(kind);
+        self.pieces.push(piece);
+    }
+
+    fn finish(self) -> Line {
+        match (self.kind, self.comment) {
+            (None, Some(comment)) => Line::Comment(comment),
+            (None, None) => Line::Blank,
+            (Some(kind), comment) => Line::Code {
+                kind,
+                code: self.pieces.join(" "),
+                comment,
+            },
+        }
+    }
+}
+
+fn trivia(source: &str, lines: &mut Vec<Line>, line: &mut LineBuilder, tokens: &[Token]) {
+    for token in tokens {
+        match token.kind {
+            TokenKind::Newline => lines.push(std::mem::take(line).finish()),
+            TokenKind::Comment => line.comment = Some(token.text(source).trim_end().into()),
+            _ => {}
+        }
+    }
+}
+
+///Comments between a directive and its address, the address can be on a later line
+fn directive_comments(text: &str) -> Vec<String> {
+    tokenize(text)
+        .iter()
+        .filter(|token| token.kind == TokenKind::Comment)
+        .map(|token| token.text(text).trim_end().into())
+        .collect()
+}
+
+fn parse_lines(source: &str) -> Vec<Line> {
+    let ast = parse(source);
+    let mut lines = Vec::new();
+    let mut line = LineBuilder::default();
+    for item in &ast.items {
+        trivia(source, &mut lines, &mut line, &item.trivia);
+        let text = &source[item.span.clone()];
+        let (kind, piece) = match &item.kind {
+            ItemKind::Org(addr) => (CodeKind::Org, format!(".org {:02X}", addr)),
+            ItemKind::Dp(addr) => (CodeKind::Code, format!(".dp {:02X}", addr)),
+            ItemKind::LabelDef(name) => (CodeKind::Label, format!("{}:", name)),
+            ItemKind::HexLiteral(val) => (CodeKind::Code, format!("0x{:X}", val.into_low())),
+            _ => (CodeKind::Code, text.to_string()),
+        };
+        if let ItemKind::Org(_) | ItemKind::Dp(_) = item.kind {
+            // the directive is written on one line, the last comment stays on it and the others go above it
+            let mut comments = directive_comments(text);
+            line.comment = comments.pop();
+            lines.extend(comments.into_iter().map(Line::Comment));
+        }
+        line.push(kind, piece);
+    }
+    trivia(source, &mut lines, &mut line, &ast.trailing_trivia);
+    lines.push(line.finish());
+    lines
+}
+
+///Collapses blank lines and puts a blank line before every `.org` block (and the comments above it)
+fn group_blocks(lines: Vec<Line>) -> Vec<Line> {
+    let mut out: Vec<Line> = Vec::new();
+    for line in lines {
+        match line {
+            Line::Blank if matches!(out.last(), None | Some(Line::Blank)) => {}
+            Line::Code {
+                kind: CodeKind::Org,
+                ..
+            } => {
+                let attached = out
+                    .iter()
+                    .rev()
+                    .take_while(|line| matches!(line, Line::Comment(_)))
+                    .count();
+                let pos = out.len() - attached;
+                if pos != 0 && !matches!(out[pos - 1], Line::Blank) {
+                    out.insert(pos, Line::Blank);
+                }
+                out.push(line);
+            }
+            line => out.push(line),
+        }
+    }
+    while matches!(out.last(), Some(Line::Blank)) {
+        out.pop();
+    }
+    out
+}
+
+///Formats mcc v3 assembly, comments are kept and the meaning of the source doesn't change
+pub fn format(source: &str) -> String {
+    let lines = group_blocks(parse_lines(source));
+
+    // indentation of every line
+    let mut indents = Vec::with_capacity(lines.len());
+    let mut under_label = false;
+    for (i, line) in lines.iter().enumerate() {
+        let indent = match line {
+            Line::Blank => "",
+            Line::Code { kind, .. } => {
+                under_label = match kind {
+                    CodeKind::Org => false,
+                    CodeKind::Label => true,
+                    CodeKind::Code => under_label,
+                };
+                if *kind == CodeKind::Code && under_label {
+                    INDENT
+                } else {
+                    ""
+                }
+            }
+            // a comment is indented like the code below it
+            Line::Comment(_) => {
+                let next = lines[i..]
+                    .iter()
+                    .find(|line| !matches!(line, Line::Comment(_)));
+                match next {
+                    Some(Line::Code {
+                        kind: CodeKind::Org | CodeKind::Label,
+                        ..
+                    }) => "",
+                    _ if under_label => INDENT,
+                    _ => "",
+                }
+            }
+        };
+        indents.push(indent);
+    }
+
+    // trailing comments of consecutive code lines are aligned, a `.org` line is a group on its own
+    let mut groups = Vec::new();
+    let mut group_start = 0;
+    for (i, line) in lines.iter().enumerate() {
+        match line {
+            Line::Code {
+                kind: CodeKind::Org,
+                ..
+            } => {
+                groups.push(group_start..i);
+                groups.push(i..i + 1);
+                group_start = i + 1;
+            }
+            Line::Code { .. } => {}
+            _ => {
+                groups.push(group_start..i);
+                group_start = i + 1;
+            }
+        }
+    }
+    groups.push(group_start..lines.len());
+    let mut comment_cols = vec![0; lines.len()];
+    for group in groups {
+        let width = group
+            .clone()
+            .filter_map(|i| match &lines[i] {
+                Line::Code {
+                    code,
+                    comment: Some(_),
+                    ..
+                } => Some(indents[i].len() + code.len()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        comment_cols[group].fill(width + 1);
+    }
+
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        match line {
+            Line::Blank => {}
+            Line::Comment(comment) => {
+                out.push_str(indents[i]);
+                out.push_str(comment);
+            }
+            Line::Code { code, comment, .. } => {
+                let code = format!("{}{}", indents[i], code);
+                match comment {
+                    Some(comment) => out.push_str(&format!(
+                        "{:<width$}{}",
+                        code,
+                        comment,
+                        width = comment_cols[i]
+                    )),
+                    None => out.push_str(&code),
+                }
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use crate::asm::v3::{codegen::gencode, parser::parse};
+
+    const PROGRAMS: [&str; 5] = [
+        include_str!("../../../../../programs/v3/add_test.asm"),
+        include_str!("../../../../../programs/v3/echo.asm"),
+        include_str!("../../../../../programs/v3/hello_world.asm"),
+        include_str!("../../../../../programs/v3/mul.asm"),
+        include_str!("../../../../../programs/v3/test.asm"),
+    ];
+
+    #[test]
+    fn formatting_is_idempotent_and_keeps_the_image() {
+        for source in PROGRAMS {
+            let formatted = format(source);
+            assert_eq!(format(&formatted), formatted);
+            let image = |source: &str| gencode(source, &parse(source)).unwrap().image;
+            assert_eq!(image(&formatted), image(source));
+        }
+    }
+
+    #[test]
+    fn layout() {
+        let source = "; header\n.org 30 # code\nstart:   psi psi\n\n\n  add # sum\ndi  ; next\n.org 40\n0xa   &&start\n";
+        let expected = "\
+; header
+.org 30 # code
+start: psi psi
+
+  add # sum
+  di  ; next
+
+.org 40
+0xA &&start
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn comments_inside_directives() {
+        let source = ".org # the code\n30\npsi\n.dp ; dp here\n20\n.org ; a\n; b\n40\n";
+        let formatted = format(source);
+        let expected = "\
+.org 30 # the code
+psi
+.dp 20 ; dp here
+
+; a
+.org 40 ; b
+";
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn comments_and_strings_survive() {
+        let source = "# é 😀\n.org 20\n\"a # b\" ; c\n";
+        let formatted = format(source);
+        assert!(formatted.contains("# é 😀"));
+        assert!(formatted.contains("\"a # b\" ; c"));
+    }
+}
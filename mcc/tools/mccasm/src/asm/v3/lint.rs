@@ -0,0 +1,474 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    ops::Range,
+};
+
+use clap::ValueEnum;
+use libmcc::v3::Instruction;
+
+use super::{
+    ast::{Ast, ItemKind, LineIndex},
+    codegen::{label_names, Assembly},
+    dp::Lost,
+    stack::{self, IssueKind, Roots, ENTRY},
+    tokenizer::TokenKind,
+};
+use crate::asm::{AsmError, Stage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Lint {
+    ///A local label is defined more than once in its scope, the last definition is used
+    ///(redefining a global label is always an error)
+    DuplicateLabel,
+    ///A label is never referenced
+    UnusedLabel,
+    ///Instructions after a jmp that no label points to
+    UnreachableCode,
+    ///Data that the vm runs into when it starts at 0x30
+    DataInCode,
+    ///An instruction pops from an empty stack or more than its routine's `( -- )` annotation provides
+    StackUnderflow,
+    ///The stack grows past 15 nibbles and sp wraps around
+    StackOverflow,
+    ///Paths with a different stack depth meet at the same instruction
+    StackJoin,
+    ///A routine leaves with a stack depth that doesn't match its `( -- )` annotation
+    StackEffect,
+    ///dp can't be followed anymore (set `.dp XX` where you know it)
+    DpUnknown,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 9] = [
+        Lint::DuplicateLabel,
+        Lint::UnusedLabel,
+        Lint::UnreachableCode,
+        Lint::DataInCode,
+        Lint::StackUnderflow,
+        Lint::StackOverflow,
+        Lint::StackJoin,
+        Lint::StackEffect,
+        Lint::DpUnknown,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::DuplicateLabel => "duplicate-label",
+            Lint::UnusedLabel => "unused-label",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::DataInCode => "data-in-code",
+            Lint::StackUnderflow => "stack-underflow",
+            Lint::StackOverflow => "stack-overflow",
+            Lint::StackJoin => "stack-join",
+            Lint::StackEffect => "stack-effect",
+            Lint::DpUnknown => "dp-unknown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    pub fn default_level(self) -> Level {
+        match self {
+            Lint::DuplicateLabel => Level::Deny,
+            _ => Level::Warn,
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    index: LineIndex,
+    levels: &'a HashMap<Lint, Level>,
+    ///Lints turned off by a `mccasm: allow(...)` comment on a line
+    allowed: HashMap<usize, HashSet<Lint>>,
+    found: Vec<(Level, AsmError)>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, lint: Lint, span: Range<usize>, message: &str) {
+        let level = self
+            .levels
+            .get(&lint)
+            .copied()
+            .unwrap_or(lint.default_level());
+        let line = self.index.line(span.start);
+        let allowed = self
+            .allowed
+            .get(&line)
+            .is_some_and(|lints| lints.contains(&lint));
+        if level == Level::Allow || allowed {
+            return;
+        }
+        self.found.push((
+            level,
+            AsmError {
+                linenum: Some(line + 1),
+                span: Some(span.clone()),
+                code_snip: self.source[span].into(),
+                message: message.into(),
+                stage: Stage::Lint(lint),
+            },
+        ));
+    }
+}
+
+///Lints named in `mccasm: allow(a, b)`
+fn parse_allow(comment: &str) -> Option<Vec<Lint>> {
+    let directive = comment[1..].trim().strip_prefix("mccasm:")?.trim();
+    let list = directive.strip_prefix("allow(")?.strip_suffix(')')?;
+    Some(
+        list.split(',')
+            .filter_map(|name| Lint::from_name(name.trim()))
+            .collect(),
+    )
+}
+
+///Every comment with the line it applies to, a comment on a line by itself also applies to the next line with code
+pub(crate) fn attached_comments<'a>(
+    source: &'a str,
+    ast: &Ast,
+    index: &LineIndex,
+) -> Vec<(usize, &'a str)> {
+    let mut attached = Vec::new();
+    let comments = ast
+        .items
+        .iter()
+        .flat_map(|item| &item.trivia)
+        .chain(&ast.trailing_trivia)
+        .filter(|token| token.kind == TokenKind::Comment);
+    for comment in comments {
+        let text = comment.text(source);
+        let line = index.line(comment.span.start);
+        attached.push((line, text));
+        let line_start = index.offset(line, 0);
+        if source[line_start..comment.span.start].trim().is_empty() {
+            let next = ast
+                .items
+                .iter()
+                .find(|item| item.span.start > comment.span.start);
+            if let Some(next) = next {
+                attached.push((index.line(next.span.start), text));
+            }
+        }
+    }
+    attached
+}
+
+fn allowed_lines(source: &str, ast: &Ast, index: &LineIndex) -> HashMap<usize, HashSet<Lint>> {
+    let mut allowed: HashMap<usize, HashSet<Lint>> = HashMap::new();
+    for (line, comment) in attached_comments(source, ast, index) {
+        if let Some(lints) = parse_allow(comment) {
+            allowed.entry(line).or_default().extend(lints);
+        }
+    }
+    allowed
+}
+
+fn duplicate_labels(linter: &mut Linter, ast: &Ast, names: &[Option<Box<str>>]) {
+    let mut defined = HashSet::new();
+    for (item, name) in ast.items.iter().zip(names) {
+        if let (ItemKind::LabelDef(label), Some(name)) = (&item.kind, name) {
+            if !defined.insert(name) && label.starts_with('.') {
+                linter.report(
+                    Lint::DuplicateLabel,
+                    item.span.clone(),
+                    "label already defined",
+                );
+            }
+        }
+    }
+}
+
+fn unused_labels(linter: &mut Linter, ast: &Ast, names: &[Option<Box<str>>]) {
+    let referenced: HashSet<_> = ast
+        .items
+        .iter()
+        .zip(names)
+        .filter(|(item, _)| matches!(item.kind, ItemKind::LabelRef { .. }))
+        .filter_map(|(_, name)| name.as_deref())
+        .collect();
+    for (item, name) in ast.items.iter().zip(names) {
+        let (ItemKind::LabelDef(label), Some(name)) = (&item.kind, name) else {
+            continue;
+        };
+        // a global label with local labels is used as their scope
+        let is_scope = !label.starts_with('.')
+            && names.iter().flatten().any(|other| {
+                other
+                    .strip_prefix(&**name)
+                    .is_some_and(|local| local.starts_with('.'))
+            });
+        if !referenced.contains(&**name) && !is_scope {
+            linter.report(Lint::UnusedLabel, item.span.clone(), "label is never used");
+        }
+    }
+}
+
+fn unreachable_code(linter: &mut Linter, ast: &Ast) {
+    let mut after_jmp = false;
+    // one warning for every block
+    let mut reported = false;
+    for item in &ast.items {
+        match item.kind {
+            ItemKind::Instruction(_) if after_jmp => {
+                if !reported {
+                    linter.report(
+                        Lint::UnreachableCode,
+                        item.span.clone(),
+                        "unreachable code after jmp",
+                    );
+                }
+                reported = true;
+            }
+            ItemKind::Instruction(Instruction::Jmp) => {
+                after_jmp = true;
+                reported = false;
+            }
+            ItemKind::LabelDef(_) | ItemKind::Org(_) => after_jmp = false,
+            _ => {}
+        }
+    }
+}
+
+///Follows the vm from the entry point until the first jmp
+fn data_in_code(linter: &mut Linter, assembly: &Assembly) {
+    let mut addr = ENTRY;
+    let mut reported = None;
+    while addr != 0xFF {
+        if let Some(span) = assembly.spans.get(&addr) {
+            if assembly.data.contains(&addr) && reported.as_ref() != Some(span) {
+                linter.report(
+                    Lint::DataInCode,
+                    span.clone(),
+                    "data is executed as an instruction",
+                );
+                reported = Some(span.clone());
+            }
+        }
+        if Instruction::from_u4(assembly.image[addr as usize]) == Instruction::Jmp {
+            break;
+        }
+        addr += 1;
+    }
+}
+
+fn stack_depth(linter: &mut Linter, ast: &Ast, assembly: &Assembly) {
+    let Roots {
+        roots,
+        jnz_targets,
+        routines,
+    } = Roots::new(linter.source, ast);
+    for issue in stack::analyze(&assembly.image, &roots, &jnz_targets) {
+        let Some(span) = assembly.spans.get(&issue.addr).cloned() else {
+            continue;
+        };
+        let routine = issue
+            .routine
+            .and_then(|addr| routines.get(&addr))
+            .map_or("the entry point".to_string(), |name| format!("'{}'", name));
+        let (lint, message) = match issue.kind {
+            IssueKind::Underflow if issue.routine.is_none() => {
+                (Lint::StackUnderflow, "pops from an empty stack".to_string())
+            }
+            IssueKind::Underflow => (
+                Lint::StackUnderflow,
+                format!("pops more than {} gets on the stack", routine),
+            ),
+            IssueKind::Overflow => (
+                Lint::StackOverflow,
+                format!(
+                    "the stack grows past {} nibbles and wraps",
+                    stack::STACK_SIZE
+                ),
+            ),
+            IssueKind::Join { depth, other } => (
+                Lint::StackJoin,
+                format!(
+                    "reached from {} with a stack depth of {} and {}",
+                    routine, other, depth
+                ),
+            ),
+            IssueKind::Effect { depth, effect } => (
+                Lint::StackEffect,
+                format!(
+                    "{} leaves {} nibbles on the stack but is annotated with {}",
+                    routine, depth, effect
+                ),
+            ),
+        };
+        linter.report(lint, span, &message);
+    }
+}
+
+fn dp_unknown(linter: &mut Linter, assembly: &Assembly) {
+    for (addr, lost) in assembly.dp.lost.iter() {
+        let Some(span) = assembly.spans.get(addr).cloned() else {
+            continue;
+        };
+        let message = match lost {
+            Lost::Mdp => "mdp loads an address that isn't known".to_string(),
+            Lost::Join(a, b) => format!("reached with dp at {:#04x} and at {:#04x}", a, b),
+            Lost::Write => "overwrites dp".to_string(),
+        };
+        linter.report(Lint::DpUnknown, span, &message);
+    }
+}
+
+///Runs every lint that isn't allowed, the level of each finding decides if it is a warning or an error
+pub fn check(
+    source: &str,
+    ast: &Ast,
+    assembly: &Assembly,
+    levels: &HashMap<Lint, Level>,
+) -> Vec<(Level, AsmError)> {
+    let index = LineIndex::new(source);
+    let allowed = allowed_lines(source, ast, &index);
+    let mut linter = Linter {
+        source,
+        index,
+        levels,
+        allowed,
+        found: Vec::new(),
+    };
+    let names = label_names(ast);
+    duplicate_labels(&mut linter, ast, &names);
+    unused_labels(&mut linter, ast, &names);
+    unreachable_code(&mut linter, ast);
+    data_in_code(&mut linter, assembly);
+    stack_depth(&mut linter, ast, assembly);
+    dp_unknown(&mut linter, assembly);
+    linter
+        .found
+        .sort_by_key(|(_, err)| err.span.as_ref().map(|span| span.start));
+    linter.found
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{check, Level, Lint};
+    use crate::asm::{
+        v3::{codegen::gencode, parser::parse},
+        Stage,
+    };
+
+    const NONE: [usize; 0] = [];
+
+    ///Lines (starting at 1) where `lint` is reported with the default levels
+    fn found(source: &str, lint: Lint) -> Vec<usize> {
+        let ast = parse(source);
+        let assembly = gencode(source, &ast).unwrap();
+        check(source, &ast, &assembly, &HashMap::new())
+            .into_iter()
+            .filter(|(_, err)| err.stage == Stage::Lint(lint))
+            .filter_map(|(_, err)| err.linenum)
+            .collect()
+    }
+
+    ///Checks that `lint` is reported on `line` and that an allow comment turns it off
+    fn triggers(source: &str, lint: Lint, line: usize) {
+        assert_eq!(found(source, lint), [line], "{}:\n{}", lint, source);
+        let mut lines: Vec<_> = source.lines().map(str::to_string).collect();
+        lines[line - 1].push_str(&format!(" ; mccasm: allow({})", lint));
+        let allowed = lines.join("\n");
+        assert_eq!(found(&allowed, lint), NONE, "{}:\n{}", lint, allowed);
+        // on a line by itself the comment applies to the next line
+        lines[line - 1] = source.lines().nth(line - 1).unwrap().to_string();
+        lines.insert(line - 1, format!("; mccasm: allow({})", lint));
+        let allowed = lines.join("\n");
+        assert_eq!(found(&allowed, lint), NONE, "{}:\n{}", lint, allowed);
+    }
+
+    #[test]
+    fn duplicate_label() {
+        triggers(
+            ".org 30\nstart: &.x\n.x: psi\n.x: psi",
+            Lint::DuplicateLabel,
+            4,
+        );
+        let levels = HashMap::from([(Lint::DuplicateLabel, Level::Warn)]);
+        let source = ".org 30\nstart: &.x\n.x:\n.x:";
+        let ast = parse(source);
+        let assembly = gencode(source, &ast).unwrap();
+        let lints = check(source, &ast, &assembly, &levels);
+        assert_eq!(lints[0].0, Level::Warn);
+    }
+
+    #[test]
+    fn global_labels_are_never_redefined() {
+        let source = ".org 30\nstart: psi\nstart: psi ; mccasm: allow(duplicate-label)";
+        let err = gencode(source, &parse(source)).unwrap_err();
+        assert_eq!(&*err.message, "label already defined");
+        assert_eq!(err.linenum, Some(3));
+    }
+
+    #[test]
+    fn unused_label() {
+        triggers(".org 30\nused: &used\nunused: psi", Lint::UnusedLabel, 3);
+        // a global label that only scopes local labels counts as used
+        assert_eq!(found(".org 30\nscope: &.x\n.x:", Lint::UnusedLabel), NONE);
+    }
+
+    #[test]
+    fn unreachable_code() {
+        triggers(".org 30\njmp\npsi\npsi", Lint::UnreachableCode, 3);
+        assert_eq!(
+            found(".org 30\njmp\ntarget: &target psi", Lint::UnreachableCode),
+            NONE
+        );
+    }
+
+    #[test]
+    fn data_in_code() {
+        triggers(".org 30\npsi\n0x1\npsi psi jmp", Lint::DataInCode, 3);
+    }
+
+    #[test]
+    fn stack_underflow() {
+        triggers(".org 30\npsi\nadd", Lint::StackUnderflow, 3);
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let source = format!(".org 30\n{}\npsi", "psi ".repeat(15));
+        triggers(&source, Lint::StackOverflow, 3);
+    }
+
+    #[test]
+    fn stack_join() {
+        // jnz jumps back with one more value on the stack
+        let source = ".org 20\n&loop\n.org 30\nloop: psd\njnz";
+        triggers(source, Lint::StackJoin, 4);
+    }
+
+    #[test]
+    fn stack_effect() {
+        // the return address is part of the inputs, sum leaves the sum behind
+        let source = ".org 30\npsi psi psi psi jmp\n.org 40\n; ( ret0 ret1 a b -- )\nsum: add\njmp";
+        triggers(source, Lint::StackEffect, 6);
+        let source = source.replace("-- )", "-- c )");
+        assert_eq!(found(&source, Lint::StackEffect), NONE);
+    }
+
+    #[test]
+    fn dp_unknown() {
+        triggers(".org 30\npsi psi\nmdp", Lint::DpUnknown, 3);
+    }
+}
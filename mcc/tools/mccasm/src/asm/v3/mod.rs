@@ -1,2 +1,10 @@
+pub mod ast;
+pub mod cfg;
 pub mod codegen;
-pub mod lexing;
+pub mod dp;
+pub mod fmt;
+pub mod lint;
+pub mod parser;
+pub mod peephole;
+pub mod stack;
+pub mod tokenizer;
@@ -0,0 +1,291 @@
+use std::ops::Range;
+
+use libmcc::v3::Instruction;
+
+use super::{
+    ast::{Ast, Item, ItemKind, ParseError},
+    tokenizer::{tokenize, Token, TokenKind},
+};
+use crate::util::{parse_hex4, parse_hex8};
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    trivia: Vec<Token>,
+    ast: Ast,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    ///Takes the next token when it directly follows `end` and has the kind
+    fn next_adjacent(&mut self, end: usize, kind: TokenKind) -> Option<Token> {
+        match self.peek() {
+            Some(token) if token.kind == kind && token.span.start == end => self.next(),
+            _ => None,
+        }
+    }
+
+    ///Index of the next token that isn't trivia
+    fn next_significant(&self) -> Option<usize> {
+        (self.pos..self.tokens.len()).find(|i| !self.tokens[*i].kind.is_trivia())
+    }
+
+    fn push(&mut self, kind: ItemKind, span: Range<usize>) {
+        self.ast.items.push(Item {
+            kind,
+            span,
+            trivia: std::mem::take(&mut self.trivia),
+        });
+    }
+
+    fn error(&mut self, message: &str, span: Range<usize>) {
+        self.ast.errors.push(ParseError {
+            message: message.into(),
+            span: span.clone(),
+        });
+        self.push(ItemKind::Error, span);
+    }
+
+    ///`.org` and `.dp` take an address that may be on a later line
+    fn directive(&mut self, directive: Token, kind: fn(u8) -> ItemKind) {
+        let arg = self
+            .next_significant()
+            .filter(|i| self.tokens[*i].kind == TokenKind::Word);
+        let Some(arg) = arg else {
+            self.error("expected an address", directive.span);
+            return;
+        };
+        let arg_span = self.tokens[arg].span.clone();
+        match parse_hex8(&self.source[arg_span.clone()]) {
+            Some(addr) => {
+                // the trivia between the directive and the address is part of the item
+                self.pos = arg + 1;
+                self.push(kind(addr), directive.span.start..arg_span.end);
+            }
+            None => {
+                self.push(ItemKind::Error, directive.span);
+                self.trivia
+                    .extend(self.tokens[self.pos..arg].iter().cloned());
+                self.pos = arg + 1;
+                self.error("Failed to parse hex digit", arg_span);
+            }
+        }
+    }
+
+    fn label_ref(&mut self, amp: Token) {
+        let start = amp.span.start;
+        let mut end = amp.span.end;
+        let mut amps = 1;
+        while let Some(token) = self.next_adjacent(end, TokenKind::Amp) {
+            end = token.span.end;
+            amps += 1;
+        }
+        let Some(name) = self.next_adjacent(end, TokenKind::Word) else {
+            self.error("expected a label name", start..end);
+            return;
+        };
+        if amps > 2 {
+            self.error("too many '&'", start..name.span.end);
+            return;
+        }
+        let name_text = name.text(self.source);
+        self.push(
+            ItemKind::LabelRef {
+                name: name_text.into(),
+                wide: amps == 2,
+            },
+            start..name.span.end,
+        );
+    }
+
+    fn word(&mut self, word: Token) {
+        let text = word.text(self.source);
+        if let Some(colon) = self.next_adjacent(word.span.end, TokenKind::Colon) {
+            self.push(
+                ItemKind::LabelDef(text.into()),
+                word.span.start..colon.span.end,
+            );
+        } else if text == ".org" {
+            self.directive(word, ItemKind::Org);
+        } else if text == ".dp" {
+            self.directive(word, ItemKind::Dp);
+        } else if let Some(hex) = text.strip_prefix("0x") {
+            match parse_hex4(hex) {
+                Some(val) => self.push(ItemKind::HexLiteral(val), word.span),
+                None => self.error("Failed to parse hex digit", word.span),
+            }
+        } else {
+            match Instruction::try_from_str(text) {
+                Some(inst) => self.push(ItemKind::Instruction(inst), word.span),
+                None => self.error("Invalid instruction", word.span),
+            }
+        }
+    }
+
+    fn string(&mut self, string: Token) {
+        let text = string.text(self.source);
+        // the tokenizer ends a string at the first quote that isn't escaped or at the end of the line
+        let body = text[1..].strip_suffix('"').filter(|body| {
+            let backslashes = body.len() - body.trim_end_matches('\\').len();
+            backslashes.is_multiple_of(2)
+        });
+        let Some(body) = body else {
+            self.error("unterminated string", string.span);
+            return;
+        };
+        match unescape(body) {
+            Some(bytes) => self.push(ItemKind::String(bytes), string.span),
+            None => self.error("invalid escape", string.span),
+        }
+    }
+}
+
+///Supports `\n`, `\r`, `\t`, `\0`, `\\`, `\"` and `\xHH`
+fn unescape(body: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = body.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(char.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        bytes.push(match chars.next()? {
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            '0' => 0,
+            '\\' => b'\\',
+            '"' => b'"',
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                if hex.len() != 2 {
+                    return None;
+                }
+                u8::from_str_radix(&hex, 16).ok()?
+            }
+            _ => return None,
+        });
+    }
+    Some(bytes)
+}
+
+///Parses mcc v3 assembly, errors are collected and the invalid source is kept as `ItemKind::Error`
+pub fn parse(source: &str) -> Ast {
+    let mut parser = Parser {
+        source,
+        tokens: tokenize(source),
+        pos: 0,
+        trivia: Vec::new(),
+        ast: Ast::default(),
+    };
+    while let Some(token) = parser.next() {
+        match token.kind {
+            kind if kind.is_trivia() => parser.trivia.push(token),
+            TokenKind::Word => parser.word(token),
+            TokenKind::Amp => parser.label_ref(token),
+            TokenKind::String => parser.string(token),
+            _ => parser.error("unexpected ':'", token.span),
+        }
+    }
+    parser.ast.trailing_trivia = parser.trivia;
+    parser.ast
+}
+
+#[cfg(test)]
+mod tests {
+    use libmcc::{u4, v3::Instruction};
+
+    use super::parse;
+    use crate::asm::v3::ast::ItemKind;
+
+    fn kinds(source: &str) -> Vec<ItemKind> {
+        let ast = parse(source);
+        assert!(ast.errors.is_empty(), "{:?}", ast.errors);
+        ast.items.into_iter().map(|item| item.kind).collect()
+    }
+
+    ///Every error as `message 'source text'`
+    fn errors(source: &str) -> Vec<String> {
+        parse(source)
+            .errors
+            .iter()
+            .map(|err| format!("{} '{}'", err.message, &source[err.span.clone()]))
+            .collect()
+    }
+
+    #[test]
+    fn items() {
+        assert_eq!(
+            kinds(".org\n  3f # addr\nloop: psi &loop &&end 0xa .dp 20"),
+            [
+                ItemKind::Org(0x3F),
+                ItemKind::LabelDef("loop".into()),
+                ItemKind::Instruction(Instruction::Psi),
+                ItemKind::LabelRef {
+                    name: "loop".into(),
+                    wide: false
+                },
+                ItemKind::LabelRef {
+                    name: "end".into(),
+                    wide: true
+                },
+                ItemKind::HexLiteral(u4::from_low(0xA)),
+                ItemKind::Dp(0x20),
+            ]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            kinds(r#""hi\n" "\"\\\x41\0" "é""#),
+            [
+                ItemKind::String(b"hi\n".to_vec()),
+                ItemKind::String(b"\"\\A\0".to_vec()),
+                ItemKind::String("é".as_bytes().to_vec()),
+            ]
+        );
+        assert_eq!(errors("\"open\npsi"), ["unterminated string '\"open'"]);
+        assert_eq!(errors(r#""a\"#), [r#"unterminated string '"a\'"#]);
+        assert_eq!(
+            errors(r#""\q" "\x4""#),
+            [r#"invalid escape '"\q"'"#, r#"invalid escape '"\x4"'"#,]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            errors("bogus 0xg : &&&x & .org zz\n.org"),
+            [
+                "Invalid instruction 'bogus'",
+                "Failed to parse hex digit '0xg'",
+                "unexpected ':' ':'",
+                "too many '&' '&&&x'",
+                "expected a label name '&'",
+                "Failed to parse hex digit 'zz'",
+                "expected an address '.org'",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_keep_the_rest_of_the_file() {
+        let ast = parse("bogus\npsi\n");
+        assert_eq!(ast.items.len(), 2);
+        assert_eq!(ast.items[0].kind, ItemKind::Error);
+        assert_eq!(ast.items[1].kind, ItemKind::Instruction(Instruction::Psi));
+        assert_eq!(ast.items[1].span, 6..9);
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+    ops::Range,
+};
+
+use libmcc::v3::{
+    format_sequence,
+    Instruction::{self, *},
+};
+
+use super::{
+    ast::{Ast, Item, ItemKind, LineIndex},
+    codegen::{self, item_addrs, Assembly},
+    dp,
+    stack::{self, Roots},
+};
+
+///Sequences that have the same effect as a shorter one (found with `mccopt peephole`)
+///as long as the stack holds what they pop, mccemu's superopt tests check every one of them
+pub const RULES: &[(&[Instruction], &[Instruction])] = &[
+    (&[Swp, Swp], &[]),
+    (&[Di, Dd], &[]),
+    (&[Dd, Di], &[]),
+    (&[Inc, Dec], &[]),
+    (&[Dec, Inc], &[]),
+    // mdp overwrites dp
+    (&[Di, Mdp], &[Mdp]),
+    (&[Dd, Mdp], &[Mdp]),
+    (&[Psi, Dd, Dd], &[Psd]),
+    (&[Psd, Di, Di], &[Psi]),
+    (&[Poi, Dd, Dd], &[Pod]),
+    (&[Pod, Di, Di], &[Poi]),
+    (&[Swp, Di, Swp], &[Di]),
+    (&[Swp, Dd, Swp], &[Dd]),
+];
+
+///A rewrite of the instructions at an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    ///Address of the first instruction before optimizing
+    pub addr: u8,
+    pub linenum: usize,
+    ///Byte range in the source
+    pub span: Range<usize>,
+    pub from: Vec<Instruction>,
+    pub to: Vec<Instruction>,
+    ///Why the change wasn't applied
+    pub skipped: Option<Box<str>>,
+}
+
+impl Change {
+    ///Nibbles the change saves
+    pub fn saved(&self) -> usize {
+        self.from.len() - self.to.len()
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {} ({:#04x}): {} -> {}",
+            self.linenum,
+            self.addr,
+            format_sequence(&self.from),
+            format_sequence(&self.to)
+        )?;
+        match &self.skipped {
+            Some(reason) => write!(f, " skipped, {}", reason),
+            None => write!(f, " saves {}", self.saved()),
+        }
+    }
+}
+
+///Nibbles the stack has to hold before `code` so it never pops from an empty stack
+fn pops(code: &[Instruction]) -> usize {
+    let mut needed = 0;
+    let mut depth = 0;
+    for inst in code {
+        let (needs, leaves) = stack::effect(*inst);
+        if needs > depth {
+            needed += needs - depth;
+            depth = needs;
+        }
+        depth = depth - needs + leaves;
+    }
+    needed
+}
+
+///An instruction of a run, None for instructions a rule wrote
+struct Slot {
+    inst: Instruction,
+    item: Option<usize>,
+    addr: u8,
+    span: Range<usize>,
+}
+
+///Instructions between labels, directives and data with the rules applied
+struct Run {
+    items: Range<usize>,
+    slots: Vec<Slot>,
+    changes: Vec<Change>,
+}
+
+///Applies the rules where the smallest depth the stack analysis knows covers what they pop,
+///`inc dec` or `swp swp` on a stack that is too short change sp
+fn rewrite(
+    source: &str,
+    ast: &Ast,
+    items: Range<usize>,
+    addrs: &[u8],
+    depths: &[Option<usize>; 256],
+) -> Run {
+    let index = LineIndex::new(source);
+    let mut slots: Vec<Slot> = items
+        .clone()
+        .map(|i| match ast.items[i].kind {
+            ItemKind::Instruction(inst) => Slot {
+                inst,
+                item: Some(i),
+                addr: addrs[i],
+                span: ast.items[i].span.clone(),
+            },
+            _ => unreachable!("runs only contain instructions"),
+        })
+        .collect();
+    let mut changes = Vec::new();
+    // a rewrite can create a new match like `di swp swp dd`
+    'rewrite: loop {
+        for pos in 0..slots.len() {
+            for (from, to) in RULES {
+                let matches = slots[pos..]
+                    .iter()
+                    .map(|slot| slot.inst)
+                    .take(from.len())
+                    .eq(from.iter().copied());
+                // written instructions keep the address of the first one they replace and the depth there,
+                // nothing is known about code only reached through a jmp
+                let depth = depths[slots[pos].addr as usize].unwrap_or(0);
+                let covered = depth >= pops(from);
+                if !matches || !covered {
+                    continue;
+                }
+                let removed: Vec<Slot> = slots.drain(pos..pos + from.len()).collect();
+                let span = removed[0].span.start..removed[from.len() - 1].span.end;
+                let addr = removed[0].addr;
+                changes.push(Change {
+                    addr,
+                    linenum: index.linenum(span.start),
+                    span: span.clone(),
+                    from: from.to_vec(),
+                    to: to.to_vec(),
+                    skipped: None,
+                });
+                let written = to.iter().map(|inst| Slot {
+                    inst: *inst,
+                    item: None,
+                    addr,
+                    span: span.clone(),
+                });
+                slots.splice(pos..pos, written);
+                continue 'rewrite;
+            }
+        }
+        break;
+    }
+    Run {
+        items,
+        slots,
+        changes,
+    }
+}
+
+///The ast with some runs replaced by their rewritten instructions
+fn apply(ast: &Ast, runs: &[&Run]) -> Ast {
+    let mut items = Vec::with_capacity(ast.items.len());
+    let mut pos = 0;
+    for run in runs {
+        items.extend_from_slice(&ast.items[pos..run.items.start]);
+        for slot in run.slots.iter() {
+            items.push(match slot.item {
+                Some(i) => ast.items[i].clone(),
+                None => Item {
+                    kind: ItemKind::Instruction(slot.inst),
+                    span: slot.span.clone(),
+                    trivia: Vec::new(),
+                },
+            });
+        }
+        pos = run.items.end;
+    }
+    items.extend_from_slice(&ast.items[pos..]);
+    Ast {
+        items,
+        trailing_trivia: ast.trailing_trivia.clone(),
+        errors: ast.errors.clone(),
+    }
+}
+
+///Addresses of the items that match `filter` by their span
+fn addrs_by_span(ast: &Ast, filter: impl Fn(&ItemKind) -> bool) -> HashMap<Range<usize>, u8> {
+    ast.items
+        .iter()
+        .zip(item_addrs(ast))
+        .filter(|(item, _)| filter(&item.kind))
+        .map(|(item, addr)| (item.span.clone(), addr))
+        .collect()
+}
+
+fn is_data(kind: &ItemKind) -> bool {
+    matches!(
+        kind,
+        ItemKind::HexLiteral(_) | ItemKind::String(_) | ItemKind::LabelRef { .. }
+    )
+}
+
+///The address of every reached jmp and jnz by its span and every address it can jump to,
+///a jnz falling through doesn't count
+type Jumps = HashMap<Range<usize>, (u8, BTreeSet<u8>)>;
+
+///Err is the address of a jump with a target that isn't known
+fn jump_targets(assembly: &Assembly) -> Result<Jumps, u8> {
+    let mut jumps = HashMap::new();
+    for (addr, successors) in assembly.dp.successors.iter() {
+        let inst = Instruction::from_u4(assembly.image[*addr as usize]);
+        if !matches!(inst, Jmp | Jnz) {
+            continue;
+        }
+        if assembly.dp.indirect.contains(addr) {
+            return Err(*addr);
+        }
+        let targets = successors
+            .iter()
+            .filter(|target| inst == Jmp || Some(**target) != addr.checked_add(1))
+            .copied()
+            .collect();
+        if let Some(span) = assembly.spans.get(addr) {
+            jumps.insert(span.clone(), (*addr, targets));
+        }
+    }
+    Ok(jumps)
+}
+
+///Why the optimized program could behave differently, None when it can't
+fn check(original: (&Ast, &Assembly), optimized: (&Ast, &Assembly)) -> Option<String> {
+    let data = addrs_by_span(optimized.0, is_data);
+    if addrs_by_span(original.0, is_data) != data {
+        return Some("it moves data".into());
+    }
+    // jnz only sets the low nibble of ip, its target has to stay on its page.
+    // Anonymous and local labels aren't in the symbols so every definition is compared by its span
+    let labels = addrs_by_span(optimized.0, |kind| matches!(kind, ItemKind::LabelDef(_)));
+    for (item, addr) in original.0.items.iter().zip(item_addrs(original.0)) {
+        let ItemKind::LabelDef(name) = &item.kind else {
+            continue;
+        };
+        if labels.get(&item.span).map(|new| new & 0xF0) != Some(addr & 0xF0) {
+            return Some(format!("it moves '{}' to another page", name));
+        }
+    }
+    let is_jnz = |kind: &ItemKind| *kind == ItemKind::Instruction(Jnz);
+    let jnzs = addrs_by_span(optimized.0, is_jnz);
+    for (span, addr) in addrs_by_span(original.0, is_jnz) {
+        if jnzs.get(&span).map(|new| new & 0xF0) != Some(addr & 0xF0) {
+            return Some("it moves a jnz to another page".into());
+        }
+    }
+    // a jump to a label follows it, any other target has to keep its item. Not knowing
+    // where a jump goes, any instruction that moves could be its target
+    let jumps = match jump_targets(original.1) {
+        Ok(jumps) => jumps,
+        Err(addr) => {
+            return Some(format!(
+                "the jump at {:#04x} has a target that isn't known",
+                addr
+            ))
+        }
+    };
+    let moved = addrs_by_span(optimized.0, |_| true);
+    let labels: HashMap<u8, Range<usize>> =
+        addrs_by_span(original.0, |kind| matches!(kind, ItemKind::LabelDef(_)))
+            .into_iter()
+            .map(|(span, addr)| (addr, span))
+            .collect();
+    let target = |addr: u8| match labels.get(&addr).or(original.1.spans.get(&addr)) {
+        Some(span) => moved.get(span).copied(),
+        None => Some(addr),
+    };
+    let new_jumps = jump_targets(optimized.1).unwrap_or_default();
+    for (span, (addr, targets)) in jumps.iter() {
+        let expected: Option<BTreeSet<u8>> = targets.iter().map(|addr| target(*addr)).collect();
+        let targets = new_jumps.get(span).map(|(_, targets)| targets);
+        if expected.is_none() || targets != expected.as_ref() {
+            return Some(format!("it changes where the jump at {:#04x} goes", addr));
+        }
+    }
+    for (addr, cell) in original.1.dp.dp.iter() {
+        let inst = Instruction::from_u4(original.1.image[*addr as usize]);
+        let Some(cell) = cell.filter(|_| dp::access(inst).is_some()) else {
+            continue;
+        };
+        if original.1.spans.get(&cell) != optimized.1.spans.get(&cell) {
+            return Some(format!("it moves the cell {:#04x} dp points to", cell));
+        }
+    }
+    None
+}
+
+///Applies the rewrites to every run of instructions where the stack holds what they pop and moving the code after it
+///keeps data, labels and jnz pages, the targets of every jump and the cells dp is known to touch where they are.
+///Returns the optimized ast and every change, skipped ones included
+pub fn optimize(source: &str, ast: &Ast, assembly: &Assembly) -> (Ast, Vec<Change>) {
+    let addrs = item_addrs(ast);
+    let Roots {
+        roots, jnz_targets, ..
+    } = Roots::new(source, ast);
+    let depths = stack::depths(&assembly.image, &roots, &jnz_targets);
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (i, item) in ast.items.iter().enumerate() {
+        match (&item.kind, start) {
+            (ItemKind::Instruction(_), None) => start = Some(i),
+            (ItemKind::Instruction(_), Some(_)) => {}
+            (_, Some(run_start)) => {
+                runs.push(rewrite(source, ast, run_start..i, &addrs, &depths));
+                start = None;
+            }
+            (_, None) => {}
+        }
+    }
+    if let Some(run_start) = start {
+        runs.push(rewrite(
+            source,
+            ast,
+            run_start..ast.items.len(),
+            &addrs,
+            &depths,
+        ));
+    }
+
+    let mut accepted: Vec<&Run> = Vec::new();
+    let mut optimized = ast.clone();
+    let mut changes = Vec::new();
+    for run in runs.iter().filter(|run| !run.changes.is_empty()) {
+        let mut trial_runs = accepted.clone();
+        trial_runs.push(run);
+        let trial = apply(ast, &trial_runs);
+        let reason = match codegen::gencode(source, &trial) {
+            Ok(trial_assembly) => check((ast, assembly), (&trial, &trial_assembly)),
+            Err(err) => Some(err.message.to_string()),
+        };
+        match reason {
+            None => {
+                accepted = trial_runs;
+                optimized = trial;
+                changes.extend(run.changes.iter().cloned());
+            }
+            Some(reason) => changes.extend(run.changes.iter().map(|change| Change {
+                skipped: Some(reason.as_str().into()),
+                ..change.clone()
+            })),
+        }
+    }
+    (optimized, changes)
+}
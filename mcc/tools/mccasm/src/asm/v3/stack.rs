@@ -0,0 +1,375 @@
+use std::collections::{HashMap, HashSet};
+
+use libmcc::{u4, v3::Instruction};
+
+use super::{
+    ast::{Ast, ItemKind, LineIndex},
+    codegen::{item_addrs, label_names},
+    lint::attached_comments,
+};
+
+///Address the vm starts executing at with an empty stack
+pub const ENTRY: u8 = 0x30;
+///sp is a single nibble, a push at this depth wraps it back to 0
+pub const STACK_SIZE: usize = 15;
+
+///A `( a b -- c )` annotation, only the amount of names matters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEffect {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl StackEffect {
+    ///Parses the text of a comment like `; ( a b -- c )`
+    pub fn parse(comment: &str) -> Option<Self> {
+        let body = comment[1..].trim().strip_prefix('(')?.strip_suffix(')')?;
+        let (inputs, outputs) = body.split_once("--")?;
+        Some(Self {
+            inputs: inputs.split_whitespace().count(),
+            outputs: outputs.split_whitespace().count(),
+        })
+    }
+}
+
+impl std::fmt::Display for StackEffect {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.write_fmt(format_args!("( {} -- {} )", self.inputs, self.outputs))
+    }
+}
+
+///Nibbles an instruction needs on the stack and the amount it leaves there
+pub fn effect(inst: Instruction) -> (usize, usize) {
+    use Instruction::*;
+    match inst {
+        Nop | Di | Dd => (0, 0),
+        Psi | Psd => (0, 1),
+        Poi | Pod => (1, 0),
+        Mdp | Jmp => (2, 0),
+        Jnz | Inc | Dec => (1, 1),
+        Swp => (2, 2),
+        Add | Sub | Mul => (2, 1),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    ///An instruction pops more than is on the stack
+    Underflow,
+    ///The stack grows past 15 nibbles
+    Overflow,
+    ///Two paths reach an address with a different depth
+    Join { depth: usize, other: usize },
+    ///A routine leaves with a depth that doesn't match its annotation
+    Effect { depth: usize, effect: StackEffect },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub addr: u8,
+    ///Address of the annotated routine, None for code reached from the entry point
+    pub routine: Option<u8>,
+}
+
+///Where the analysis starts and what it knows about the stack there
+pub struct Root {
+    pub addr: u8,
+    pub effect: Option<StackEffect>,
+}
+
+///The roots of a source: the entry point and every label with a `( -- )` annotation
+pub struct Roots {
+    pub roots: Vec<Root>,
+    ///Labels a 1 nibble reference points to
+    pub jnz_targets: Vec<u8>,
+    ///Name of every annotated routine by its address
+    pub routines: HashMap<u8, Box<str>>,
+}
+
+impl Roots {
+    pub fn new(source: &str, ast: &Ast) -> Self {
+        let index = LineIndex::new(source);
+        let names = label_names(ast);
+        let addrs = item_addrs(ast);
+        let mut effects = HashMap::new();
+        for (line, comment) in attached_comments(source, ast, &index) {
+            if let Some(effect) = StackEffect::parse(comment) {
+                effects.entry(line).or_insert(effect);
+            }
+        }
+
+        let mut roots = vec![Root {
+            addr: ENTRY,
+            effect: None,
+        }];
+        let mut routines = HashMap::new();
+        let mut jnz_targets = Vec::new();
+        for ((item, name), addr) in ast.items.iter().zip(&names).zip(&addrs) {
+            match &item.kind {
+                ItemKind::LabelDef(_) => {
+                    let effect = effects.get(&index.line(item.span.start));
+                    if let (Some(effect), Some(name)) = (effect, name) {
+                        if !routines.contains_key(addr) {
+                            routines.insert(*addr, name.clone());
+                            roots.push(Root {
+                                addr: *addr,
+                                effect: Some(*effect),
+                            });
+                        }
+                    }
+                }
+                // jnz only sets the low nibble of ip, a 1 nibble reference is the usual target
+                ItemKind::LabelRef { wide: false, .. } => {
+                    let target = ast.items.iter().zip(&names).zip(&addrs).find(
+                        |((other, other_name), _)| {
+                            matches!(other.kind, ItemKind::LabelDef(_)) && *other_name == name
+                        },
+                    );
+                    if let Some((_, target)) = target {
+                        jnz_targets.push(*target);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self {
+            roots,
+            jnz_targets,
+            routines,
+        }
+    }
+}
+
+///Follows every path from `root` and returns the smallest depth before every reached instruction.
+///A path that reaches an instruction with a smaller depth than before is followed again
+fn walk(
+    image: &[u4; 256],
+    root: &Root,
+    jnz_targets: &[u8],
+    mut report: impl FnMut(IssueKind, u8),
+) -> [Option<usize>; 256] {
+    let mut depths: [Option<usize>; 256] = [None; 256];
+    let mut work = vec![(root.addr, root.effect.map_or(0, |effect| effect.inputs))];
+    while let Some((addr, depth)) = work.pop() {
+        if let Some(other) = depths[addr as usize] {
+            if other != depth {
+                report(IssueKind::Join { depth, other }, addr);
+            }
+            if depth >= other {
+                continue;
+            }
+        }
+        depths[addr as usize] = Some(depth);
+
+        let inst = Instruction::from_u4(image[addr as usize]);
+        let (needs, leaves) = effect(inst);
+        if depth < needs {
+            report(IssueKind::Underflow, addr);
+        }
+        let mut depth = depth.saturating_sub(needs) + leaves;
+        if depth > STACK_SIZE {
+            report(IssueKind::Overflow, addr);
+            // keep going at the limit so only the pushes past it are reported
+            depth = STACK_SIZE;
+        }
+
+        match inst {
+            Instruction::Jmp => {
+                if let Some(effect) = root.effect {
+                    if depth != effect.outputs {
+                        report(IssueKind::Effect { depth, effect }, addr);
+                    }
+                }
+            }
+            // the vm halts after the instruction at ff
+            _ if addr == 0xFF => {}
+            Instruction::Jnz => {
+                work.push((addr + 1, depth));
+                for target in jnz_targets {
+                    if target & 0xF0 == addr & 0xF0 {
+                        work.push((*target, depth));
+                    }
+                }
+            }
+            _ => work.push((addr + 1, depth)),
+        }
+    }
+    depths
+}
+
+///Follows every path from the roots through the image and tracks the depth of the stack.
+///
+///Code reached from the entry point knows the real depth, an annotated routine starts with its inputs on the stack.
+///A jmp ends a path (its target is computed at runtime), a jnz continues at the next address
+///and at every label in `jnz_targets` on the same page
+pub fn analyze(image: &[u4; 256], roots: &[Root], jnz_targets: &[u8]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut seen = HashSet::new();
+    for root in roots {
+        let routine = root.effect.map(|_| root.addr);
+        walk(image, root, jnz_targets, |kind, addr| {
+            let key = (std::mem::discriminant(&kind), addr, routine);
+            if seen.insert(key) {
+                issues.push(Issue {
+                    kind,
+                    addr,
+                    routine,
+                });
+            }
+        });
+    }
+    issues
+}
+
+///The smallest depth of the stack before every instruction [analyze] reaches from any of the roots
+pub fn depths(image: &[u4; 256], roots: &[Root], jnz_targets: &[u8]) -> [Option<usize>; 256] {
+    let mut depths: [Option<usize>; 256] = [None; 256];
+    for root in roots {
+        let found = walk(image, root, jnz_targets, |_, _| {});
+        for (depth, found) in depths.iter_mut().zip(found) {
+            *depth = match (*depth, found) {
+                (Some(depth), Some(found)) => Some(depth.min(found)),
+                (depth, found) => depth.or(found),
+            };
+        }
+    }
+    depths
+}
+
+#[cfg(test)]
+mod tests {
+    use libmcc::{
+        u4,
+        v3::Instruction::{self, *},
+    };
+
+    use super::{analyze, depths, Issue, IssueKind, Root, StackEffect, ENTRY};
+
+    fn image(code: &[Instruction]) -> [u4; 256] {
+        let mut image = [u4::ZERO; 256];
+        for (i, inst) in code.iter().enumerate() {
+            image[ENTRY as usize + i] = inst.into_u4();
+        }
+        image
+    }
+
+    fn entry() -> Root {
+        Root {
+            addr: ENTRY,
+            effect: None,
+        }
+    }
+
+    fn issue(kind: IssueKind, addr: u8) -> Issue {
+        Issue {
+            kind,
+            addr,
+            routine: None,
+        }
+    }
+
+    #[test]
+    fn parse_annotations() {
+        assert_eq!(
+            StackEffect::parse("; ( ret0 ret1 a b -- c )"),
+            Some(StackEffect {
+                inputs: 4,
+                outputs: 1
+            })
+        );
+        assert_eq!(
+            StackEffect::parse(";(--)"),
+            Some(StackEffect {
+                inputs: 0,
+                outputs: 0
+            })
+        );
+        assert_eq!(StackEffect::parse("; a b -- c"), None);
+        assert_eq!(StackEffect::parse("; ( a b )"), None);
+    }
+
+    #[test]
+    fn underflow() {
+        // add leaves its result even without a second value, pod has something to pop
+        let issues = analyze(&image(&[Psi, Add, Pod, Pod]), &[entry()], &[]);
+        assert_eq!(
+            issues,
+            [
+                issue(IssueKind::Underflow, 0x31),
+                issue(IssueKind::Underflow, 0x33)
+            ]
+        );
+        assert_eq!(analyze(&image(&[Psi, Psi, Add, Pod]), &[entry()], &[]), []);
+    }
+
+    #[test]
+    fn overflow() {
+        let issues = analyze(&image(&[Psi; 16]), &[entry()], &[]);
+        assert_eq!(issues, [issue(IssueKind::Overflow, 0x3F)]);
+        let mut code = [Psi; 18];
+        code[16] = Nop;
+        let issues = analyze(&image(&code), &[entry()], &[]);
+        assert_eq!(
+            issues,
+            [
+                issue(IssueKind::Overflow, 0x3F),
+                issue(IssueKind::Overflow, 0x41)
+            ]
+        );
+    }
+
+    #[test]
+    fn join() {
+        // the jnz back to 0x31 has one more nibble on the stack each time around
+        let code = [Psi, Psd, Jnz];
+        let issues = analyze(&image(&code), &[entry()], &[0x31]);
+        assert_eq!(
+            issues,
+            [issue(IssueKind::Join { depth: 2, other: 1 }, 0x31)]
+        );
+        // a jnz target on another page can't be reached
+        assert_eq!(analyze(&image(&code), &[entry()], &[0x41]), []);
+        // popping the extra nibble before the jnz keeps the depth the same
+        let code = [Psi, Psd, Pod, Jnz];
+        let issues = analyze(&image(&code), &[entry()], &[0x31]);
+        assert!(issues
+            .iter()
+            .all(|issue| !matches!(issue.kind, IssueKind::Join { .. })));
+    }
+
+    #[test]
+    fn routine_effects() {
+        let mut image = image(&[]);
+        for (i, inst) in [Add, Jmp].iter().enumerate() {
+            image[0x40 + i] = inst.into_u4();
+        }
+        let routine = |inputs, outputs| Root {
+            addr: 0x40,
+            effect: Some(StackEffect { inputs, outputs }),
+        };
+        assert_eq!(analyze(&image, &[routine(4, 1)], &[]), []);
+        assert_eq!(
+            analyze(&image, &[routine(4, 0)], &[]),
+            [Issue {
+                kind: IssueKind::Effect {
+                    depth: 1,
+                    effect: StackEffect {
+                        inputs: 4,
+                        outputs: 0
+                    }
+                },
+                addr: 0x41,
+                routine: Some(0x40),
+            }]
+        );
+    }
+
+    #[test]
+    fn smallest_depths() {
+        // the first path reaches 0x32 with 2 nibbles, the jnz back to it with 1 and the pod after it sees 1 less
+        let depths = depths(&image(&[Psi, Psi, Pod, Jnz, Pod]), &[entry()], &[0x32]);
+        assert_eq!(depths[0x30..0x36], [0, 1, 1, 0, 1, 0].map(Some));
+        assert_eq!(depths[0x2F], None);
+    }
+}
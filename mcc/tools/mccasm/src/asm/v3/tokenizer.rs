@@ -0,0 +1,144 @@
+use std::{iter::Peekable, ops::Range, str::CharIndices};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Whitespace,
+    ///`\n` or `\r\n`
+    Newline,
+    ///From `#` or `;` to the end of the line
+    Comment,
+    ///Mnemonics, label names, directives and literals
+    Word,
+    ///`"text"` with `\` escapes, ends at the end of the line when it isn't terminated
+    String,
+    Colon,
+    Amp,
+}
+
+impl TokenKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    ///Byte range in the source
+    pub span: Range<usize>,
+}
+
+impl Token {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.span.clone()]
+    }
+}
+
+fn is_word_char(char: char) -> bool {
+    !char.is_whitespace() && !matches!(char, '#' | ';' | ':' | '&' | '"')
+}
+
+///Splits the source into tokens, joining the text of all tokens gives back the source
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    // consumes chars while pred holds and returns the end of the token
+    let end_while = |chars: &mut Peekable<CharIndices>, pred: &dyn Fn(char) -> bool| {
+        while chars.next_if(|(_, char)| pred(*char)).is_some() {}
+        chars.peek().map_or(source.len(), |(pos, _)| *pos)
+    };
+    while let Some((start, char)) = chars.next() {
+        let (kind, end) = match char {
+            '\n' => (TokenKind::Newline, start + 1),
+            '\r' if source[start + 1..].starts_with('\n') => {
+                chars.next();
+                (TokenKind::Newline, start + 2)
+            }
+            '#' | ';' => (
+                TokenKind::Comment,
+                end_while(&mut chars, &|char| char != '\n' && char != '\r'),
+            ),
+            '"' => {
+                let mut escaped = false;
+                let mut end = start + 1;
+                while let Some((pos, char)) =
+                    chars.next_if(|(_, char)| *char != '\n' && *char != '\r')
+                {
+                    if char == '"' && !escaped {
+                        end = pos + 1;
+                        break;
+                    }
+                    escaped = char == '\\' && !escaped;
+                    end = chars.peek().map_or(source.len(), |(pos, _)| *pos);
+                }
+                (TokenKind::String, end)
+            }
+            ':' => (TokenKind::Colon, start + 1),
+            '&' => (TokenKind::Amp, start + 1),
+            char if char.is_whitespace() => (
+                TokenKind::Whitespace,
+                end_while(&mut chars, &|char| {
+                    char.is_whitespace() && char != '\n' && char != '\r'
+                }),
+            ),
+            _ => (TokenKind::Word, end_while(&mut chars, &is_word_char)),
+        };
+        tokens.push(Token {
+            kind,
+            span: start..end,
+        });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, TokenKind::*};
+
+    fn kinds(source: &str) -> Vec<(super::TokenKind, &str)> {
+        tokenize(source)
+            .iter()
+            .map(|token| (token.kind, token.text(source)))
+            .collect()
+    }
+
+    #[test]
+    fn joining_the_tokens_gives_back_the_source() {
+        let source = "loop: psi &&end\r\n  \"a \\\" b\" ; é 😀\n\t0x1#c\n\"open";
+        let text: std::string::String = tokenize(source)
+            .iter()
+            .map(|token| token.text(source))
+            .collect();
+        assert_eq!(text, source);
+    }
+
+    #[test]
+    fn token_kinds() {
+        assert_eq!(
+            kinds("loop: &&end # x\r\n"),
+            [
+                (Word, "loop"),
+                (Colon, ":"),
+                (Whitespace, " "),
+                (Amp, "&"),
+                (Amp, "&"),
+                (Word, "end"),
+                (Whitespace, " "),
+                (Comment, "# x"),
+                (Newline, "\r\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_end_at_the_quote_or_the_line() {
+        assert_eq!(kinds(r#""a\"b"c"#), [(String, r#""a\"b""#), (Word, "c")]);
+        assert_eq!(
+            kinds("\"open\npsi"),
+            [(String, "\"open"), (Newline, "\n"), (Word, "psi")]
+        );
+    }
+}
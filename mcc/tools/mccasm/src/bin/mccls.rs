@@ -0,0 +1,16 @@
+use std::{
+    io::{self, BufReader},
+    process,
+};
+
+use mccasm::ls;
+
+fn main() {
+    match ls::serve(BufReader::new(io::stdin()), io::stdout()) {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            eprintln!("FATAL: {}", err);
+            process::exit(-1);
+        }
+    }
+}
@@ -1,6 +1,26 @@
-use libmcc::u4;
+use std::collections::{BTreeMap, BTreeSet};
 
-use crate::{util, Format};
+use clap::ValueEnum;
+use libmcc::{u4, v3::Instruction};
+
+use crate::{
+    asm::v3::{
+        codegen::{describe_addr, Assembly, Labels, Lines},
+        dp,
+    },
+    util,
+};
+
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
+#[value()]
+pub enum Format {
+    ///Choose format based on file extension
+    Auto,
+    Hex,
+    Bin,
+    ///Unpacked binary format (every nibble is byte aligned)
+    Ubin,
+}
 
 pub fn emit_hex(data: [u4; 256]) -> Vec<u8> {
     let mut output = String::new();
@@ -23,7 +43,7 @@ pub fn emit_bin_unpacked(data: [u4; 256]) -> Vec<u8> {
 }
 pub fn emit_bin_packed(data: [u4; 256]) -> Vec<u8> {
     let mut output = Vec::with_capacity(16 * 16);
-    for nibpair in data.chunks(2).into_iter() {
+    for nibpair in data.chunks(2) {
         let nib0 = nibpair[0].into_low();
         let nib1 = nibpair[1].into_high();
 
@@ -38,15 +58,87 @@ pub fn emit(format: Format, file_ext: Option<&str>, code: [u4; 256]) -> Vec<u8>
         Format::Ubin => emit_bin_unpacked(code),
         Format::Auto => emit(
             file_ext
-                .map(|ext| match ext {
+                .and_then(|ext| match ext {
                     "hex" => Some(Format::Hex),
                     "bin" => Some(Format::Bin),
                     _ => None,
                 })
-                .flatten()
                 .unwrap_or(Format::Bin),
             file_ext,
             code,
         ),
     }
 }
+
+///One `<addr> <label>` pair per line sorted by address followed by an `<addr> .data <size>` line for every run of data
+pub fn emit_symbols(labels: &Labels, data: &BTreeSet<u8>) -> Vec<u8> {
+    let mut labels: Vec<_> = labels.iter().collect();
+    labels
+        .sort_by(|(a_name, a_addr), (b_name, b_addr)| a_addr.cmp(b_addr).then(a_name.cmp(b_name)));
+
+    let mut output = String::new();
+    for (name, addr) in labels {
+        output.push_str(&format!("{:#04x} {}\n", addr, name));
+    }
+    let mut runs: Vec<(u8, usize)> = Vec::new();
+    for addr in data {
+        match runs.last_mut() {
+            Some((start, size)) if *start as usize + *size == *addr as usize => *size += 1,
+            _ => runs.push((*addr, 1)),
+        }
+    }
+    for (start, size) in runs {
+        output.push_str(&format!("{:#04x} .data {}\n", start, size));
+    }
+    output.into_bytes()
+}
+
+///One `<addr> <line>` pair per line for every address that was written
+pub fn emit_lines(lines: &Lines) -> Vec<u8> {
+    let mut output = String::new();
+    for (addr, linenum) in lines {
+        output.push_str(&format!("{:#04x} {}\n", addr, linenum));
+    }
+    output.into_bytes()
+}
+
+///The cell a reached instruction reads or writes like `25 data+1`, `??` when dp isn't known there
+pub fn describe_cell(assembly: &Assembly, addr: u8) -> Option<String> {
+    dp::access(Instruction::from_u4(assembly.image[addr as usize]))?;
+    let dp = assembly.dp.dp.get(&addr)?;
+    Some(match dp {
+        Some(cell) => match describe_addr(&assembly.symbols, *cell) {
+            Some(label) => format!("{:02x} {}", cell, label),
+            None => format!("{:02x}", cell),
+        },
+        None => "??".to_string(),
+    })
+}
+
+///Every address with its nibble and the cell it touches next to the source line that wrote it
+pub fn emit_listing(source: &str, assembly: &Assembly) -> Vec<u8> {
+    let mut addrs: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+    for (addr, linenum) in assembly.lines.iter() {
+        addrs.entry(*linenum).or_default().push(*addr);
+    }
+
+    let mut output = String::new();
+    for (i, text) in source.lines().enumerate() {
+        let Some(addrs) = addrs.get(&(i + 1)) else {
+            output.push_str(format!("{:27}{}", "", text).trim_end());
+            output.push('\n');
+            continue;
+        };
+        for (j, addr) in addrs.iter().enumerate() {
+            let cell = describe_cell(assembly, *addr).unwrap_or_default();
+            let text = if j == 0 { text } else { "" };
+            let line = format!(
+                "{:02x}  {:x}  {:20}  {}",
+                addr, assembly.image[*addr as usize], cell, text
+            );
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+    }
+    output.into_bytes()
+}
@@ -0,0 +1,11 @@
+pub mod asm;
+pub mod emiting;
+pub mod framing;
+pub mod ls;
+pub mod util;
+
+pub use asm::{
+    assemble,
+    v3::codegen::{Assembly, Section},
+    AsmError, Diagnostics, Options,
+};
@@ -0,0 +1,457 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    ops::Range,
+};
+
+use libmcc::{u4, v3::Instruction};
+use serde_json::{json, Value};
+
+use crate::{
+    asm::{
+        v3::{
+            ast::{ItemKind, LineIndex},
+            codegen::{self, Assembly},
+            lint::{self, Level},
+            parser,
+        },
+        AsmError, Stage,
+    },
+    emiting,
+    framing::{read_message, write_message},
+};
+
+const CORE_DOCS: &str = include_str!("../../../docs/v3/core.md");
+const ORG_DOC: &str = "**.org** XX\n\nPuts all code and data below it at address XX";
+const DP_DOC: &str =
+    "**.dp** XX\n\nTells the dp analysis that dp is XX when the next instruction runs";
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const SEVERITY_ERROR: u64 = 1;
+const SEVERITY_WARNING: u64 = 2;
+const KIND_KEYWORD: u64 = 14;
+const KIND_CONSTANT: u64 = 21;
+
+///An item of the syntax tree with the address it is assembled to
+struct Token {
+    span: Range<usize>,
+    kind: ItemKind,
+    addr: u8,
+    ///Name of the label after resolving local and anonymous labels
+    label: Option<Box<str>>,
+}
+impl Token {
+    fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    ///Span of the label name without the `&`/`&&` or `:`
+    fn label_span(&self) -> Range<usize> {
+        match self.kind {
+            ItemKind::LabelDef(_) => self.span.start..self.span.end - 1,
+            ItemKind::LabelRef { wide: true, .. } => self.span.start + 2..self.span.end,
+            ItemKind::LabelRef { wide: false, .. } => self.span.start + 1..self.span.end,
+            _ => self.span.clone(),
+        }
+    }
+}
+
+///Everything known about an open document
+struct Document {
+    text: String,
+    index: LineIndex,
+    tokens: Vec<Token>,
+    diagnostics: Vec<Value>,
+    ///None when the document doesn't assemble
+    assembly: Option<Assembly>,
+}
+
+///Positions count UTF-16 code units, comments and strings can hold any character
+fn range(text: &str, index: &LineIndex, span: Range<usize>) -> Value {
+    let position =
+        |offset| json!({ "line": index.line(offset), "character": index.col_utf16(text, offset) });
+    json!({ "start": position(span.start), "end": position(span.end) })
+}
+
+fn diagnostic(
+    text: &str,
+    index: &LineIndex,
+    span: Range<usize>,
+    message: &str,
+    severity: u64,
+) -> Value {
+    json!({
+        "range": range(text, index, span),
+        "severity": severity,
+        "source": "mccasm",
+        "message": message,
+    })
+}
+
+impl Document {
+    ///The parser keeps going after errors so one bad line doesn't hide the rest of the file
+    fn analyze(text: String) -> Self {
+        let index = LineIndex::new(&text);
+        let ast = parser::parse(&text);
+        let mut diagnostics: Vec<_> = ast
+            .errors
+            .iter()
+            .map(|err| {
+                let message = format!("{} '{}'", err.message, &text[err.span.clone()]);
+                diagnostic(&text, &index, err.span.clone(), &message, SEVERITY_ERROR)
+            })
+            .collect();
+        let assembly = match codegen::gencode(&text, &ast) {
+            Ok(assembly) if ast.errors.is_empty() => {
+                for (level, err) in lint::check(&text, &ast, &assembly, &HashMap::new()) {
+                    let severity = match level {
+                        Level::Deny => SEVERITY_ERROR,
+                        _ => SEVERITY_WARNING,
+                    };
+                    diagnostics.push(asm_diagnostic(&text, &index, &err, severity));
+                }
+                Some(assembly)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                diagnostics.push(asm_diagnostic(&text, &index, &err, SEVERITY_ERROR));
+                None
+            }
+        };
+
+        let names = codegen::label_names(&ast);
+        let addrs = codegen::item_addrs(&ast);
+        let tokens = ast
+            .items
+            .into_iter()
+            .zip(names)
+            .zip(addrs)
+            .map(|((item, label), addr)| Token {
+                span: item.span,
+                kind: item.kind,
+                addr,
+                label,
+            })
+            .collect();
+        Self {
+            text,
+            index,
+            tokens,
+            diagnostics,
+            assembly,
+        }
+    }
+
+    fn token_at(&self, position: &Value) -> Option<&Token> {
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let offset = self.index.offset_utf16(&self.text, line, character);
+        self.tokens
+            .iter()
+            .find(|token| token.span.start <= offset && offset <= token.span.end)
+    }
+
+    fn range(&self, span: Range<usize>) -> Value {
+        range(&self.text, &self.index, span)
+    }
+
+    fn definition(&self, name: &str) -> Option<&Token> {
+        self.tokens.iter().find(|token| {
+            matches!(token.kind, ItemKind::LabelDef(_)) && token.label() == Some(name)
+        })
+    }
+
+    fn hover(&self, token: &Token) -> String {
+        let at = format!("at `{:#04x}`", token.addr);
+        match &token.kind {
+            ItemKind::LabelDef(name) | ItemKind::LabelRef { name, .. } => {
+                match token.label().and_then(|label| self.definition(label)) {
+                    Some(def) => format!("**{}** = `{:#04x}`", name, def.addr),
+                    None => format!("**{}** is not defined", name),
+                }
+            }
+            ItemKind::Instruction(inst) => {
+                let at = match self
+                    .assembly
+                    .as_ref()
+                    .and_then(|assembly| emiting::describe_cell(assembly, token.addr))
+                {
+                    Some(cell) => format!("{}, dp: `{}`", at, cell),
+                    None => at,
+                };
+                match mnemonic_doc(*inst) {
+                    Some(doc) => format!("{}\n\n{}", doc, at),
+                    None => at,
+                }
+            }
+            ItemKind::HexLiteral(val) => format!("`{:#03x}` {}", val, at),
+            ItemKind::String(bytes) => format!("{} bytes {}", bytes.len(), at),
+            ItemKind::Org(_) => ORG_DOC.to_string(),
+            ItemKind::Dp(_) => DP_DOC.to_string(),
+            ItemKind::Error => at,
+        }
+    }
+}
+
+///Errors without a span point at their whole line
+fn asm_diagnostic(text: &str, index: &LineIndex, err: &AsmError, severity: u64) -> Value {
+    let span = err.span.clone().unwrap_or_else(|| {
+        let line = err.linenum.map_or(0, |linenum| linenum - 1);
+        let start = index.offset(line, 0);
+        let len = text[start..].find('\n').unwrap_or(text.len() - start);
+        start..start + len
+    });
+    let mut diagnostic = diagnostic(
+        text,
+        index,
+        span,
+        &format!("{} '{}'", err.message, err.code_snip),
+        severity,
+    );
+    if let Stage::Lint(lint) = err.stage {
+        diagnostic["code"] = json!(lint.name());
+    }
+    diagnostic
+}
+
+///The section of docs/v3/core.md describing an instruction
+fn mnemonic_doc(inst: Instruction) -> Option<String> {
+    let heading = format!("### {} ", inst.as_str());
+    let mut lines = CORE_DOCS
+        .lines()
+        .skip_while(|line| !line.starts_with(&heading));
+    let title = lines.next()?.trim_start_matches('#').trim();
+    let body: Vec<_> = lines
+        .take_while(|line| !line.starts_with('#'))
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let (mnemonic, opcode) = title.split_once(' ').unwrap_or((title, ""));
+    Some(format!(
+        "**{}** ({})\n\n{}",
+        mnemonic,
+        opcode,
+        body.join("\n")
+    ))
+}
+
+fn instructions() -> impl Iterator<Item = Instruction> {
+    (0..16).map(|opcode| Instruction::from_u4(u4::from_low(opcode)))
+}
+
+struct Server<W: Write> {
+    output: W,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    ///Exit code once the client sent exit
+    exit: Option<i32>,
+}
+
+impl<W: Write> Server<W> {
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&mut self.output, &message)
+    }
+
+    fn open(&mut self, uri: &str, text: String) -> io::Result<()> {
+        let document = Document::analyze(text);
+        let diagnostics = document.diagnostics.clone();
+        self.documents.insert(uri.to_string(), document);
+        self.notify(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.open(uri, text.to_string())?;
+            }
+            "textDocument/didChange" => {
+                // full sync, the last change is the whole document
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|changes| changes.last()) {
+                    self.open(uri, text["text"].as_str().unwrap_or_default().to_string())?;
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.notify(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )?;
+            }
+            "exit" => self.exit = Some(if self.shutdown { 0 } else { 1 }),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    ///Returns None for unknown methods
+    fn handle_request(&mut self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let position = &params["position"];
+        let document = self.documents.get(uri);
+        let token = document.and_then(|document| document.token_at(position));
+        Some(match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "completionProvider": { "triggerCharacters": ["&", "."] },
+                },
+                "serverInfo": { "name": "mccls", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/hover" => match (document, token) {
+                (Some(document), Some(token)) => json!({
+                    "contents": { "kind": "markdown", "value": document.hover(token) },
+                    "range": document.range(token.span.clone()),
+                }),
+                _ => Value::Null,
+            },
+            "textDocument/definition" => {
+                let def = document
+                    .zip(token.and_then(Token::label))
+                    .and_then(|(document, name)| Some((document, document.definition(name)?)));
+                match def {
+                    Some((document, def)) => {
+                        json!({ "uri": uri, "range": document.range(def.label_span()) })
+                    }
+                    None => Value::Null,
+                }
+            }
+            "textDocument/references" => {
+                let (Some(document), Some(name)) = (document, token.and_then(Token::label)) else {
+                    return Some(json!([]));
+                };
+                let include_declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(true);
+                let locations: Vec<_> = document
+                    .tokens
+                    .iter()
+                    .filter(|token| token.label() == Some(name))
+                    .filter(|token| {
+                        include_declaration || !matches!(token.kind, ItemKind::LabelDef(_))
+                    })
+                    .map(|token| json!({ "uri": uri, "range": document.range(token.label_span()) }))
+                    .collect();
+                json!(locations)
+            }
+            "textDocument/completion" => match document {
+                Some(document) => json!(completions(document, position)),
+                None => json!([]),
+            },
+            _ => return None,
+        })
+    }
+}
+
+fn completions(document: &Document, position: &Value) -> Vec<Value> {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let line_start = document.index.offset(line, 0);
+    let cursor = document.index.offset_utf16(&document.text, line, character);
+    let before = &document.text[line_start..cursor];
+    let word_start = before
+        .trim_end_matches(|char: char| !char.is_whitespace())
+        .len();
+    let word = &before[word_start..];
+
+    let mut labels: Vec<_> = document
+        .tokens
+        .iter()
+        .filter(|token| matches!(token.kind, ItemKind::LabelDef(_)))
+        .filter_map(|token| Some((token.label()?.to_string(), token.addr)))
+        // anonymous labels can't be referred to by name
+        .filter(|(name, _)| !name.contains('#'))
+        .collect();
+    labels.sort();
+    labels.dedup_by(|a, b| a.0 == b.0);
+
+    // after & only labels make sense, they replace the text after the ampersands
+    if word.starts_with('&') {
+        let name_start = line_start + word_start + word.len() - word.trim_start_matches('&').len();
+        let replace = name_start..cursor;
+        return labels
+            .into_iter()
+            .map(|(name, addr)| {
+                json!({
+                    "label": name,
+                    "kind": KIND_CONSTANT,
+                    "detail": format!("{:#04x}", addr),
+                    "textEdit": { "range": document.range(replace.clone()), "newText": name },
+                })
+            })
+            .collect();
+    }
+
+    let mut items: Vec<_> = instructions()
+        .map(|inst| {
+            let mut item = json!({ "label": inst.as_str(), "kind": KIND_KEYWORD });
+            if let Some(doc) = mnemonic_doc(inst) {
+                item["documentation"] = json!({ "kind": "markdown", "value": doc });
+            }
+            item
+        })
+        .collect();
+    items.push(json!({
+        "label": ".org",
+        "kind": KIND_KEYWORD,
+        "documentation": { "kind": "markdown", "value": ORG_DOC },
+    }));
+    items.push(json!({
+        "label": ".dp",
+        "kind": KIND_KEYWORD,
+        "documentation": { "kind": "markdown", "value": DP_DOC },
+    }));
+    for (name, addr) in labels {
+        items.push(json!({
+            "label": format!("&&{}", name),
+            "kind": KIND_CONSTANT,
+            "detail": format!("{:#04x}", addr),
+        }));
+    }
+    items
+}
+
+///Serves one client until it sends exit or closes the input, returns the exit code it asked for
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<i32> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        shutdown: false,
+        exit: None,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let response = match server.handle_request(method, params) {
+                    Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": METHOD_NOT_FOUND, "message": format!("unknown method '{}'", method) },
+                    }),
+                };
+                write_message(&mut server.output, &response)?
+            }
+            None => server.handle_notification(method, params)?,
+        }
+        if let Some(code) = server.exit {
+            return Ok(code);
+        }
+    }
+    Ok(0)
+}
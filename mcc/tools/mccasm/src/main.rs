@@ -1,4 +1,5 @@
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, Subcommand};
+use libmcc::v3::from_bin_packed;
 use std::{
     fs,
     io::{self, Read},
@@ -7,27 +8,60 @@ use std::{
 };
 use stderrlog::LogLevelNum;
 
-use crate::{emiting::*, util::count_nonzero_pages};
-mod asm;
-mod emiting;
-mod util;
+use mccasm::{
+    asm::{
+        self,
+        v3::lint::{Level, Lint},
+    },
+    emiting::*,
+    util::count_nonzero_pages,
+    Options,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input file or - to read from stdin
     #[arg(default_value = "-")]
     input: String,
 
     /// Output file
-    #[arg(short = 'o', long)]
-    output: String,
+    #[arg(short = 'o', long, required = true)]
+    output: Option<String>,
 
     /// The format of the output
     #[arg(short = 'f', long, default_value = "auto")]
     format: Format,
 
+    /// Write the address of every label to a file
+    #[arg(short = 's', long)]
+    symbols: Option<String>,
+
+    /// Write the source line of every address to a file (used by debuggers)
+    #[arg(short = 'l', long)]
+    lines: Option<String>,
+
+    /// Turn a lint into an error (duplicate-label is denied by default)
+    #[arg(long, value_name = "LINT")]
+    deny: Vec<Lint>,
+
+    /// Turn a lint off
+    #[arg(long, value_name = "LINT")]
+    allow: Vec<Lint>,
+
+    /// Write a listing with the address, nibble and the cell dp points to for every line to a file
+    #[arg(long)]
+    listing: Option<String>,
+
+    /// Apply peephole rewrites like removing `di dd` and print every change
+    #[arg(short = 'O', long)]
+    optimize: bool,
+
     /// Prints the amount of space the program uses
     #[arg(short = 'm', long)]
     memory_usage: bool,
@@ -37,16 +71,36 @@ struct Cli {
     log_level: usize,
 }
 
-#[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
-#[value()]
-enum Format {
-    ///Choose format based on file extension
-    Auto,
-    Hex,
-    Bin,
-    ///Unpacked binary format (every nibble is byte aligned)
-    Ubin,
+#[derive(Subcommand)]
+enum Command {
+    /// Format assembly sources
+    Fmt(FmtArgs),
+    /// Write the control-flow graph of a source or a bin image as Graphviz DOT
+    Cfg(CfgArgs),
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    /// Files to format in place or - to format stdin to stdout
+    #[arg(default_value = "-")]
+    inputs: Vec<String>,
+
+    /// Don't write anything, exit with 1 when a file isn't formatted
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Args)]
+struct CfgArgs {
+    /// Assembly source, or an image when it ends with .bin
+    #[arg(default_value = "-")]
+    input: String,
+
+    /// Output file, stdout when not given
+    #[arg(short = 'o', long)]
+    output: Option<String>,
 }
+
 fn get_input_data(path: &str) -> io::Result<String> {
     if path == "-" {
         let mut str = String::new();
@@ -54,18 +108,81 @@ fn get_input_data(path: &str) -> io::Result<String> {
         stdin.read_to_string(&mut str)?;
         Ok(str)
     } else {
-        let can_path = std::fs::canonicalize(&path)?;
+        let can_path = std::fs::canonicalize(path)?;
         let str = fs::read_to_string(can_path)?;
         Ok(str)
     }
 }
 
-fn die(message: &str) {
+fn die(message: &str) -> ! {
     eprintln!("FATAL: {}", message);
     process::exit(-1);
 }
+fn fmt(args: FmtArgs) {
+    let mut unformatted = false;
+    for path in args.inputs {
+        let source = get_input_data(&path).unwrap_or_else(|err: io::Error| {
+            die(&format!(
+                "Failed to read input '{}'\n{}",
+                path,
+                &err.to_string()
+            ))
+        });
+        let formatted = asm::v3::fmt::format(&source);
+        if path == "-" && !args.check {
+            print!("{}", formatted);
+            continue;
+        }
+        if formatted == source {
+            continue;
+        }
+        unformatted = true;
+        if args.check {
+            println!("would reformat {}", path);
+        } else {
+            fs::write(&path, formatted).unwrap_or_else(|err| {
+                die(&format!("Failed to write '{}'\n\n {}", path, err));
+            });
+        }
+    }
+    if args.check && unformatted {
+        process::exit(1);
+    }
+}
+
+fn cfg(args: CfgArgs) {
+    let (graph, labels) = if args.input.ends_with(".bin") {
+        let data = fs::read(&args.input)
+            .unwrap_or_else(|err| die(&format!("Failed to read input '{}'\n{}", args.input, err)));
+        let image = from_bin_packed(&data)
+            .unwrap_or_else(|| die("Input data was not the size of the memory (128 bytes)"));
+        (asm::v3::cfg::from_image(&image), Default::default())
+    } else {
+        let source = get_input_data(&args.input).unwrap_or_else(|err: io::Error| {
+            die(&format!("Failed to read input '{}'\n{}", args.input, err))
+        });
+        let assembly = mccasm::assemble(&source, &Options::default())
+            .unwrap_or_else(|err| die(&err.to_string()));
+        let leaders: Vec<u8> = assembly.symbols.values().copied().collect();
+        let graph = asm::v3::cfg::build(&assembly.image, &assembly.dp, &leaders);
+        (graph, assembly.symbols)
+    };
+    let dot = graph.to_dot(&labels);
+    match args.output {
+        Some(path) => fs::write(&path, dot).unwrap_or_else(|err| {
+            die(&format!("Failed to write '{}'\n\n {}", path, err));
+        }),
+        None => print!("{}", dot),
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Fmt(args)) => return fmt(args),
+        Some(Command::Cfg(args)) => return cfg(args),
+        None => {}
+    }
     stderrlog::new()
         .verbosity(LogLevelNum::from(cli.log_level))
         .module(module_path!())
@@ -73,36 +190,77 @@ fn main() {
         .init()
         .unwrap();
 
-    let output_file = Path::new(&cli.output);
+    // clap only allows a missing output together with a subcommand
+    let output = cli.output.unwrap_or_default();
+    let output_file = Path::new(&output);
 
     let input_data = get_input_data(&cli.input).unwrap_or_else(|err: io::Error| {
         die(&format!(
             "Failed to read input '{}'\n{}",
             cli.input,
             &err.to_string()
-        ));
-        String::new()
+        ))
     });
 
-    let out = match asm::assemble(input_data) {
-        Ok(out) => out,
-        Err(err) => {
-            die(&err.to_string());
-            return;
-        }
+    let mut options = Options::default();
+    options.optimize = cli.optimize;
+    for lint in cli.allow {
+        options.lints.insert(lint, Level::Allow);
+    }
+    for lint in cli.deny {
+        options.lints.insert(lint, Level::Deny);
+    }
+    let assembly = match mccasm::assemble(&input_data, &options) {
+        Ok(assembly) => assembly,
+        Err(err) => die(&err.to_string()),
     };
 
+    for warning in assembly.warnings.iter() {
+        eprintln!("WARNING: {}", warning);
+    }
+
+    if cli.optimize {
+        for change in assembly.optimizations.iter() {
+            println!("{}", change);
+        }
+        let saved: usize = assembly
+            .optimizations
+            .iter()
+            .filter(|change| change.skipped.is_none())
+            .map(|change| change.saved())
+            .sum();
+        println!("Saved {} nibbles", saved);
+    }
+
     let content = emit(
         cli.format,
-        output_file.extension().map(|ext| ext.to_str()).flatten(),
-        out,
+        output_file.extension().and_then(|ext| ext.to_str()),
+        assembly.image,
     );
 
-    fs::write(cli.output, content).unwrap_or_else(|err| {
+    fs::write(&output, content).unwrap_or_else(|err| {
         die(&format!("Failed to write output file\n\n {}", err));
     });
 
+    if let Some(symbols) = cli.symbols {
+        fs::write(symbols, emit_symbols(&assembly.symbols, &assembly.data)).unwrap_or_else(|err| {
+            die(&format!("Failed to write symbols file\n\n {}", err));
+        });
+    }
+
+    if let Some(path) = cli.lines {
+        fs::write(path, emit_lines(&assembly.lines)).unwrap_or_else(|err| {
+            die(&format!("Failed to write lines file\n\n {}", err));
+        });
+    }
+
+    if let Some(path) = cli.listing {
+        fs::write(path, emit_listing(&input_data, &assembly)).unwrap_or_else(|err| {
+            die(&format!("Failed to write listing file\n\n {}", err));
+        });
+    }
+
     if cli.memory_usage {
-        println!("Using {}/16 pages", count_nonzero_pages(&out));
+        println!("Using {}/16 pages", count_nonzero_pages(&assembly.image));
     }
 }
@@ -7,7 +7,7 @@ pub fn to_hex4(val: u4) -> String {
     str
 }
 
-pub fn parse_hex8<'a>(str: &'a str) -> Option<u8> {
+pub fn parse_hex8(str: &str) -> Option<u8> {
     let str = str.to_lowercase();
     if str.len() != 2 {
         return None;
@@ -16,15 +16,12 @@ pub fn parse_hex8<'a>(str: &'a str) -> Option<u8> {
     u8::from_str_radix(&str, 16).ok()
 }
 
-pub fn parse_hex4<'a>(str: &'a str) -> Option<u4> {
+pub fn parse_hex4(str: &str) -> Option<u4> {
     let str = str.to_lowercase();
     if str.len() != 1 {
         return None;
     }
-    for char in str.chars() {
-        return char.to_digit(16).map(|val| u4::from_u32(val));
-    }
-    return None;
+    str.chars().next()?.to_digit(16).map(u4::from_u32)
 }
 
 pub fn count_nonzero_pages(data: &[u4; 256]) -> usize {
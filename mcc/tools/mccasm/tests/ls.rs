@@ -0,0 +1,181 @@
+//! Drives the language server in-process through the Content-Length framing
+
+use std::io::Cursor;
+
+use mccasm::{
+    framing::{read_message, write_message},
+    ls,
+};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///add.asm";
+const SOURCE: &str = ".org 20
+&&start
+0x3
+
+.org 30
+start:
+psi
+psi
+mdp
+bogus
+";
+
+///Sends every request and returns the exit code and every message the server wrote
+fn exchange(messages: &[Value]) -> (i32, Vec<Value>) {
+    let mut input = Vec::new();
+    for message in messages {
+        write_message(&mut input, message).unwrap();
+    }
+    let mut output = Vec::new();
+    let code = ls::serve(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    let mut replies = Vec::new();
+    while let Some(message) = read_message(&mut output).unwrap() {
+        replies.push(message);
+    }
+    (code, replies)
+}
+
+fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn at(line: u64, character: u64) -> Value {
+    json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+}
+
+///Opens SOURCE, sends `requests` and shuts down
+fn session(requests: &[Value]) -> Vec<Value> {
+    let mut messages = vec![
+        request(1, "initialize", json!({ "capabilities": {} })),
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "mcc", "version": 1, "text": SOURCE } }),
+        ),
+    ];
+    messages.extend_from_slice(requests);
+    messages.push(request(99, "shutdown", Value::Null));
+    messages.push(notification("exit", Value::Null));
+    let (code, replies) = exchange(&messages);
+    assert_eq!(code, 0);
+    replies
+}
+
+fn result(replies: &[Value], id: u64) -> &Value {
+    let reply = replies
+        .iter()
+        .find(|reply| reply["id"] == id)
+        .unwrap_or_else(|| panic!("no reply to request {}", id));
+    &reply["result"]
+}
+
+#[test]
+fn initialize_and_diagnostics() {
+    let replies = session(&[]);
+    let capabilities = &result(&replies, 1)["capabilities"];
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(
+        capabilities["completionProvider"]["triggerCharacters"],
+        json!(["&", "."])
+    );
+
+    let diagnostics = replies
+        .iter()
+        .find(|reply| reply["method"] == "textDocument/publishDiagnostics")
+        .unwrap();
+    let diagnostics = diagnostics["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0]["range"],
+        json!({ "start": { "line": 9, "character": 0 }, "end": { "line": 9, "character": 5 } })
+    );
+}
+
+#[test]
+fn hover_and_definition() {
+    let replies = session(&[
+        request(2, "textDocument/hover", at(1, 4)),
+        request(3, "textDocument/hover", at(6, 1)),
+        request(4, "textDocument/definition", at(1, 4)),
+    ]);
+    let hover = result(&replies, 2);
+    assert_eq!(hover["contents"]["value"], "**start** = `0x30`");
+    assert_eq!(
+        hover["range"]["start"],
+        json!({ "line": 1, "character": 0 })
+    );
+    let psi = result(&replies, 3)["contents"]["value"].as_str().unwrap();
+    assert!(psi.starts_with("**psi**"), "{}", psi);
+    assert!(psi.ends_with("at `0x30`"), "{}", psi);
+    assert_eq!(
+        result(&replies, 4)["range"],
+        json!({ "start": { "line": 5, "character": 0 }, "end": { "line": 5, "character": 5 } })
+    );
+}
+
+#[test]
+fn completion() {
+    let replies = session(&[
+        request(2, "textDocument/completion", at(1, 2)),
+        request(3, "textDocument/completion", at(7, 0)),
+    ]);
+    let labels = result(&replies, 2).as_array().unwrap();
+    assert_eq!(labels.len(), 1);
+    assert_eq!(labels[0]["label"], "start");
+    assert_eq!(
+        labels[0]["textEdit"]["range"],
+        json!({ "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 2 } })
+    );
+    let words: Vec<_> = result(&replies, 3)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert!(words.contains(&"psi"));
+    assert!(words.contains(&".org"));
+    assert!(words.contains(&"&&start"));
+}
+
+#[test]
+fn unknown_methods_and_exit_without_shutdown() {
+    let (code, replies) = exchange(&[
+        request(1, "workspace/symbol", json!({})),
+        notification("exit", Value::Null),
+    ]);
+    assert_eq!(code, 1);
+    assert_eq!(replies[0]["error"]["code"], -32601);
+}
+
+#[test]
+fn positions_count_utf16_code_units() {
+    let text = ".org 30\n\"é😀\" &&start\nstart: # é😀 &\n";
+    let uri = json!({ "uri": URI });
+    let at = |line: u64, character: u64| json!({ "textDocument": uri, "position": { "line": line, "character": character } });
+    let (_, replies) = exchange(&[
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "mcc", "version": 1, "text": text } }),
+        ),
+        request(1, "textDocument/hover", at(1, 8)),
+        request(2, "textDocument/completion", at(2, 14)),
+        request(3, "textDocument/completion", at(2, 99)),
+    ]);
+    let hover = result(&replies, 1);
+    assert_eq!(hover["contents"]["value"], "**start** = `0x3e`");
+    assert_eq!(
+        hover["range"],
+        json!({ "start": { "line": 1, "character": 6 }, "end": { "line": 1, "character": 13 } })
+    );
+    for id in [2, 3] {
+        assert_eq!(
+            result(&replies, id)[0]["textEdit"]["range"],
+            json!({ "start": { "line": 2, "character": 14 }, "end": { "line": 2, "character": 14 } })
+        );
+    }
+}
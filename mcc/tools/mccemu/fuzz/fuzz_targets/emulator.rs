@@ -0,0 +1,9 @@
+//! `cargo fuzz run emulator` in mccemu, tests/fuzz.rs runs the same harness with a fixed seed
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mccemu::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::run(fuzz::image_from_bytes(data), fuzz::CYCLES);
+});
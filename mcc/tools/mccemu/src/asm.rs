@@ -0,0 +1,59 @@
+use std::fs;
+
+use libmcc::{u4, v3::from_bin_packed};
+use mccasm::{emiting::emit_bin_packed, Options};
+
+use crate::symbols::{LineMap, SymbolMap};
+
+///An image together with the debug info mccasm produced for it
+pub struct Assembled {
+    pub image: Vec<u8>,
+    pub symbols: SymbolMap,
+    pub lines: LineMap,
+}
+
+///Assembles a source file in-process
+pub fn assemble(path: &str) -> Result<Assembled, String> {
+    assemble_with(path, &Options::default())
+}
+
+///Like `assemble` with the options of the mccasm flags
+pub fn assemble_with(path: &str, options: &Options) -> Result<Assembled, String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let assembly = mccasm::assemble(&source, options).map_err(|err| err.to_string())?;
+
+    let mut labels: Vec<_> = assembly.symbols.iter().collect();
+    labels.sort();
+    let mut symbols = SymbolMap::default();
+    for (name, addr) in labels {
+        symbols.insert(*addr, name);
+    }
+    for addr in assembly.data.iter() {
+        symbols.mark_data(*addr);
+    }
+    Ok(Assembled {
+        image: emit_bin_packed(assembly.image),
+        symbols,
+        lines: LineMap::from(assembly.lines),
+    })
+}
+
+///The image of a source file or of a .bin with the symbol map at `symbols`
+pub fn load_image(path: &str, symbols: Option<&str>) -> Result<([u4; 256], SymbolMap), String> {
+    let (image, symbols) = if path.ends_with(".asm") {
+        let assembled = assemble(path)?;
+        (assembled.image, assembled.symbols)
+    } else {
+        let image = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        let symbols = match symbols {
+            Some(symbols) => {
+                SymbolMap::load(symbols).map_err(|err| format!("{}: {}", symbols, err))?
+            }
+            None => SymbolMap::default(),
+        };
+        (image, symbols)
+    };
+    let image = from_bin_packed(&image)
+        .ok_or_else(|| format!("{}: image was not the size of the memory (128 bytes)", path))?;
+    Ok((image, symbols))
+}
@@ -0,0 +1,98 @@
+use std::process;
+
+use clap::{Parser, ValueEnum};
+use mccemu::{
+    asm::load_image,
+    cli::{self, die},
+    equiv::{self, Failure, Method},
+    prop::Property,
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MethodArg {
+    Auto,
+    Exhaustive,
+    Symbolic,
+}
+
+#[derive(Parser)]
+#[command(author, version)]
+#[command(propagate_version = true)]
+#[command(about = "Checks that two programs produce the same outputs for every value of their input cells", long_about = None)]
+struct Cli {
+    ///Assembly source (.asm) or image (.bin)
+    a: String,
+
+    ///Assembly source (.asm) or image (.bin)
+    b: String,
+
+    ///Input cells as labels or addresses, every input is a nibble
+    #[arg(short = 'i', long, value_name = "CELLS", value_delimiter = ',')]
+    inputs: Vec<String>,
+
+    ///Where results are read from: stack (the top of the stack), labels or addresses
+    #[arg(short = 'o', long, value_name = "OUTPUTS", value_delimiter = ',')]
+    outputs: Vec<String>,
+
+    ///Inputs for which a program runs longer count as not halting
+    #[arg(short = 'c', long, default_value = "100000")]
+    max_cycles: u64,
+
+    ///Symbolic gives up after running this many instructions over all paths of a program
+    #[arg(long, default_value = "1048576")]
+    max_steps: usize,
+
+    ///Symbolic needs at most 4 inputs, auto falls back to exhaustive
+    #[arg(long, value_enum, default_value = "auto")]
+    method: MethodArg,
+}
+
+fn property(cli: &Cli, path: &str) -> Result<Property, String> {
+    let (image, symbols) = load_image(path, None)?;
+    let mut property = Property::new(image);
+    property.max_cycles = cli.max_cycles;
+    property.max_steps = cli.max_steps;
+    property.inputs =
+        cli::inputs(&symbols, &cli.inputs).map_err(|err| format!("{}: {}", path, err))?;
+    property.outputs =
+        cli::outputs(&symbols, &cli.outputs).map_err(|err| format!("{}: {}", path, err))?;
+    Ok(property)
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if cli.outputs.is_empty() {
+        die("no outputs to compare");
+    }
+    let (a, b) = property(&cli, &cli.a)
+        .and_then(|a| Ok((a, property(&cli, &cli.b)?)))
+        .unwrap_or_else(|err| die(&err));
+    let method = match cli.method {
+        MethodArg::Auto => Method::Auto,
+        MethodArg::Exhaustive => Method::Exhaustive,
+        MethodArg::Symbolic => Method::Symbolic,
+    };
+
+    match equiv::check(&a, &b, method) {
+        Ok(report) => {
+            println!(
+                "EQUIVALENT {} {} ({} inputs, {})",
+                cli.a, cli.b, report.cases, report.method
+            );
+            if report.unfinished > 0 {
+                println!(
+                    "    neither halted within {} cycles for {} inputs",
+                    cli.max_cycles, report.unfinished
+                );
+            }
+        }
+        Err(Failure::Different(counterexample)) => {
+            println!("DIFFERENT {} {}", cli.a, cli.b);
+            for line in counterexample.to_string().lines() {
+                println!("    {}", line);
+            }
+            process::exit(1);
+        }
+        Err(failure) => die(&failure.to_string()),
+    }
+}
@@ -0,0 +1,127 @@
+use std::process;
+
+use clap::{Parser, Subcommand};
+use libmcc::v3::{format_sequence, parse_sequence};
+use mccemu::{
+    cli::die,
+    superopt::{self, Effect, Search, MAX_LEN},
+};
+
+#[derive(Parser)]
+#[command(author, version)]
+#[command(propagate_version = true)]
+#[command(about = "Searches for the shortest v3 instruction sequences", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    ///Find the shortest sequences with the effect of a reference sequence or a stack effect
+    Super {
+        ///Sequence to shorten like 'swp swp'
+        #[arg(short, long, value_name = "SEQUENCE", conflicts_with = "effects")]
+        reference: Option<String>,
+
+        ///Stack effect like 'a b -- b a' or a test vector like '1 2 -- 3', dp and memory stay the same
+        #[arg(short, long = "effect", value_name = "EFFECT")]
+        effects: Vec<String>,
+
+        ///Longest sequence to try
+        #[arg(short, long, default_value = "4")]
+        max_len: usize,
+
+        ///Random start states (or input values for an effect) every sequence is run on
+        #[arg(short, long, default_value = "256")]
+        tests: usize,
+
+        ///Print at most this many sequences
+        #[arg(short, long, default_value = "16")]
+        limit: usize,
+
+        #[arg(long, default_value = "1")]
+        seed: u64,
+    },
+    ///List every short sequence that can be replaced by a shorter one
+    Peephole {
+        ///Longest sequence to shorten
+        #[arg(short, long, default_value = "3")]
+        max_len: usize,
+
+        ///Random start states every sequence is run on
+        #[arg(short, long, default_value = "256")]
+        tests: usize,
+
+        #[arg(long, default_value = "1")]
+        seed: u64,
+    },
+}
+
+fn search(
+    reference: Option<&str>,
+    effects: &[String],
+    tests: usize,
+    seed: u64,
+) -> Result<Search, String> {
+    if let Some(reference) = reference {
+        let code = parse_sequence(reference)?;
+        return Search::reference(&code, tests, seed);
+    }
+    if effects.is_empty() {
+        return Err("expected a --reference sequence or an --effect".into());
+    }
+    let effects = effects
+        .iter()
+        .map(|text| Effect::parse(text).map_err(|err| format!("'{}': {}", text, err)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Search::effects(&effects, tests, seed)
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Super {
+            reference,
+            effects,
+            max_len,
+            tests,
+            limit,
+            seed,
+        } => {
+            if max_len > MAX_LEN {
+                die(&format!("--max-len is at most {}", MAX_LEN));
+            }
+            let search =
+                search(reference.as_deref(), &effects, tests, seed).unwrap_or_else(|err| die(&err));
+            let found = search.shortest(max_len, limit);
+            let Some(first) = found.first() else {
+                println!("no sequence of up to {} instructions", max_len);
+                process::exit(1);
+            };
+            println!(
+                "{} instructions ({} nibbles, {} cycles):",
+                first.len(),
+                first.len(),
+                first.len()
+            );
+            for code in found.iter() {
+                println!("    {}", format_sequence(code));
+            }
+        }
+        Command::Peephole {
+            max_len,
+            tests,
+            seed,
+        } => {
+            if max_len > MAX_LEN {
+                die(&format!("--max-len is at most {}", MAX_LEN));
+            }
+            let rewrites = superopt::peephole(max_len, tests, seed);
+            for rewrite in rewrites.iter() {
+                println!("{}", rewrite);
+            }
+            println!("{} rewrites", rewrites.len());
+        }
+    }
+}
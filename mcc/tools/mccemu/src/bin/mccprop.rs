@@ -0,0 +1,97 @@
+use std::process;
+
+use clap::Parser;
+use mccemu::{
+    asm::load_image,
+    cli::{self, die},
+    expr::Expr,
+    prop::Property,
+};
+
+#[derive(Parser)]
+#[command(author, version)]
+#[command(propagate_version = true)]
+#[command(about = "Checks an assembly routine against an expression for every value of its input cells", long_about = None)]
+struct Cli {
+    ///Assembly source (.asm) or image (.bin)
+    input: String,
+
+    ///Symbol map written by mccasm --symbols (for a .bin)
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<String>,
+
+    ///Input cell as a label or NAME=ADDR, every input is a nibble
+    #[arg(short = 'i', long = "input", value_name = "CELL")]
+    inputs: Vec<String>,
+
+    ///Where a result is read from: stack (the top of the stack), a label or an address
+    #[arg(short = 'o', long = "output", value_name = "OUTPUT")]
+    outputs: Vec<String>,
+
+    ///Expected value of every output like 'a * b' (the inputs are names, results are cut to a nibble)
+    #[arg(short = 'e', long = "expect", value_name = "EXPR")]
+    expects: Vec<String>,
+
+    ///Stop when ip reaches this label or address instead of at the end of memory
+    #[arg(long, value_name = "ADDR")]
+    halt_at: Option<String>,
+
+    ///Fail when the vm runs for more than this many cycles
+    #[arg(short = 'c', long, default_value = "100000")]
+    max_cycles: u64,
+
+    ///Sample inputs randomly when there are more combinations than this
+    #[arg(long, default_value = "65536")]
+    max_cases: usize,
+
+    ///Seed for sampled inputs
+    #[arg(long, default_value = "1")]
+    seed: u64,
+}
+
+fn property(cli: &Cli) -> Result<(Property, Vec<Expr>), String> {
+    let (image, symbols) = load_image(&cli.input, cli.symbols.as_deref())?;
+    let mut property = Property::new(image);
+    property.max_cycles = cli.max_cycles;
+    property.max_cases = cli.max_cases;
+    property.seed = cli.seed;
+    property.halt_at = cli
+        .halt_at
+        .as_deref()
+        .map(|addr| symbols.resolve(addr))
+        .transpose()?;
+    property.inputs = cli::inputs(&symbols, &cli.inputs)?;
+    property.outputs = cli::outputs(&symbols, &cli.outputs)?;
+    let expects = cli::expects(&cli.expects, property.outputs.len())?;
+    Ok((property, expects))
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let (property, expects) = property(&cli).unwrap_or_else(|err| die(&err));
+
+    let names: Vec<_> = property
+        .inputs
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect();
+    let result = property
+        .check(|values| cli::expected(&expects, &names, values).unwrap_or_else(|err| die(&err)));
+    match result {
+        Ok(report) => {
+            let kind = if report.exhaustive {
+                "exhaustive"
+            } else {
+                "sampled"
+            };
+            println!("PASS {} ({} cases, {})", cli.input, report.cases, kind);
+        }
+        Err(failure) => {
+            println!("FAIL {}", cli.input);
+            for line in failure.to_string().lines() {
+                println!("    {}", line);
+            }
+            process::exit(1);
+        }
+    }
+}
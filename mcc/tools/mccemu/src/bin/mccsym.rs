@@ -0,0 +1,168 @@
+use std::process;
+
+use clap::Parser;
+use libmcc::{u4, v3::Instruction};
+use mccemu::{
+    asm::load_image,
+    cli::{self, die},
+    expr::Expr,
+    prop::Output,
+    symbolic::{Disproof, Domain, Engine, MAX_INPUTS},
+};
+
+#[derive(Parser)]
+#[command(author, version)]
+#[command(propagate_version = true)]
+#[command(about = "Runs an assembly routine with symbolic input cells and reports every path through it", long_about = None)]
+struct Cli {
+    ///Assembly source (.asm) or image (.bin)
+    input: String,
+
+    ///Symbol map written by mccasm --symbols (for a .bin)
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<String>,
+
+    ///Symbolic input cell as a label or NAME=ADDR, every input is a nibble
+    #[arg(short = 'i', long = "input", value_name = "CELL")]
+    inputs: Vec<String>,
+
+    ///Output to prove: stack (the top of the stack), a label or an address
+    #[arg(short = 'o', long = "output", value_name = "OUTPUT")]
+    outputs: Vec<String>,
+
+    ///Value of every output for all inputs like 'a * b' (results are cut to a nibble)
+    #[arg(short = 'e', long = "expect", value_name = "EXPR")]
+    expects: Vec<String>,
+
+    ///Paths running longer than this many cycles are reported as unfinished
+    #[arg(short = 'c', long, default_value = "10000")]
+    max_cycles: u64,
+
+    ///Give up after running this many instructions over all paths
+    #[arg(long, default_value = "1048576")]
+    max_steps: usize,
+}
+
+fn engine(cli: &Cli) -> Result<(Engine, Vec<Output>, Vec<Expr>), String> {
+    let (image, symbols) = load_image(&cli.input, cli.symbols.as_deref())?;
+    let mut engine = Engine::new(image);
+    engine.max_cycles = cli.max_cycles;
+    engine.max_steps = cli.max_steps;
+    engine.inputs = cli::inputs(&symbols, &cli.inputs)?;
+    if engine.inputs.len() > MAX_INPUTS {
+        return Err(format!("at most {} inputs can be symbolic", MAX_INPUTS));
+    }
+    let outputs = cli::outputs(&symbols, &cli.outputs)?;
+    let expects = cli::expects(&cli.expects, outputs.len())?;
+    Ok((engine, outputs, expects))
+}
+
+///Like `a=0x3 b=*`
+fn describe_domain(names: &[Box<str>], domain: &Domain) -> String {
+    let parts: Vec<_> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let values = domain.values(i);
+            let values = match values.len() {
+                16 => "*".to_string(),
+                1 => format!("{:#03x}", values.first().unwrap()),
+                _ => {
+                    let values: Vec<_> = values.iter().map(|v| format!("{:#03x}", v)).collect();
+                    format!("{{{}}}", values.join(","))
+                }
+            };
+            format!("{}={}", name, values)
+        })
+        .collect();
+    parts.join(" ")
+}
+
+fn describe_inputs(names: &[Box<str>], inputs: &[u4]) -> String {
+    let parts: Vec<_> = names
+        .iter()
+        .zip(inputs)
+        .map(|(name, value)| format!("{}={:#03x}", name, value))
+        .collect();
+    parts.join(" ")
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let (engine, outputs, expects) = engine(&cli).unwrap_or_else(|err| die(&err));
+    let names = engine.names();
+    let exploration = engine.explore();
+
+    println!("halt states: {}", exploration.halts.len());
+    for halt in exploration.halts.iter() {
+        println!(
+            "    {}  cycles={} sp={:#03x} stack_top={}",
+            describe_domain(&names, &halt.domain),
+            halt.cycles,
+            halt.stack.len(),
+            halt.stack_top().render(&names)
+        );
+    }
+    println!("jumps:");
+    for (addr, targets) in exploration.jumps.iter() {
+        let inst = Instruction::from_u4(engine.image[*addr as usize]);
+        let targets: Vec<_> = targets.iter().map(|t| format!("{:#04x}", t)).collect();
+        println!(
+            "    {:#04x} {} -> {}",
+            addr,
+            inst.as_str(),
+            targets.join(" ")
+        );
+    }
+    for ((kind, addr), domain) in exploration.issues.iter() {
+        println!(
+            "{} at {:#04x} for {} inputs like {}",
+            kind,
+            addr,
+            domain.len(),
+            describe_inputs(&names, &domain.example().unwrap_or_default())
+        );
+    }
+    if !exploration.unfinished.is_empty() {
+        println!(
+            "unfinished after {} cycles: {} inputs like {}",
+            engine.max_cycles,
+            exploration.unfinished.len(),
+            describe_inputs(&names, &exploration.unfinished.example().unwrap())
+        );
+    }
+    if exploration.incomplete {
+        println!("incomplete: gave up after {} steps", engine.max_steps);
+    }
+
+    if outputs.is_empty() {
+        return;
+    }
+    let result = exploration.prove(&outputs, |values| {
+        cli::expected(&expects, &names, values).unwrap_or_else(|err| die(&err))
+    });
+    match result {
+        Ok(cases) => println!("PROVEN {} ({} inputs)", cli.input, cases),
+        Err(disproof) => {
+            println!("FAIL {}", cli.input);
+            match disproof {
+                Disproof::Mismatch {
+                    inputs,
+                    expected,
+                    actual,
+                } => {
+                    println!("    inputs: {}", describe_inputs(&names, &inputs));
+                    for ((output, expected), actual) in outputs.iter().zip(expected).zip(actual) {
+                        let marker = if expected == actual { "" } else { " <-" };
+                        println!(
+                            "    {}: expected {:#03x} got {:#03x}{}",
+                            output, expected, actual, marker
+                        );
+                    }
+                }
+                disproof => println!("    {}", disproof),
+            }
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use std::{fs, io, process, rc::Rc};
+
+use clap::Parser;
+use libmcc::{u4, v3::from_bin_packed};
+use mccemu::{
+    emulator::{self, Emulator},
+    ext::{
+        chardev::{CharDev, EofMode, InputMode, SharedBuf},
+        ExtManager, Extension,
+    },
+};
+
+#[derive(Parser)]
+#[command(author, version)]
+#[command(propagate_version = true)]
+#[command(about = "Runs mcc programs and checks the expectations written in their source", long_about = None)]
+struct Cli {
+    ///Assembly sources (.asm) or images (.bin) to test
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    ///Fail a program when it runs for more than this many cycles
+    #[arg(short = 'c', long, default_value = "100000")]
+    max_cycles: u64,
+
+    ///Expectation to check for every input (same syntax as a '; expect' comment)
+    #[arg(short = 'e', long)]
+    expect: Vec<String>,
+
+    ///Assemble sources with the peephole rewrites of mccasm -O
+    #[arg(short = 'O', long)]
+    optimize: bool,
+}
+
+enum Check {
+    StackTop(u4),
+    Sp(u4),
+    Ip(u8),
+    Dp(u8),
+    Mem(u8, u4),
+    Stdout(String),
+}
+
+#[derive(Default)]
+struct TestCase {
+    checks: Vec<Check>,
+    stdin: Option<String>,
+}
+impl TestCase {
+    fn uses_chardev(&self) -> bool {
+        self.stdin.is_some()
+            || self
+                .checks
+                .iter()
+                .any(|check| matches!(check, Check::Stdout(_)))
+    }
+}
+
+fn parse_num(str: &str) -> Option<u32> {
+    if let Some(hex) = str.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        str.parse().ok()
+    }
+}
+fn parse_u4(str: &str) -> Result<u4, String> {
+    match parse_num(str) {
+        Some(val) if val <= 0xF => Ok(u4::from_u32(val)),
+        _ => Err(format!("'{}' is not a nibble", str)),
+    }
+}
+fn parse_u8(str: &str) -> Result<u8, String> {
+    match parse_num(str) {
+        Some(val) if val <= 0xFF => Ok(val as u8),
+        _ => Err(format!("'{}' is not an address", str)),
+    }
+}
+
+///Splits `key=value key="some value"` into pairs
+fn parse_pairs(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|char| char.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(pairs);
+        }
+
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some('=') => break,
+                Some(char) if !char.is_whitespace() => key.push(char),
+                _ => return Err(format!("expected '=' after '{}'", key)),
+            }
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('0') => value.push('\0'),
+                        Some(char) => value.push(char),
+                        None => return Err("unterminated string".into()),
+                    },
+                    Some(char) => value.push(char),
+                    None => return Err("unterminated string".into()),
+                }
+            }
+        } else {
+            while let Some(char) = chars.next_if(|char| !char.is_whitespace()) {
+                value.push(char);
+            }
+        }
+        pairs.push((key, value));
+    }
+}
+
+fn parse_check(key: &str, value: String) -> Result<Check, String> {
+    Ok(match key {
+        "stack_top" => Check::StackTop(parse_u4(&value)?),
+        "sp" => Check::Sp(parse_u4(&value)?),
+        "ip" => Check::Ip(parse_u8(&value)?),
+        "dp" => Check::Dp(parse_u8(&value)?),
+        "stdout" => Check::Stdout(value),
+        _ => {
+            let addr = key
+                .strip_prefix("mem[")
+                .and_then(|key| key.strip_suffix(']'))
+                .ok_or_else(|| format!("unknown expectation '{}'", key))?;
+            Check::Mem(parse_u8(addr)?, parse_u4(&value)?)
+        }
+    })
+}
+
+fn parse_expect(test: &mut TestCase, text: &str) -> Result<(), String> {
+    for (key, value) in parse_pairs(text)? {
+        test.checks.push(parse_check(&key, value)?);
+    }
+    Ok(())
+}
+
+fn parse_setup(test: &mut TestCase, text: &str) -> Result<(), String> {
+    for (key, value) in parse_pairs(text)? {
+        match key.as_str() {
+            "stdin" => test.stdin = Some(value),
+            _ => return Err(format!("unknown setup '{}'", key)),
+        }
+    }
+    Ok(())
+}
+
+///Collects the `; expect` and `; setup` comments of a source file
+fn parse_source(source: &str) -> Result<TestCase, String> {
+    let mut test = TestCase::default();
+    for (linenum, line) in source.lines().enumerate() {
+        let Some(pos) = line.find(['#', ';']) else {
+            continue;
+        };
+        let comment = line[pos + 1..].trim();
+        let result = if let Some(text) = comment.strip_prefix("expect ") {
+            parse_expect(&mut test, text)
+        } else if let Some(text) = comment.strip_prefix("setup ") {
+            parse_setup(&mut test, text)
+        } else {
+            Ok(())
+        };
+        result.map_err(|err| format!("line {}: {}", linenum + 1, err))?;
+    }
+    Ok(test)
+}
+
+struct Outcome {
+    cycles: u64,
+    failures: Vec<String>,
+}
+
+fn run(cli: &Cli, test: &TestCase, image: [u4; 256]) -> Outcome {
+    let stdout = SharedBuf::default();
+    let mut extensions: Vec<Box<dyn Extension>> = Vec::new();
+    if test.uses_chardev() {
+        let stdin = test.stdin.clone().unwrap_or_default();
+        extensions.push(Box::new(CharDev::from_reader(
+            Box::new(io::Cursor::new(stdin.into_bytes())),
+            Box::new(stdout.clone()),
+            EofMode::Halt,
+            InputMode::Raw,
+        )));
+    }
+    let extmgr = Rc::new(ExtManager::from_extensions(extensions));
+    let mut emulator = mccemu::new_emulator(image, extmgr.clone());
+
+    emulator.start();
+    while emulator.is_running && emulator.cycles < cli.max_cycles {
+        emulator.tick();
+        if extmgr.is_halted() {
+            emulator.stop();
+        }
+    }
+
+    let mut failures = Vec::new();
+    if let Some(err) = extmgr.take_error() {
+        failures.push(err.to_string());
+    }
+    if emulator.is_running {
+        failures.push(format!("did not halt within {} cycles", cli.max_cycles));
+    }
+    for check in test.checks.iter() {
+        if let Some(failure) = check_state(check, &emulator, &stdout) {
+            failures.push(failure);
+        }
+    }
+    Outcome {
+        cycles: emulator.cycles,
+        failures,
+    }
+}
+
+fn check_state(check: &Check, emulator: &Emulator, stdout: &SharedBuf) -> Option<String> {
+    fn compare(name: &str, expected: String, got: String) -> Option<String> {
+        if expected == got {
+            None
+        } else {
+            Some(format!("{}: expected {} got {}", name, expected, got))
+        }
+    }
+    fn nib(val: u4) -> String {
+        format!("{:#03x}", val)
+    }
+    fn byte(val: u8) -> String {
+        format!("{:#04x}", val)
+    }
+    match check {
+        Check::StackTop(val) => compare("stack_top", nib(*val), nib(emulator.stack_peek())),
+        Check::Sp(val) => compare("sp", nib(*val), nib(emulator.sp())),
+        Check::Ip(val) => compare(
+            "ip",
+            byte(*val),
+            byte(emulator.ghost_read_mem8(emulator::IP_ADDR0)),
+        ),
+        Check::Dp(val) => compare(
+            "dp",
+            byte(*val),
+            byte(emulator.ghost_read_mem8(emulator::DP_ADDR0)),
+        ),
+        Check::Mem(addr, val) => compare(
+            &format!("mem[{:#04x}]", addr),
+            nib(*val),
+            nib(emulator.ghost_read_mem(*addr)),
+        ),
+        Check::Stdout(val) => compare(
+            "stdout",
+            format!("{:?}", val),
+            format!("{:?}", String::from_utf8_lossy(&stdout.0.borrow())),
+        ),
+    }
+}
+
+fn test_input(cli: &Cli, path: &str) -> Result<Outcome, String> {
+    let mut test = TestCase::default();
+    let image = if path.ends_with(".asm") {
+        let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        test = parse_source(&source)?;
+        let mut options = mccasm::Options::default();
+        options.optimize = cli.optimize;
+        mccemu::asm::assemble_with(path, &options)?.image
+    } else {
+        fs::read(path).map_err(|err| err.to_string())?
+    };
+    for text in cli.expect.iter() {
+        parse_expect(&mut test, text)?;
+    }
+    let image =
+        from_bin_packed(&image).ok_or("image was not the size of the memory (128 bytes)")?;
+    Ok(run(cli, &test, image))
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let mut passed = 0;
+    let mut failed = 0;
+    for path in cli.inputs.iter() {
+        match test_input(&cli, path) {
+            Ok(outcome) if outcome.failures.is_empty() => {
+                passed += 1;
+                println!("PASS {} ({} cycles)", path, outcome.cycles);
+            }
+            Ok(outcome) => {
+                failed += 1;
+                println!("FAIL {} ({} cycles)", path, outcome.cycles);
+                for failure in outcome.failures {
+                    println!("    {}", failure);
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                println!("FAIL {}", path);
+                println!("    {}", err);
+            }
+        }
+    }
+    println!();
+    println!("{} passed; {} failed", passed, failed);
+    if failed != 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,76 @@
+use std::{fs, process};
+
+use clap::{Parser, Subcommand};
+use mccemu::{cli::die, trace::parse_fields};
+
+#[derive(Parser)]
+#[command(author, version)]
+#[command(propagate_version = true)]
+#[command(about = "Tools for traces recorded with mccemu --trace", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    ///Reports the first cycle where two traces diverge
+    Diff { a: String, b: String },
+}
+
+fn read_trace(path: &str) -> String {
+    fs::read_to_string(path)
+        .unwrap_or_else(|err| die(&format!("Failed to read trace '{}'\n{}", path, err)))
+}
+
+///Returns true when the traces are the same
+fn diff(a_path: &str, b_path: &str) -> bool {
+    let a = read_trace(a_path);
+    let b = read_trace(b_path);
+    let mut a_lines = a.lines();
+    let mut b_lines = b.lines();
+    let mut last = None;
+    loop {
+        match (a_lines.next(), b_lines.next()) {
+            (None, None) => return true,
+            (Some(a_line), Some(b_line)) if a_line == b_line => last = Some(a_line),
+            (a_line, b_line) => {
+                println!("traces diverge");
+                if let Some(last) = last {
+                    println!("  last common: {}", last);
+                }
+                println!("  {}: {}", a_path, a_line.unwrap_or("<end of trace>"));
+                println!("  {}: {}", b_path, b_line.unwrap_or("<end of trace>"));
+                if let (Some(a_line), Some(b_line)) = (a_line, b_line) {
+                    let b_fields = parse_fields(b_line);
+                    for (key, a_value) in parse_fields(a_line) {
+                        let b_value = b_fields
+                            .iter()
+                            .find(|(b_key, _)| *b_key == key)
+                            .map(|(_, value)| *value);
+                        if b_value != Some(a_value) {
+                            println!(
+                                "  {}: {} != {}",
+                                key,
+                                a_value,
+                                b_value.unwrap_or("<missing>")
+                            );
+                        }
+                    }
+                }
+                return false;
+            }
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Diff { a, b } => {
+            if !diff(&a, &b) {
+                process::exit(1);
+            }
+        }
+    }
+}
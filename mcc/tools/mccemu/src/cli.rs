@@ -0,0 +1,59 @@
+//! Argument handling shared by the command line tools
+
+use std::{collections::HashMap, process};
+
+use libmcc::u4;
+
+use crate::{expr::Expr, prop::Output, symbols::SymbolMap};
+
+pub fn die(message: &str) -> ! {
+    eprintln!("FATAL: {}", message);
+    process::exit(-1);
+}
+
+///Input cells given as a label or NAME=ADDR, a label names itself
+pub fn inputs(symbols: &SymbolMap, args: &[String]) -> Result<Vec<(Box<str>, u8)>, String> {
+    args.iter()
+        .map(|input| {
+            let (name, addr) = input.split_once('=').unwrap_or((input, input));
+            Ok((name.into(), symbols.resolve(addr)?))
+        })
+        .collect()
+}
+
+///Outputs given as stack (the top of the stack), a label or an address
+pub fn outputs(symbols: &SymbolMap, args: &[String]) -> Result<Vec<Output>, String> {
+    args.iter()
+        .map(|output| match output.as_str() {
+            "stack" => Ok(Output::StackTop),
+            addr => symbols.resolve(addr).map(Output::Cell),
+        })
+        .collect()
+}
+
+///One expression for every output
+pub fn expects(args: &[String], outputs: usize) -> Result<Vec<Expr>, String> {
+    if args.len() != outputs {
+        return Err(format!(
+            "got {} outputs but {} expectations",
+            outputs,
+            args.len()
+        ));
+    }
+    args.iter()
+        .map(|text| Expr::parse(text).map_err(|err| format!("'{}': {}", text, err)))
+        .collect()
+}
+
+///Evaluates the expressions with the inputs named `names` set to `values`, results are cut to a nibble
+pub fn expected(expects: &[Expr], names: &[Box<str>], values: &[u4]) -> Result<Vec<u4>, String> {
+    let vars: HashMap<&str, u64> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| (&**name, value.into_low() as u64))
+        .collect();
+    expects
+        .iter()
+        .map(|expr| Ok(u4::from_low(expr.eval(&vars)? as u8)))
+        .collect()
+}
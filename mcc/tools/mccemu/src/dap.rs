@@ -0,0 +1,519 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use libmcc::v3::from_bin_packed;
+use mccasm::framing::{read_message, write_message};
+use serde_json::{json, Value};
+
+use crate::{
+    debugger::DEFAULT_HISTORY,
+    ext::{
+        chardev::{CharDev, EofMode, InputMode, SharedBuf},
+        ExtManager,
+    },
+    session::Session,
+    symbols::{LineMap, SymbolMap},
+};
+
+const THREAD_ID: u64 = 1;
+const REGISTERS_REF: u64 = 1;
+const STACK_REF: u64 = 2;
+const LABELS_REF: u64 = 3;
+///Check for a pause request every this many cycles while running
+const PAUSE_POLL_CYCLES: u64 = 1024;
+
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - i * 8)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[(bits >> (18 - i * 6) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn parse_addr(str: &str) -> Option<u8> {
+    u8::from_str_radix(str.trim_start_matches("0x"), 16).ok()
+}
+
+///A launched program with its debug info
+struct Program {
+    session: Session,
+    symbols: SymbolMap,
+    lines: LineMap,
+    source: Option<String>,
+    output: SharedBuf,
+    output_sent: usize,
+}
+
+impl Program {
+    fn launch(args: &Value) -> Result<Self, String> {
+        let arg = |name: &str| args.get(name).and_then(|value| value.as_str());
+        let path = arg("program").ok_or("missing 'program'")?;
+
+        let (image, symbols, lines, source) = if path.ends_with(".asm") {
+            let assembled = crate::asm::assemble(path)?;
+            let source = Some(path.to_string());
+            (assembled.image, assembled.symbols, assembled.lines, source)
+        } else {
+            let image = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+            let symbols = match arg("symbols") {
+                Some(path) => SymbolMap::load(path).map_err(|err| format!("{}: {}", path, err))?,
+                None => SymbolMap::default(),
+            };
+            let lines = match arg("lines") {
+                Some(path) => LineMap::load(path).map_err(|err| format!("{}: {}", path, err))?,
+                None => LineMap::default(),
+            };
+            (image, symbols, lines, arg("source").map(str::to_string))
+        };
+        let Some(image) = from_bin_packed(&image) else {
+            return Err("image was not the size of the memory (128 bytes)".into());
+        };
+
+        let output = SharedBuf::default();
+        let stdin = arg("stdin").unwrap_or_default().as_bytes().to_vec();
+        let extmgr = Rc::new(ExtManager::from_extensions(vec![Box::new(
+            CharDev::from_reader(
+                Box::new(io::Cursor::new(stdin)),
+                Box::new(output.clone()),
+                EofMode::Halt,
+                InputMode::Raw,
+            ),
+        )]));
+        let mut emulator = crate::new_emulator(image, extmgr.clone());
+        emulator.start();
+        let mut session = Session::new(emulator, extmgr);
+        let history = args.get("history").and_then(|value| value.as_u64());
+        session.set_history(history.map_or(DEFAULT_HISTORY, |history| history as usize));
+
+        Ok(Self {
+            session,
+            symbols,
+            lines,
+            source,
+            output,
+            output_sent: 0,
+        })
+    }
+
+    fn source_json(&self) -> Option<Value> {
+        let path = self.source.as_ref()?;
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path);
+        Some(json!({ "name": name, "path": path }))
+    }
+
+    fn describe(&self, addr: u8) -> String {
+        match self.symbols.describe(addr) {
+            Some(label) => format!("{:#04x} ({})", addr, label),
+            None => format!("{:#04x}", addr),
+        }
+    }
+
+    fn variables(&self, reference: u64) -> Option<Vec<Value>> {
+        let emulator = &self.session.emulator;
+        let variable = |name: String, value: String, addr: Option<u8>| {
+            let mut variable = json!({ "name": name, "value": value, "variablesReference": 0 });
+            if let Some(addr) = addr {
+                variable["memoryReference"] = json!(format!("{:#04x}", addr));
+            }
+            variable
+        };
+        Some(match reference {
+            REGISTERS_REF => vec![
+                variable(
+                    "ip".into(),
+                    self.describe(emulator.ip()),
+                    Some(emulator.ip()),
+                ),
+                variable(
+                    "dp".into(),
+                    self.describe(emulator.dp()),
+                    Some(emulator.dp()),
+                ),
+                variable("sp".into(), format!("{:#03x}", emulator.sp()), None),
+                variable("cycles".into(), emulator.cycles.to_string(), None),
+            ],
+            STACK_REF => emulator
+                .stack()
+                .iter()
+                .enumerate()
+                .map(|(i, nib)| variable(i.to_string(), format!("{:#03x}", nib), None))
+                .collect(),
+            LABELS_REF => self
+                .symbols
+                .iter()
+                .map(|(addr, name)| {
+                    let value = format!("{:#03x} @ {:#04x}", emulator.ghost_read_mem(addr), addr);
+                    variable(name.into(), value, Some(addr))
+                })
+                .collect(),
+            _ => return None,
+        })
+    }
+
+    ///Chardev output that was not sent to the client yet
+    fn take_output(&mut self) -> Option<String> {
+        let output = self.output.0.borrow();
+        if output.len() == self.output_sent {
+            return None;
+        }
+        let new = String::from_utf8_lossy(&output[self.output_sent..]).into_owned();
+        self.output_sent = output.len();
+        Some(new)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Continue,
+    ///Run until ip is on a different source line
+    Line,
+}
+
+///Debug adapter talking to one client
+pub struct DapServer<W: Write> {
+    output: W,
+    seq: u64,
+    incoming: Receiver<Value>,
+    ///Requests received while the program was running
+    queue: VecDeque<Value>,
+    program: Option<Program>,
+    stop_on_entry: bool,
+}
+
+type Response = Result<Value, String>;
+
+impl<W: Write> DapServer<W> {
+    pub fn new(incoming: Receiver<Value>, output: W) -> Self {
+        Self {
+            output,
+            seq: 0,
+            incoming,
+            queue: VecDeque::new(),
+            program: None,
+            stop_on_entry: false,
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn respond(&mut self, request: &Value, response: Response) -> io::Result<()> {
+        let mut message = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": response.is_ok(),
+        });
+        match response {
+            Ok(body) => message["body"] = body,
+            Err(err) => message["message"] = json!(err),
+        }
+        self.send(message)
+    }
+
+    fn program(&mut self) -> Result<&mut Program, String> {
+        self.program
+            .as_mut()
+            .ok_or_else(|| "no program launched".into())
+    }
+
+    ///Runs until the next request is handled, returns false when the session ended
+    pub fn handle_next(&mut self) -> io::Result<bool> {
+        let request = match self.queue.pop_front() {
+            Some(request) => request,
+            None => match self.incoming.recv() {
+                Ok(request) => request,
+                Err(_) => return Ok(false),
+            },
+        };
+        self.handle(request)
+    }
+
+    fn handle(&mut self, request: Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default().to_string();
+        let args = request.get("arguments").cloned().unwrap_or(Value::Null);
+        match command.as_str() {
+            "initialize" => {
+                let body = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsStepBack": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsTerminateRequest": true,
+                });
+                self.respond(&request, Ok(body))?;
+            }
+            "launch" => {
+                let response = Program::launch(&args).map(|program| {
+                    self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                    self.program = Some(program);
+                    json!({})
+                });
+                let launched = response.is_ok();
+                self.respond(&request, response)?;
+                if launched {
+                    self.send_event("initialized", json!({}))?;
+                }
+            }
+            "configurationDone" => {
+                self.respond(&request, Ok(json!({})))?;
+                if self.stop_on_entry {
+                    self.send_event(
+                        "stopped",
+                        json!({ "reason": "entry", "threadId": THREAD_ID }),
+                    )?;
+                } else {
+                    self.execute(RunMode::Continue)?;
+                }
+            }
+            "stepOut" => {
+                // v3 has no call instruction so there is no frame to return to
+                self.respond(&request, Err("mcc has no calls to step out of".into()))?;
+            }
+            "continue" | "next" | "stepIn" => {
+                let response = self
+                    .program()
+                    .map(|_| json!({ "allThreadsContinued": true }));
+                let ok = response.is_ok();
+                self.respond(&request, response)?;
+                if ok {
+                    let mode = match command.as_str() {
+                        "continue" => RunMode::Continue,
+                        _ => RunMode::Line,
+                    };
+                    self.execute(mode)?;
+                }
+            }
+            "stepBack" | "reverseContinue" => {
+                let response = self.program().map(|program| {
+                    if command == "stepBack" {
+                        program.session.back(1);
+                    } else {
+                        program.session.reverse_continue();
+                    }
+                    json!({})
+                });
+                let ok = response.is_ok();
+                self.respond(&request, response)?;
+                if ok {
+                    self.send_event(
+                        "stopped",
+                        json!({ "reason": "step", "threadId": THREAD_ID }),
+                    )?;
+                }
+            }
+            "pause" => {
+                self.respond(&request, Ok(json!({})))?;
+                self.send_event(
+                    "stopped",
+                    json!({ "reason": "pause", "threadId": THREAD_ID }),
+                )?;
+            }
+            "disconnect" | "terminate" => {
+                self.respond(&request, Ok(json!({})))?;
+                if command == "terminate" {
+                    self.send_event("terminated", json!({}))?;
+                }
+                return Ok(false);
+            }
+            _ => {
+                let response = self.handle_query(&command, &args);
+                self.respond(&request, response)?;
+            }
+        }
+        Ok(true)
+    }
+
+    ///Requests that don't run the program
+    fn handle_query(&mut self, command: &str, args: &Value) -> Response {
+        match command {
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "mcc" }] })),
+            "setBreakpoints" => {
+                let program = self.program()?;
+                let mut breakpoints = Vec::new();
+                program.session.debugger.breakpoints.clear();
+                let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+                for (id, breakpoint) in requested.iter().enumerate() {
+                    let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+                    let code_line = program.lines.next_code_line(line);
+                    let addrs = code_line
+                        .map(|line| program.lines.addrs_of(line))
+                        .unwrap_or_default();
+                    program.session.debugger.breakpoints.extend(addrs.iter());
+                    let mut breakpoint = json!({
+                        "id": id + 1,
+                        "verified": !addrs.is_empty(),
+                        "line": code_line.unwrap_or(line),
+                    });
+                    if let Some(addr) = addrs.first() {
+                        breakpoint["instructionReference"] = json!(format!("{:#04x}", addr));
+                    }
+                    breakpoints.push(breakpoint);
+                }
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "stackTrace" => {
+                let program = self.program()?;
+                let ip = program.session.emulator.ip();
+                let mut frame = json!({
+                    "id": 1,
+                    "name": program.symbols.describe(ip).unwrap_or_else(|| "main".into()),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#04x}", ip),
+                });
+                if let (Some(source), Some(line)) =
+                    (program.source_json(), program.lines.line_of(ip))
+                {
+                    frame["source"] = source;
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+                Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+            }
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REF, "expensive": false },
+                { "name": "Labels", "variablesReference": LABELS_REF, "expensive": false },
+            ] })),
+            "variables" => {
+                let program = self.program()?;
+                let reference = args["variablesReference"].as_u64().unwrap_or(0);
+                let variables = program
+                    .variables(reference)
+                    .ok_or_else(|| format!("unknown variablesReference {}", reference))?;
+                Ok(json!({ "variables": variables }))
+            }
+            "readMemory" => {
+                let program = self.program()?;
+                let reference = args["memoryReference"].as_str().unwrap_or_default();
+                let start = parse_addr(reference)
+                    .ok_or_else(|| format!("invalid memoryReference '{}'", reference))?
+                    as i64
+                    + args["offset"].as_i64().unwrap_or(0);
+                let count = args["count"].as_i64().unwrap_or(0);
+                let end = (start + count).clamp(0, 256);
+                let start = start.clamp(0, end);
+                // every nibble is one byte
+                let data: Vec<u8> = (start..end)
+                    .map(|addr| {
+                        program
+                            .session
+                            .emulator
+                            .ghost_read_mem(addr as u8)
+                            .into_low()
+                    })
+                    .collect();
+                Ok(json!({
+                    "address": format!("{:#04x}", start),
+                    "data": base64(&data),
+                    "unreadableBytes": count - data.len() as i64,
+                }))
+            }
+            _ => Err(format!("unsupported request '{}'", command)),
+        }
+    }
+
+    ///Returns true when a pause request arrived
+    fn poll_pause(&mut self) -> io::Result<bool> {
+        loop {
+            match self.incoming.try_recv() {
+                Ok(request) if request["command"] == "pause" => {
+                    self.respond(&request, Ok(json!({})))?;
+                    return Ok(true);
+                }
+                Ok(request) => self.queue.push_back(request),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return Ok(false),
+            }
+        }
+    }
+
+    fn execute(&mut self, mode: RunMode) -> io::Result<()> {
+        let Some(program) = self.program.as_mut() else {
+            return Ok(());
+        };
+        let start_line = program.lines.line_of(program.session.emulator.ip());
+        let reason = loop {
+            let program = self.program.as_mut().unwrap();
+            if !program.session.is_running() {
+                break None;
+            }
+            program.session.tick()?;
+            let session = &program.session;
+            if !session.is_running() {
+                break None;
+            }
+            if session.debugger.should_break(&session.emulator) {
+                break Some("breakpoint");
+            }
+            if mode == RunMode::Line {
+                let line = program.lines.line_of(session.emulator.ip());
+                if line != start_line && (line.is_some() || start_line.is_none()) {
+                    break Some("step");
+                }
+            }
+            if session.emulator.cycles.is_multiple_of(PAUSE_POLL_CYCLES) && self.poll_pause()? {
+                break Some("pause");
+            }
+        };
+
+        let program = self.program.as_mut().unwrap();
+        if let Some(output) = program.take_output() {
+            self.send_event("output", json!({ "category": "stdout", "output": output }))?;
+        }
+        match reason {
+            Some(reason) => self.send_event(
+                "stopped",
+                json!({ "reason": reason, "threadId": THREAD_ID }),
+            ),
+            None => {
+                let program = self.program.as_ref().unwrap();
+                let exit_code = program.session.emulator.stack_peek().into_low();
+                self.send_event("exited", json!({ "exitCode": exit_code }))?;
+                self.send_event("terminated", json!({}))
+            }
+        }
+    }
+}
+
+///Serves one client until it disconnects
+pub fn serve(input: impl BufRead + Send + 'static, output: impl Write) -> io::Result<()> {
+    let (sender, incoming) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    let mut server = DapServer::new(incoming, output);
+    while server.handle_next()? {}
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::{
+    emulator::{AccessKind, Emulator},
+    ext::ExtManager,
+    state::Snapshot,
+};
+
+///Ticks that can be stepped back when a debugger is attached and no history length is given
+pub const DEFAULT_HISTORY: usize = 10000;
+
+struct HistoryEntry {
+    ///State before the tick
+    snapshot: Snapshot,
+    writes: Vec<u8>,
+}
+
+///Breakpoints, watched cells and the history used for stepping back
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u8>,
+    pub watches: BTreeSet<u8>,
+    history: VecDeque<HistoryEntry>,
+    history_limit: usize,
+}
+
+impl Debugger {
+    pub fn new(history_limit: usize) -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            watches: BTreeSet::new(),
+            history: VecDeque::new(),
+            history_limit,
+        }
+    }
+
+    ///Whether ticks have to be recorded, taking a snapshot on every tick is not free
+    pub fn is_recording(&self) -> bool {
+        self.history_limit > 0
+    }
+
+    ///Remembers the state from before the last tick, call after every tick
+    pub fn record(&mut self, before: Snapshot, emulator: &Emulator) {
+        if !self.is_recording() {
+            return;
+        }
+        if self.history.len() == self.history_limit {
+            self.history.pop_front();
+        }
+        self.history.push_back(HistoryEntry {
+            snapshot: before,
+            writes: emulator
+                .accesses()
+                .iter()
+                .filter(|access| access.kind == AccessKind::Write)
+                .map(|access| access.addr)
+                .collect(),
+        });
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    ///Returns true when the last tick should stop a continuing vm
+    pub fn should_break(&self, emulator: &Emulator) -> bool {
+        self.breakpoints.contains(&emulator.ip())
+            || emulator.accesses().iter().any(|access| {
+                access.kind == AccessKind::Write && self.watches.contains(&access.addr)
+            })
+    }
+
+    ///Undoes up to `count` ticks and returns how many were undone
+    pub fn back(&mut self, count: usize, emulator: &mut Emulator, extmgr: &ExtManager) -> usize {
+        let mut undone = 0;
+        while undone < count {
+            let Some(entry) = self.history.pop_back() else {
+                break;
+            };
+            entry.snapshot.restore(emulator, extmgr);
+            undone += 1;
+        }
+        undone
+    }
+
+    ///Steps back until ip is on a breakpoint or the next tick writes a watched cell
+    pub fn reverse_continue(&mut self, emulator: &mut Emulator, extmgr: &ExtManager) -> usize {
+        let mut undone = 0;
+        while let Some(entry) = self.history.pop_back() {
+            entry.snapshot.restore(emulator, extmgr);
+            undone += 1;
+            if self.breakpoints.contains(&emulator.ip())
+                || entry.writes.iter().any(|addr| self.watches.contains(addr))
+            {
+                break;
+            }
+        }
+        undone
+    }
+}
@@ -1,3 +1,5 @@
+use std::cell::{Ref, RefCell};
+
 use libmcc::{u4, v3::Instruction};
 
 pub const IP_ADDR0: u8 = 0x00;
@@ -5,13 +7,37 @@ pub const IP_ADDR1: u8 = 0x01;
 pub const DP_ADDR0: u8 = 0x02;
 pub const DP_ADDR1: u8 = 0x03;
 pub const SP_ADDR: u8 = 0x04;
+///First address after the register cells
+pub const REG_END: u8 = 0x05;
 
 pub const STACK_START: u8 = 0x10;
 
+type MemAccesFn = dyn Fn(u8, u4, bool, &Emulator) -> Option<u4>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+    ///Read of the instruction at ip
+    Fetch,
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MemAccess {
+    pub kind: AccessKind,
+    pub addr: u8,
+    pub value: u4,
+    ///The value was supplied by an extension
+    pub ext: bool,
+}
+
 pub struct Emulator {
     pub mem: [u4; 256],
     pub is_running: bool,
-    on_mem_acces: Box<dyn Fn(u8, u4, bool, &Self) -> Option<u4>>,
+    ///Amount of executed instructions
+    pub cycles: u64,
+    on_mem_acces: Box<MemAccesFn>,
+    accesses: RefCell<Vec<MemAccess>>,
 }
 
 impl Emulator {
@@ -22,7 +48,24 @@ impl Emulator {
         Emulator {
             mem,
             is_running: false,
+            cycles: 0,
             on_mem_acces: Box::new(on_mem_acces),
+            accesses: RefCell::new(Vec::new()),
+        }
+    }
+
+    ///Memory accesses of the last tick (register cells are not included)
+    pub fn accesses(&self) -> Ref<'_, Vec<MemAccess>> {
+        self.accesses.borrow()
+    }
+    fn log_access(&self, kind: AccessKind, addr: u8, value: u4, ext: bool) {
+        if addr >= REG_END {
+            self.accesses.borrow_mut().push(MemAccess {
+                kind,
+                addr,
+                value,
+                ext,
+            });
         }
     }
     pub fn set_dp(&mut self, value: u8) {
@@ -48,7 +91,7 @@ impl Emulator {
 
     pub fn stack_push(&mut self, value: u4) {
         //println!("{:#04x}", value.into_u8());
-        self.set_sp((self.sp().overflowing_add(u4::ONE)).into());
+        self.set_sp(self.sp().overflowing_add(u4::ONE));
         self.write_mem(STACK_START + self.sp().into_low(), value);
     }
     pub fn stack_pop(&mut self) -> u4 {
@@ -56,12 +99,16 @@ impl Emulator {
         if self.sp() == u4::ZERO {
             return value;
         }
-        self.set_sp((self.sp().overflowing_sub(u4::ONE)).into());
+        self.set_sp(self.sp().overflowing_sub(u4::ONE));
         value
     }
-    pub fn stack_peek(&mut self) -> u4 {
-        let value = self.mem[0x10 as usize + self.sp().into_usize()];
-        value
+    ///The values on the stack from bottom to top
+    pub fn stack(&self) -> &[u4] {
+        let start = STACK_START as usize + 1;
+        &self.mem[start..start + self.sp().into_usize()]
+    }
+    pub fn stack_peek(&self) -> u4 {
+        self.mem[STACK_START as usize + self.sp().into_usize()]
     }
 
     pub fn start(&mut self) {
@@ -75,22 +122,29 @@ impl Emulator {
         self.is_running = false;
     }
 
+    ///Every address computation wraps around, a 16 bit value at 0xFF continues at 0x00
     pub fn read_mem8(&self, addr: u8) -> u8 {
         let lower = self.read_mem(addr).into_low();
-        let upper = self.read_mem(addr + 1).into_high();
-        return lower | upper;
+        let upper = self.read_mem(addr.wrapping_add(1)).into_high();
+        lower | upper
     }
     ///Read memory without triggering an on_mem_acces call
     pub fn ghost_read_mem8(&self, addr: u8) -> u8 {
         let lower = self.ghost_read_mem(addr).into_low();
-        let upper = self.ghost_read_mem(addr + 1).into_high();
-        return lower | upper;
+        let upper = self.ghost_read_mem(addr.wrapping_add(1)).into_high();
+        lower | upper
     }
     pub fn read_mem(&self, addr: u8) -> u4 {
+        self.read_mem_as(AccessKind::Read, addr)
+    }
+    fn read_mem_as(&self, kind: AccessKind, addr: u8) -> u4 {
         if let Some(ext_out) = (self.on_mem_acces)(addr, u4::ZERO, false, self) {
+            self.log_access(kind, addr, ext_out, true);
             return ext_out;
         }
-        self.ghost_read_mem(addr)
+        let value = self.ghost_read_mem(addr);
+        self.log_access(kind, addr, value, false);
+        value
     }
     ///Read memory without triggering an on_mem_acces call
     pub fn ghost_read_mem(&self, addr: u8) -> u4 {
@@ -98,18 +152,21 @@ impl Emulator {
     }
     pub fn write_mem(&mut self, addr: u8, value: u4) {
         self.mem[addr as usize] = value;
+        self.log_access(AccessKind::Write, addr, value, false);
         (self.on_mem_acces)(addr, value, true, self);
     }
     pub fn write_mem8(&mut self, addr: u8, value: u8) {
         self.write_mem(addr, u4::from_low(value));
-        self.write_mem(addr + 1, u4::from_high(value));
+        self.write_mem(addr.wrapping_add(1), u4::from_high(value));
     }
 
     pub fn tick(&mut self) -> Option<Instruction> {
         if !self.is_running {
             return None;
         }
-        let current_nib = self.read_mem(self.ip());
+        self.accesses.borrow_mut().clear();
+        self.cycles += 1;
+        let current_nib = self.read_mem_as(AccessKind::Fetch, self.ip());
         let instruct: Instruction = Instruction::from_u4(current_nib);
 
         let mut jump = false;
@@ -120,22 +177,22 @@ impl Emulator {
                 //println!("{:#04x}", self.read_mem(0x20));
                 //println!("{:#04x}", self.dp());
                 self.stack_push(self.read_mem(self.dp()));
-                self.set_dp(self.dp() + 1);
+                self.set_dp(self.dp().wrapping_add(1));
             }
             Psd => {
                 //println!("{:#04x}", self.dp());
                 self.stack_push(self.read_mem(self.dp()));
-                self.set_dp(self.dp() - 1);
+                self.set_dp(self.dp().wrapping_sub(1));
             }
             Poi => {
                 let val = self.stack_pop();
                 self.write_mem(self.dp(), val);
-                self.set_dp(self.dp() + 1);
+                self.set_dp(self.dp().wrapping_add(1));
             }
             Pod => {
                 let val = self.stack_pop();
                 self.write_mem(self.dp(), val);
-                self.set_dp(self.dp() - 1);
+                self.set_dp(self.dp().wrapping_sub(1));
             }
             Swp => {
                 let val1 = self.stack_pop();
@@ -151,8 +208,8 @@ impl Emulator {
                 self.write_mem(DP_ADDR1, val);
                 //println!("mdp{}", val);
             }
-            Di => self.set_dp(self.dp() + 1),
-            Dd => self.set_dp(self.dp() - 1),
+            Di => self.set_dp(self.dp().wrapping_add(1)),
+            Dd => self.set_dp(self.dp().wrapping_sub(1)),
             Jmp => {
                 let val = self.stack_pop();
                 self.write_mem(IP_ADDR0, val);
@@ -197,7 +254,7 @@ impl Emulator {
             return Some(instruct);
         }
         if !jump {
-            self.set_ip(self.ip() + 1);
+            self.set_ip(self.ip().wrapping_add(1));
         }
         Some(instruct)
     }
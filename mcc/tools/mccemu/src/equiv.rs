@@ -0,0 +1,220 @@
+use std::fmt::Display;
+
+use libmcc::u4;
+
+use crate::{
+    prop::{Output, Property, Run},
+    symbolic::{Engine, MAX_INPUTS},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    ///Symbolic when there are few enough inputs and every path is followed, exhaustive otherwise
+    Auto,
+    ///Runs both programs for every combination of input values
+    Exhaustive,
+    ///Runs both programs once with symbolic inputs
+    Symbolic,
+}
+impl Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Method::Auto => f.write_str("auto"),
+            Method::Exhaustive => f.write_str("exhaustive"),
+            Method::Symbolic => f.write_str("symbolic"),
+        }
+    }
+}
+
+///How two programs were shown to be equivalent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub cases: usize,
+    ///Exhaustive or Symbolic
+    pub method: Method,
+    ///Inputs for which neither program halted within the cycle bound
+    pub unfinished: usize,
+}
+
+///Inputs for which the programs differ
+#[derive(Debug, Clone)]
+pub struct Counterexample {
+    pub inputs: Vec<(Box<str>, u4)>,
+    pub outputs: Vec<Output>,
+    pub a: Run,
+    pub b: Run,
+}
+impl Display for Counterexample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inputs: Vec<_> = self
+            .inputs
+            .iter()
+            .map(|(name, value)| format!("{}={:#03x}", name, value))
+            .collect();
+        writeln!(f, "inputs: {}", inputs.join(" "))?;
+        match (&self.a.outputs, &self.b.outputs) {
+            (Ok(a), Ok(b)) => {
+                for ((output, a), b) in self.outputs.iter().zip(a).zip(b) {
+                    let marker = if a == b { "" } else { " <-" };
+                    writeln!(f, "{}: {:#03x} vs {:#03x}{}", output, a, b, marker)?;
+                }
+            }
+            (a, b) => {
+                for (name, outputs) in [("first", a), ("second", b)] {
+                    match outputs {
+                        Ok(outputs) => {
+                            let outputs: Vec<_> =
+                                outputs.iter().map(|v| format!("{:#03x}", v)).collect();
+                            writeln!(f, "{}: {}", name, outputs.join(" "))?
+                        }
+                        Err(err) => writeln!(f, "{}: {}", name, err)?,
+                    }
+                }
+            }
+        }
+        for (name, run) in [("first", &self.a), ("second", &self.b)] {
+            writeln!(f, "trace of the {} program:", name)?;
+            for line in run.trace.iter() {
+                writeln!(f, "    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+///Why `check` couldn't show that two programs are equivalent
+#[derive(Debug, Clone)]
+pub enum Failure {
+    Different(Box<Counterexample>),
+    ///`Method::Symbolic` was asked for with more than `MAX_INPUTS` inputs
+    TooManyInputs,
+    ///`Method::Symbolic` couldn't follow every path of a program
+    Incomplete,
+}
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Different(counterexample) => counterexample.fmt(f),
+            Failure::TooManyInputs => {
+                write!(f, "at most {} inputs can be symbolic", MAX_INPUTS)
+            }
+            Failure::Incomplete => f.write_str("symbolic gave up before following every path"),
+        }
+    }
+}
+
+///The outputs for every combination of input values, None when the vm didn't halt
+type Outcomes = Vec<Option<Vec<u4>>>;
+
+fn index(values: &[u4]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| value.into_usize() << (i * 4))
+        .sum()
+}
+
+fn values(inputs: usize, index: usize) -> Vec<u4> {
+    (0..inputs)
+        .map(|i| u4::from_low((index >> (i * 4)) as u8))
+        .collect()
+}
+
+///None when not every path could be followed
+fn symbolic_outcomes(property: &Property) -> Option<Outcomes> {
+    let mut engine = Engine::new(property.image);
+    engine.inputs = property.inputs.clone();
+    engine.max_cycles = property.max_cycles;
+    engine.max_steps = property.max_steps;
+    let exploration = engine.explore();
+    if exploration.incomplete {
+        return None;
+    }
+    let mut outcomes = vec![None; 1 << (property.inputs.len() * 4)];
+    for halt in exploration.halts.iter() {
+        let syms: Vec<_> = property
+            .outputs
+            .iter()
+            .map(|output| halt.output(*output))
+            .collect();
+        for values in halt.domain.assignments() {
+            outcomes[index(&values)] = Some(syms.iter().map(|sym| sym.eval(&values)).collect());
+        }
+    }
+    Some(outcomes)
+}
+
+///Decides whether `a` and `b` produce the same outputs for every value of their inputs,
+///the inputs are paired up in order and `halt_at` is ignored.
+///Only `Method::Auto` falls back to exhaustive when symbolic can't be used
+pub fn check(a: &Property, b: &Property, method: Method) -> Result<Report, Failure> {
+    assert_eq!(a.inputs.len(), b.inputs.len(), "different number of inputs");
+    assert_eq!(
+        a.outputs.len(),
+        b.outputs.len(),
+        "different number of outputs"
+    );
+    // the symbolic engine always runs to the end of memory, the exhaustive runs have to as well
+    let without_halt_at = |property: &Property| Property {
+        halt_at: None,
+        ..property.clone()
+    };
+    let (a, b) = (&without_halt_at(a), &without_halt_at(b));
+    let inputs = a.inputs.len();
+    let cases = 16usize.pow(inputs as u32);
+
+    let symbolic = match method {
+        Method::Exhaustive => None,
+        Method::Symbolic if inputs > MAX_INPUTS => return Err(Failure::TooManyInputs),
+        Method::Symbolic => {
+            let outcomes = symbolic_outcomes(a).zip(symbolic_outcomes(b));
+            Some(outcomes.ok_or(Failure::Incomplete)?)
+        }
+        Method::Auto if inputs > MAX_INPUTS => None,
+        Method::Auto => symbolic_outcomes(a).zip(symbolic_outcomes(b)),
+    };
+    let (method, differs, unfinished) = match symbolic {
+        Some((outcomes_a, outcomes_b)) => {
+            let differs = (0..cases).find(|i| outcomes_a[*i] != outcomes_b[*i]);
+            let unfinished = (0..cases)
+                .filter(|i| outcomes_a[*i].is_none() && outcomes_b[*i].is_none())
+                .count();
+            (Method::Symbolic, differs, unfinished)
+        }
+        None => {
+            let mut unfinished = 0;
+            let differs = (0..cases).find(|i| {
+                let values = values(inputs, *i);
+                let (run_a, run_b) = (a.run(&values), b.run(&values));
+                if run_a.outputs.is_err() && run_b.outputs.is_err() {
+                    unfinished += 1;
+                    return false;
+                }
+                run_a.outputs != run_b.outputs
+            });
+            (Method::Exhaustive, differs, unfinished)
+        }
+    };
+
+    match differs {
+        None => Ok(Report {
+            cases,
+            method,
+            unfinished,
+        }),
+        Some(i) => {
+            let values = values(inputs, i);
+            Err(Failure::Different(Box::new(Counterexample {
+                inputs: a
+                    .inputs
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .zip(values.iter().copied())
+                    .collect(),
+                outputs: a.outputs.clone(),
+                a: a.run(&values),
+                b: b.run(&values),
+            })))
+        }
+    }
+}
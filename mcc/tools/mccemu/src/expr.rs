@@ -0,0 +1,196 @@
+use std::{collections::HashMap, iter::Peekable, str::Chars};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Op {
+    ///Higher binds tighter, the same order as in C
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Or => 1,
+            Op::Xor => 2,
+            Op::And => 3,
+            Op::Shl | Op::Shr => 4,
+            Op::Add | Op::Sub => 5,
+            Op::Mul | Op::Div | Op::Rem => 6,
+        }
+    }
+}
+
+///An integer expression like `(a * b) & 0xF` over named values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(u64),
+    Var(Box<str>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|char| char.is_whitespace()).is_some() {}
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(char) = self
+            .chars
+            .next_if(|char| char.is_alphanumeric() || *char == '_' || *char == '.')
+        {
+            word.push(char);
+        }
+        word
+    }
+
+    fn op(&mut self) -> Result<Option<Op>, String> {
+        self.skip_whitespace();
+        let op = match self.chars.peek() {
+            Some('|') => Op::Or,
+            Some('^') => Op::Xor,
+            Some('&') => Op::And,
+            Some('+') => Op::Add,
+            Some('-') => Op::Sub,
+            Some('*') => Op::Mul,
+            Some('/') => Op::Div,
+            Some('%') => Op::Rem,
+            Some('<') | Some('>') => {
+                let char = self.chars.next().unwrap();
+                if self.chars.next() != Some(char) {
+                    return Err(format!("expected '{}{}'", char, char));
+                }
+                return Ok(Some(if char == '<' { Op::Shl } else { Op::Shr }));
+            }
+            _ => return Ok(None),
+        };
+        self.chars.next();
+        Ok(Some(op))
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('(') => {
+                self.chars.next();
+                let expr = self.expr(0)?;
+                self.skip_whitespace();
+                match self.chars.next() {
+                    Some(')') => Ok(expr),
+                    _ => Err("expected ')'".into()),
+                }
+            }
+            Some('-') => {
+                self.chars.next();
+                Ok(Expr::Neg(Box::new(self.atom()?)))
+            }
+            Some('~') => {
+                self.chars.next();
+                Ok(Expr::Not(Box::new(self.atom()?)))
+            }
+            Some(char) if char.is_ascii_digit() => {
+                let word = self.word();
+                let num = match word.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                num.map(Expr::Num)
+                    .map_err(|_| format!("'{}' is not a number", word))
+            }
+            Some(_) => match self.word() {
+                word if word.is_empty() => Err(format!(
+                    "unexpected '{}'",
+                    self.chars.peek().copied().unwrap_or_default()
+                )),
+                word => Ok(Expr::Var(word.into())),
+            },
+            None => Err("unexpected end of expression".into()),
+        }
+    }
+
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.atom()?;
+        loop {
+            let checkpoint = self.chars.clone();
+            let Some(op) = self.op()? else {
+                return Ok(lhs);
+            };
+            if op.precedence() < min_precedence {
+                self.chars = checkpoint;
+                return Ok(lhs);
+            }
+            let rhs = self.expr(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+        let expr = parser.expr(0)?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(expr),
+            Some(char) => Err(format!("unexpected '{}'", char)),
+        }
+    }
+
+    ///Expressions next to each other like `b a+1`
+    pub fn parse_list(text: &str) -> Result<Vec<Self>, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+        let mut exprs = Vec::new();
+        loop {
+            parser.skip_whitespace();
+            if parser.chars.peek().is_none() {
+                return Ok(exprs);
+            }
+            exprs.push(parser.expr(0)?);
+        }
+    }
+
+    ///Arithmetic wraps around at 64 bits
+    pub fn eval(&self, vars: &HashMap<&str, u64>) -> Result<u64, String> {
+        Ok(match self {
+            Expr::Num(num) => *num,
+            Expr::Var(name) => *vars
+                .get(&**name)
+                .ok_or_else(|| format!("unknown name '{}'", name))?,
+            Expr::Neg(expr) => expr.eval(vars)?.wrapping_neg(),
+            Expr::Not(expr) => !expr.eval(vars)?,
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(vars)?, rhs.eval(vars)?);
+                match op {
+                    Op::Or => lhs | rhs,
+                    Op::Xor => lhs ^ rhs,
+                    Op::And => lhs & rhs,
+                    Op::Shl => lhs.checked_shl(rhs as u32).unwrap_or(0),
+                    Op::Shr => lhs.checked_shr(rhs as u32).unwrap_or(0),
+                    Op::Add => lhs.wrapping_add(rhs),
+                    Op::Sub => lhs.wrapping_sub(rhs),
+                    Op::Mul => lhs.wrapping_mul(rhs),
+                    Op::Div => lhs.checked_div(rhs).ok_or("division by zero")?,
+                    Op::Rem => lhs.checked_rem(rhs).ok_or("division by zero")?,
+                }
+            }
+        })
+    }
+}
@@ -1,39 +1,267 @@
-use std::io::Write;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    rc::Rc,
+};
 
+use clap::{Args, ValueEnum};
 use console::Term;
 use libmcc::u4;
 
 use crate::emulator::Emulator;
 
 use super::Extension;
+
+pub const DATA0_ADDR: u8 = 0xF0;
+pub const DATA1_ADDR: u8 = 0xF1;
+
+///What a read returns when there is no more input
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EofMode {
+    ///Stop the vm
+    Halt,
+    ///Return 0x00
+    Zero,
+    ///Return a line feed (0x0A)
+    Lf,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    ///Read a whole line before handing out characters (the line is echoed on a terminal)
+    Line,
+    ///Hand out every character as soon as it is typed
+    Raw,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ChardevArgs {
+    ///File to read chardev input from or - to read stdin (defaults to stdin when it is not a terminal)
+    #[arg(long, value_name = "FILE")]
+    pub chardev_in: Option<String>,
+
+    ///Use this string as chardev input
+    #[arg(long, value_name = "STRING", conflicts_with = "chardev_in")]
+    pub chardev_str: Option<String>,
+
+    ///File to write chardev output to
+    #[arg(long, value_name = "FILE")]
+    pub chardev_out: Option<String>,
+
+    ///What to do when reading past the end of the input
+    #[arg(long, default_value = "halt")]
+    pub chardev_eof: EofMode,
+
+    #[arg(long, default_value = "raw")]
+    pub chardev_mode: InputMode,
+}
+
+///Output buffer that can be read while the chardev writes to it
+#[derive(Clone, Default)]
+pub struct SharedBuf(pub Rc<RefCell<Vec<u8>>>);
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Input {
+    Term(Term),
+    Reader(Box<dyn BufRead>),
+}
+
 pub struct CharDev {
     current_char: u8,
-    stdout: Term,
+    ///The last characters read from the input so they can be read again after stepping back
+    history: VecDeque<u8>,
+    ///How many characters were dropped from the front of the history
+    history_start: usize,
+    history_limit: usize,
+    ///Position in the input including the dropped characters
+    history_pos: usize,
+    input: Input,
+    output: Box<dyn Write>,
+    pending: VecDeque<u8>,
+    eof: EofMode,
+    mode: InputMode,
+    halted: bool,
+    ///A failed read or write, the vm stops until it is taken
+    error: Option<io::Error>,
 }
 
 impl CharDev {
-    pub fn new() -> Self {
+    ///`output` overrides --chardev-out
+    pub fn new(args: &ChardevArgs, output: Option<Box<dyn Write>>) -> io::Result<Self> {
+        let input = if let Some(str) = &args.chardev_str {
+            Input::Reader(Box::new(io::Cursor::new(str.clone().into_bytes())))
+        } else {
+            match args.chardev_in.as_deref() {
+                Some("-") => Input::Reader(Box::new(io::stdin().lock())),
+                Some(path) => Input::Reader(Box::new(BufReader::new(File::open(path)?))),
+                None if io::stdin().is_terminal() => Input::Term(Term::stdout()),
+                None => Input::Reader(Box::new(io::stdin().lock())),
+            }
+        };
+        let output: Box<dyn Write> = match (output, &args.chardev_out) {
+            (Some(output), _) => output,
+            (None, Some(path)) => Box::new(File::create(path)?),
+            (None, None) => Box::new(Term::stdout()),
+        };
+
+        Ok(Self::with_input(
+            input,
+            output,
+            args.chardev_eof,
+            args.chardev_mode,
+        ))
+    }
+
+    ///Creates a chardev that never touches the terminal
+    pub fn from_reader(
+        input: Box<dyn BufRead>,
+        output: Box<dyn Write>,
+        eof: EofMode,
+        mode: InputMode,
+    ) -> Self {
+        Self::with_input(Input::Reader(input), output, eof, mode)
+    }
+
+    fn with_input(input: Input, output: Box<dyn Write>, eof: EofMode, mode: InputMode) -> Self {
         Self {
             current_char: 0,
-            stdout: Term::stdout(),
+            history: VecDeque::new(),
+            history_start: 0,
+            history_limit: 0,
+            history_pos: 0,
+            input,
+            output,
+            pending: VecDeque::new(),
+            eof,
+            mode,
+            halted: false,
+            error: None,
+        }
+    }
+
+    ///Returns None at the end of the input
+    fn read_char(&mut self) -> io::Result<Option<u8>> {
+        if let Some(char) = self.history.get(self.history_pos - self.history_start) {
+            self.history_pos += 1;
+            return Ok(Some(*char));
+        }
+        let Some(char) = self.read_input()? else {
+            return Ok(None);
+        };
+        self.history.push_back(char);
+        self.trim_history();
+        self.history_pos += 1;
+        Ok(Some(char))
+    }
+
+    fn read_input(&mut self) -> io::Result<Option<u8>> {
+        if let Some(char) = self.pending.pop_front() {
+            return Ok(Some(char));
+        }
+        match (&mut self.input, self.mode) {
+            (Input::Term(term), InputMode::Raw) => Ok(Some(term.read_char()? as u8)),
+            (Input::Term(term), InputMode::Line) => {
+                let line = term.read_line()?;
+                self.pending.extend(line.bytes());
+                self.pending.push_back(b'\n');
+                Ok(self.pending.pop_front())
+            }
+            (Input::Reader(reader), InputMode::Raw) => {
+                let mut buf = [0];
+                match reader.read(&mut buf)? {
+                    0 => Ok(None),
+                    _ => Ok(Some(buf[0])),
+                }
+            }
+            (Input::Reader(reader), InputMode::Line) => {
+                let mut line = Vec::new();
+                reader.read_until(b'\n', &mut line)?;
+                self.pending.extend(line);
+                Ok(self.pending.pop_front())
+            }
         }
     }
+
+    fn trim_history(&mut self) {
+        while self.history.len() > self.history_limit {
+            self.history.pop_front();
+            self.history_start += 1;
+        }
+    }
+
+    fn fail(&mut self, action: &str, err: io::Error) {
+        self.halted = true;
+        self.error = Some(io::Error::new(
+            err.kind(),
+            format!("chardev: failed to {}: {}", action, err),
+        ));
+    }
 }
 impl Extension for CharDev {
     fn on_mem_write(&mut self, addr: u8, _value: u4, emulator: &Emulator) {
-        if addr == 0xF1 {
-            let buf = [emulator.ghost_read_mem8(0xF0)];
-            self.stdout.write(&buf).unwrap();
+        if addr == DATA1_ADDR {
+            let buf = [emulator.ghost_read_mem8(DATA0_ADDR)];
+            if let Err(err) = self.output.write_all(&buf) {
+                self.fail("write output", err);
+            }
         }
     }
     fn on_mem_read(&mut self, addr: u8, _emulator: &Emulator) -> Option<u4> {
-        if addr == 0xF0 {
-            self.current_char = self.stdout.read_char().unwrap() as u8;
+        if addr == DATA0_ADDR {
+            self.current_char = match self.read_char() {
+                Ok(Some(char)) => char,
+                Err(err) => {
+                    self.fail("read input", err);
+                    0x00
+                }
+                Ok(None) => match self.eof {
+                    EofMode::Halt => {
+                        self.halted = true;
+                        0x00
+                    }
+                    EofMode::Zero => 0x00,
+                    EofMode::Lf => b'\n',
+                },
+            };
             return Some(u4::from_low(self.current_char));
         };
-        if addr == 0xF1 {
+        if addr == DATA1_ADDR {
             return Some(u4::from_high(self.current_char));
         };
         None
     }
+    fn is_halted(&self) -> bool {
+        self.halted
+    }
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+    fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        self.trim_history();
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.current_char, self.halted as u8];
+        state.extend_from_slice(&(self.history_pos as u64).to_le_bytes());
+        state
+    }
+    fn load_state(&mut self, state: &[u8]) {
+        if let [current_char, halted, pos @ ..] = state {
+            self.current_char = *current_char;
+            self.halted = *halted != 0;
+            let pos = pos.try_into().map(u64::from_le_bytes).unwrap_or(0);
+            self.history_pos =
+                (pos as usize).clamp(self.history_start, self.history_start + self.history.len());
+        }
+    }
 }
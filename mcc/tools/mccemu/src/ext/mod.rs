@@ -1,19 +1,36 @@
-use std::cell::RefCell;
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+};
 
 use clap::ValueEnum;
 use libmcc::u4;
 
 use crate::emulator::Emulator;
 
-use self::chardev::CharDev;
+use self::chardev::{CharDev, ChardevArgs};
 
-mod chardev;
+pub mod chardev;
 
 pub trait Extension {
     fn on_mem_read(&mut self, _addr: u8, _emulator: &Emulator) -> Option<u4> {
         None
     }
     fn on_mem_write(&mut self, _addr: u8, _value: u4, _emulator: &Emulator) {}
+    ///Returns true when the extension wants the vm to stop
+    fn is_halted(&self) -> bool {
+        false
+    }
+    ///An I/O error that made the extension stop the vm
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+    ///How many ticks can be stepped back, extensions that keep a log for this can trim it
+    fn set_history_limit(&mut self, _limit: usize) {}
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load_state(&mut self, _state: &[u8]) {}
 }
 
 #[derive(ValueEnum, Debug, Clone)]
@@ -42,12 +59,53 @@ pub struct ExtManager {
     extensions: RefCell<Vec<Box<dyn Extension>>>,
 }
 impl ExtManager {
-    pub fn new(ext_types: Vec<ExtType>) -> Self {
-        Self {
+    pub fn new(
+        ext_types: Vec<ExtType>,
+        chardev_args: &ChardevArgs,
+        mut chardev_output: Option<Box<dyn Write>>,
+    ) -> io::Result<Self> {
+        Ok(Self {
             extensions: type_to_ext!(ext_types,
-                ChardevAscii=>CharDev::new()
+                ChardevAscii=>CharDev::new(chardev_args, chardev_output.take())?
             )
             .into(),
+        })
+    }
+
+    pub fn from_extensions(extensions: Vec<Box<dyn Extension>>) -> Self {
+        Self {
+            extensions: extensions.into(),
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.extensions.borrow().iter().any(|ext| ext.is_halted())
+    }
+
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.extensions
+            .borrow_mut()
+            .iter_mut()
+            .find_map(|ext| ext.take_error())
+    }
+
+    pub fn set_history_limit(&self, limit: usize) {
+        for ext in self.extensions.borrow_mut().iter_mut() {
+            ext.set_history_limit(limit);
+        }
+    }
+
+    pub fn save_state(&self) -> Vec<Vec<u8>> {
+        self.extensions
+            .borrow()
+            .iter()
+            .map(|ext| ext.save_state())
+            .collect()
+    }
+
+    pub fn load_state(&self, states: &[Vec<u8>]) {
+        for (ext, state) in self.extensions.borrow_mut().iter_mut().zip(states) {
+            ext.load_state(state);
         }
     }
 
@@ -0,0 +1,76 @@
+use libmcc::u4;
+
+use crate::{emulator::Emulator, halt::HaltConditions, strict::Strict};
+
+///Cycles every image runs for
+pub const CYCLES: u64 = 2000;
+
+///xorshift64*, a seed always produces the same images
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck at 0
+        Self(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    ///256 random nibbles, the register cells included
+    pub fn image(&mut self) -> [u4; 256] {
+        let mut image = [u4::ZERO; 256];
+        for chunk in image.chunks_mut(16) {
+            let mut bits = self.next_u64();
+            for nib in chunk {
+                *nib = u4::from_low(bits as u8);
+                bits >>= 4;
+            }
+        }
+        image
+    }
+}
+
+///Two nibbles per byte like a packed bin, missing nibbles are 0
+pub fn image_from_bytes(data: &[u8]) -> [u4; 256] {
+    let mut image = [u4::ZERO; 256];
+    for (i, byte) in data.iter().take(128).enumerate() {
+        image[i * 2] = u4::from_low(*byte);
+        image[i * 2 + 1] = u4::from_high(*byte);
+    }
+    image
+}
+
+fn run_emulator(mut emulator: Emulator, cycles: u64) {
+    let mut halt = HaltConditions::new(Some(cycles), Vec::new(), true);
+    let mut strict = Strict::new(None, None);
+    while emulator.is_running {
+        let ip = emulator.ip();
+        // only checked, a trap doesn't stop the run so the emulator sees every state
+        strict.before(&emulator);
+        let Some(instruct) = emulator.tick() else {
+            break;
+        };
+        strict.after(&emulator, ip, instruct);
+        if halt.check(&emulator, ip).is_some() {
+            break;
+        }
+    }
+}
+
+///Runs an image with the registers it contains and once more from the usual start state.
+///
+///Panics when the emulator or one of the checks around it does
+pub fn run(image: [u4; 256], cycles: u64) {
+    let mut emulator = Emulator::new(image, |_, _, _, _| None);
+    emulator.is_running = true;
+    run_emulator(emulator, cycles);
+
+    let mut emulator = Emulator::new(image, |_, _, _, _| None);
+    emulator.start();
+    run_emulator(emulator, cycles);
+}
@@ -0,0 +1,366 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+use libmcc::u4;
+
+use crate::session::Session;
+
+///Registers in the order of the `g` packet, every register is sent as 1 byte
+const REGISTER_COUNT: usize = 3;
+///Check for an interrupt from the client every this many cycles while continuing
+const INTERRUPT_POLL_CYCLES: u64 = 4096;
+const SIGTRAP: &str = "S05";
+const SIGINT: &str = "S02";
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.mcc.v3.core">
+    <reg name="ip" bitsize="8" type="code_ptr" regnum="0"/>
+    <reg name="dp" bitsize="8" type="data_ptr" regnum="1"/>
+    <reg name="sp" bitsize="8" type="uint8" regnum="2"/>
+  </feature>
+</target>"#;
+
+pub enum Action {
+    Reply(String),
+    ///Run until a breakpoint, the end of the program or an interrupt from the client
+    Continue,
+    Close,
+}
+
+///Memory addresses are nibble addresses and every nibble is transferred as 1 byte
+pub struct GdbStub {
+    no_ack: bool,
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn parse_hex(str: &str) -> Option<usize> {
+    usize::from_str_radix(str, 16).ok()
+}
+
+fn decode_hex(str: &str) -> Option<Vec<u8>> {
+    if !str.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..str.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(str.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_range(str: &str) -> Option<(u8, usize)> {
+    let (addr, len) = str.split_once(',')?;
+    let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+    if addr + len > 256 {
+        return None;
+    }
+    Some((addr as u8, len))
+}
+
+impl Default for GdbStub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        Self { no_ack: false }
+    }
+
+    ///The reply for the client when the vm stopped
+    pub fn stop_reply(session: &Session) -> String {
+        if session.is_running() {
+            SIGTRAP.into()
+        } else {
+            format!("W{:02x}", session.emulator.stack_peek().into_low())
+        }
+    }
+
+    fn read_register(session: &Session, regnum: usize) -> Option<u8> {
+        let emulator = &session.emulator;
+        match regnum {
+            0 => Some(emulator.ip()),
+            1 => Some(emulator.dp()),
+            2 => Some(emulator.sp().into_low()),
+            _ => None,
+        }
+    }
+
+    fn write_register(session: &mut Session, regnum: usize, value: u8) -> bool {
+        let emulator = &mut session.emulator;
+        match regnum {
+            0 => emulator.set_ip(value),
+            1 => emulator.set_dp(value),
+            2 => emulator.set_sp(u4::from_low(value)),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn handle_packet(&mut self, packet: &str, session: &mut Session) -> Action {
+        let reply = |str: &str| Action::Reply(str.to_string());
+        let error = || reply("E01");
+        let (command, args) = packet.split_at(packet.len().min(1));
+        match command {
+            "?" => Action::Reply(Self::stop_reply(session)),
+            "g" => Action::Reply(
+                (0..REGISTER_COUNT)
+                    .filter_map(|regnum| Self::read_register(session, regnum))
+                    .map(|value| format!("{:02x}", value))
+                    .collect(),
+            ),
+            "G" => match decode_hex(args) {
+                Some(values) if values.len() == REGISTER_COUNT => {
+                    for (regnum, value) in values.into_iter().enumerate() {
+                        Self::write_register(session, regnum, value);
+                    }
+                    reply("OK")
+                }
+                _ => error(),
+            },
+            "p" => match parse_hex(args).and_then(|regnum| Self::read_register(session, regnum)) {
+                Some(value) => Action::Reply(format!("{:02x}", value)),
+                None => error(),
+            },
+            "P" => {
+                let Some((regnum, value)) = args.split_once('=') else {
+                    return error();
+                };
+                match (parse_hex(regnum), decode_hex(value).as_deref()) {
+                    (Some(regnum), Some([value]))
+                        if Self::write_register(session, regnum, *value) =>
+                    {
+                        reply("OK")
+                    }
+                    _ => error(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => Action::Reply(
+                    (0..len)
+                        .map(|i| {
+                            let nib = session.emulator.ghost_read_mem(addr + i as u8);
+                            format!("{:02x}", nib.into_low())
+                        })
+                        .collect(),
+                ),
+                None => error(),
+            },
+            "M" => {
+                let Some((range, data)) = args.split_once(':') else {
+                    return error();
+                };
+                // every cell holds a nibble, a byte above 0xf would lose its high nibble
+                match (parse_range(range), decode_hex(data)) {
+                    (Some((addr, len)), Some(data))
+                        if data.len() == len && data.iter().all(|byte| *byte <= 0xF) =>
+                    {
+                        for (i, byte) in data.into_iter().enumerate() {
+                            session.emulator.mem[addr as usize + i] = u4::from_low(byte);
+                        }
+                        reply("OK")
+                    }
+                    _ => error(),
+                }
+            }
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let (Some("0"), Some(addr)) = (parts.next(), parts.next().and_then(parse_hex))
+                else {
+                    // only software breakpoints are supported
+                    return reply("");
+                };
+                let Ok(addr) = u8::try_from(addr) else {
+                    return error();
+                };
+                if command == "Z" {
+                    session.debugger.breakpoints.insert(addr);
+                } else {
+                    session.debugger.breakpoints.remove(&addr);
+                }
+                reply("OK")
+            }
+            "s" => {
+                if session.is_running() {
+                    if let Err(err) = session.tick() {
+                        return Action::Reply(format!("E.{}", err));
+                    }
+                }
+                Action::Reply(Self::stop_reply(session))
+            }
+            "c" => Action::Continue,
+            "k" => Action::Close,
+            "D" => {
+                // the reply is sent before the connection is closed
+                reply("OK")
+            }
+            "H" => reply("OK"),
+            "q" | "Q" => self.handle_query(packet),
+            _ => reply(""),
+        }
+    }
+
+    fn handle_query(&mut self, packet: &str) -> Action {
+        let reply = |str: &str| Action::Reply(str.to_string());
+        if packet.starts_with("qSupported") {
+            return reply("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+");
+        }
+        if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            return reply("OK");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = range
+                .split_once(',')
+                .and_then(|(offset, len)| Some((parse_hex(offset)?, parse_hex(len)?)))
+            else {
+                return reply("E01");
+            };
+            let start = offset.min(TARGET_XML.len());
+            let end = (offset + len).min(TARGET_XML.len());
+            let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+            return Action::Reply(format!("{}{}", marker, &TARGET_XML[start..end]));
+        }
+        match packet {
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            _ => reply(""),
+        }
+    }
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+    Closed,
+}
+
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut buf = [0];
+    match stream.read(&mut buf)? {
+        0 => Ok(None),
+        _ => Ok(Some(buf[0])),
+    }
+}
+
+fn read_incoming(stream: &mut TcpStream, no_ack: bool) -> io::Result<Incoming> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(Incoming::Closed),
+            Some(0x03) => return Ok(Incoming::Interrupt),
+            Some(b'$') => {}
+            // acks and anything else between packets
+            Some(_) => continue,
+        }
+        let mut packet = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(Incoming::Closed),
+                Some(b'#') => break,
+                Some(byte) => packet.push(byte),
+            }
+        }
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum)?;
+        let packet = String::from_utf8_lossy(&packet).into_owned();
+        let valid = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+            == Some(checksum(&packet));
+        if !no_ack {
+            stream.write_all(if valid { b"+" } else { b"-" })?;
+        }
+        if valid {
+            return Ok(Incoming::Packet(packet));
+        }
+    }
+}
+
+fn send(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    write!(stream, "${}#{:02x}", data, checksum(data))?;
+    stream.flush()
+}
+
+///Returns true when the client sent an interrupt
+fn poll_interrupt(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut buf = [0];
+    let result = match stream.read(&mut buf) {
+        Ok(1) => Ok(buf[0] == 0x03),
+        Ok(_) => Ok(false),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(err) => Err(err),
+    };
+    stream.set_nonblocking(false)?;
+    result
+}
+
+fn run(stream: &mut TcpStream, session: &mut Session) -> io::Result<&'static str> {
+    // step off a breakpoint we are stopped on
+    let mut first = true;
+    while session.is_running() {
+        if !first
+            && session
+                .debugger
+                .breakpoints
+                .contains(&session.emulator.ip())
+        {
+            return Ok(SIGTRAP);
+        }
+        first = false;
+        session.tick()?;
+        if session.debugger.should_break(&session.emulator) && session.is_running() {
+            return Ok(SIGTRAP);
+        }
+        if session
+            .emulator
+            .cycles
+            .is_multiple_of(INTERRUPT_POLL_CYCLES)
+            && poll_interrupt(stream)?
+        {
+            return Ok(SIGINT);
+        }
+    }
+    Ok(SIGTRAP)
+}
+
+///Serves one client until it detaches, kills the vm or disconnects, returns true when the vm was killed
+pub fn serve(mut stream: TcpStream, session: &mut Session) -> io::Result<bool> {
+    let mut stub = GdbStub::new();
+    loop {
+        let packet = match read_incoming(&mut stream, stub.no_ack)? {
+            Incoming::Packet(packet) => packet,
+            Incoming::Interrupt => {
+                send(&mut stream, SIGINT)?;
+                continue;
+            }
+            Incoming::Closed => return Ok(false),
+        };
+        match stub.handle_packet(&packet, session) {
+            Action::Reply(reply) => {
+                send(&mut stream, &reply)?;
+                if packet == "D" {
+                    return Ok(false);
+                }
+            }
+            Action::Continue => {
+                let signal = run(&mut stream, session)?;
+                if session.is_running() {
+                    send(&mut stream, signal)?;
+                } else {
+                    send(&mut stream, &GdbStub::stop_reply(session))?;
+                }
+            }
+            Action::Close => return Ok(true),
+        }
+    }
+}
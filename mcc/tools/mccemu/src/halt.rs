@@ -0,0 +1,80 @@
+use std::{collections::HashMap, fmt::Display};
+
+use libmcc::u4;
+
+use crate::emulator::Emulator;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HaltReason {
+    ///ip reached 0xFF
+    End,
+    MaxCycles,
+    Address(u8),
+    ///A jump landed in a loop that can never change the machine state
+    SelfJump,
+    Extension,
+    ///`--strict` caught the program doing something suspicious
+    Strict,
+}
+impl Display for HaltReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltReason::End => f.write_str("end"),
+            HaltReason::MaxCycles => f.write_str("max_cycles"),
+            HaltReason::Address(addr) => write!(f, "address {:#04x}", addr),
+            HaltReason::SelfJump => f.write_str("self_jump"),
+            HaltReason::Extension => f.write_str("extension"),
+            HaltReason::Strict => f.write_str("strict"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HaltConditions {
+    pub max_cycles: Option<u64>,
+    pub addrs: Vec<u8>,
+    pub self_jump: bool,
+    ///Memory at the last time a jump landed on an address
+    jump_states: HashMap<u8, [u4; 256]>,
+}
+
+impl HaltConditions {
+    pub fn new(max_cycles: Option<u64>, addrs: Vec<u8>, self_jump: bool) -> Self {
+        Self {
+            max_cycles,
+            addrs,
+            self_jump,
+            jump_states: HashMap::new(),
+        }
+    }
+
+    ///Checks the state after a tick, `ip` is the address of the instruction that was executed
+    pub fn check(&mut self, emulator: &Emulator, ip: u8) -> Option<HaltReason> {
+        if !emulator.is_running {
+            return Some(HaltReason::End);
+        }
+        if self
+            .max_cycles
+            .is_some_and(|max_cycles| emulator.cycles >= max_cycles)
+        {
+            return Some(HaltReason::MaxCycles);
+        }
+        let next_ip = emulator.ip();
+        if self.addrs.contains(&next_ip) {
+            return Some(HaltReason::Address(next_ip));
+        }
+        if self.self_jump {
+            // input from an extension can change what the loop does next time
+            if emulator.accesses().iter().any(|access| access.ext) {
+                self.jump_states.clear();
+            }
+            if next_ip != ip.wrapping_add(1) {
+                if self.jump_states.get(&next_ip) == Some(&emulator.mem) {
+                    return Some(HaltReason::SelfJump);
+                }
+                self.jump_states.insert(next_ip, emulator.mem);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,39 @@
+use std::rc::Rc;
+
+use emulator::Emulator;
+use ext::ExtManager;
+use libmcc::u4;
+
+pub mod asm;
+pub mod cli;
+pub mod dap;
+pub mod debugger;
+pub mod emulator;
+pub mod equiv;
+pub mod expr;
+pub mod ext;
+pub mod fuzz;
+pub mod gdb;
+pub mod halt;
+pub mod profile;
+pub mod prop;
+pub mod session;
+pub mod state;
+pub mod strict;
+pub mod superopt;
+pub mod symbolic;
+pub mod symbols;
+pub mod trace;
+pub mod tui;
+
+///Creates an emulator that forwards all memory accesses to the extensions
+pub fn new_emulator(mem: [u4; 256], extmgr: Rc<ExtManager>) -> Emulator {
+    Emulator::new(mem, move |addr, value, write, emulator| {
+        if write {
+            extmgr.on_mem_write(addr, value, emulator);
+            None
+        } else {
+            extmgr.on_mem_read(addr, emulator)
+        }
+    })
+}
@@ -1,15 +1,28 @@
 use std::{
-    fs,
-    io::{self, Read},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    net::TcpListener,
     process,
+    rc::Rc,
 };
 
-use clap::Parser;
-use emulator::Emulator;
-use libmcc::{u4, v3::Instruction};
-
-mod emulator;
-mod ext;
+use clap::{Parser, ValueEnum};
+use console::Term;
+use libmcc::v3::{from_bin_packed, Instruction};
+use mccemu::{
+    dap,
+    debugger::DEFAULT_HISTORY,
+    emulator::Emulator,
+    ext::{self, chardev::SharedBuf},
+    gdb,
+    halt::{HaltConditions, HaltReason},
+    profile::Profile,
+    session::Session,
+    state::Snapshot,
+    strict::Strict,
+    symbols::{LineMap, SymbolMap},
+    tui::Tui,
+};
 
 #[derive(Parser)]
 #[command(author, version)]
@@ -31,9 +44,119 @@ struct Cli {
     #[arg(short = 'x', long)]
     ext: Vec<ext::ExtType>,
 
+    #[command(flatten)]
+    chardev: ext::chardev::ChardevArgs,
+
     ///Print the top of the stack when the vm exits
     #[arg(short = 'p', long)]
     print: bool,
+
+    ///Record every executed instruction to a file
+    #[arg(short = 't', long, value_name = "FILE")]
+    trace: Option<String>,
+
+    ///Count executed instructions and memory accesses and print a report when the vm exits
+    #[arg(long)]
+    profile: bool,
+
+    ///Symbol map written by mccasm --symbols
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<String>,
+
+    ///Line map written by mccasm --lines (tells --strict which cells the assembler initialized)
+    #[arg(long, value_name = "FILE")]
+    lines: Option<String>,
+
+    ///Stop on stack underflow/overflow, dp wrapping, writes to the register cells through poi/pod,
+    ///reads of uninitialized cells and executing data (needs --symbols and --lines for the last two)
+    #[arg(long)]
+    strict: bool,
+
+    ///Stop the vm after this many cycles (exits with 124)
+    #[arg(long, value_name = "N")]
+    max_cycles: Option<u64>,
+
+    ///Stop the vm when ip reaches this address
+    #[arg(long, value_name = "ADDR", value_parser = parse_addr)]
+    halt_at: Vec<u8>,
+
+    ///Stop the vm when a jump lands in a loop that can't change the machine state anymore (like a jmp to itself)
+    #[arg(long)]
+    halt_on_self_jump: bool,
+
+    ///Use the top of the stack or the value at an address as exit code
+    #[arg(long, value_name = "stack|ADDR", value_parser = parse_exit_code)]
+    exit_code: Option<ExitCode>,
+
+    ///Print the registers, stack, memory and cycle count when the vm exits
+    #[arg(long, value_name = "FORMAT")]
+    dump_state: Option<DumpFormat>,
+
+    ///Break when ip reaches this address
+    #[arg(long = "break", value_name = "ADDR", value_parser = parse_addr)]
+    breakpoints: Vec<u8>,
+
+    ///Break after an instruction writes to this address
+    #[arg(long = "watch", value_name = "ADDR", value_parser = parse_addr)]
+    watches: Vec<u8>,
+
+    ///Amount of cycles that can be stepped back while debugging
+    ///[default: 10000 when stepping, breaking or debugging, 0 otherwise]
+    #[arg(long, value_name = "N")]
+    history: Option<usize>,
+
+    ///Continue from a state written by --save-state or --dump-state json instead of loading an input
+    #[arg(long, value_name = "FILE")]
+    load_state: Option<String>,
+
+    ///Save the state to a file when the vm exits
+    #[arg(long, value_name = "FILE")]
+    save_state: Option<String>,
+
+    ///Full screen terminal interface
+    #[arg(long, conflicts_with_all = ["step", "nop_break"])]
+    tui: bool,
+
+    ///Wait for a gdb client on this address (like 127.0.0.1:1234) before running
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["step", "nop_break", "tui"])]
+    gdb: Option<String>,
+
+    ///Speak the Debug Adapter Protocol on stdin and stdout, the program is chosen by the launch request
+    #[arg(long, conflicts_with_all = ["step", "nop_break", "tui", "gdb"])]
+    dap: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum DumpFormat {
+    Json,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ExitCode {
+    StackTop,
+    Mem(u8),
+}
+impl ExitCode {
+    fn get(self, emulator: &Emulator) -> i32 {
+        match self {
+            ExitCode::StackTop => emulator.stack_peek().into_low() as i32,
+            ExitCode::Mem(addr) => emulator.ghost_read_mem(addr).into_low() as i32,
+        }
+    }
+}
+
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+fn parse_addr(str: &str) -> Result<u8, String> {
+    u8::from_str_radix(str.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("'{}' is not a hex address", str))
+}
+fn parse_exit_code(str: &str) -> Result<ExitCode, String> {
+    if str == "stack" {
+        Ok(ExitCode::StackTop)
+    } else {
+        parse_addr(str).map(ExitCode::Mem)
+    }
 }
 
 fn get_input_data(path: &str) -> io::Result<Vec<u8>> {
@@ -43,61 +166,231 @@ fn get_input_data(path: &str) -> io::Result<Vec<u8>> {
         stdin.read_to_end(&mut buf)?;
         Ok(buf)
     } else {
-        let can_path = std::fs::canonicalize(&path)?;
+        let can_path = std::fs::canonicalize(path)?;
         let vec = fs::read(can_path)?;
         Ok(vec)
     }
 }
-fn die(message: &str) {
+fn die(message: &str) -> ! {
     eprintln!("FATAL: {}", message);
     process::exit(-1);
 }
-fn from_bin_packed(data: Vec<u8>) -> [u4; 256] {
-    let mut out = [u4::ZERO; 256];
-    let mut count = 0;
-    for byte in data.into_iter() {
-        out[count] = u4::from_low(byte);
-        out[count + 1] = u4::from_high(byte);
-        count += 2;
+fn print_state(emulator: &Emulator, instruct: Option<Instruction>) {
+    println!("VM BREAK");
+    if let Some(instruct) = instruct {
+        println!("instruct: {:#03x} {:?}", instruct.into_u4(), instruct);
+    }
+    println!("cycle: {}", emulator.cycles);
+    println!("ip: {:#04x}", emulator.ip());
+    println!("dp: {:#04x}", emulator.dp());
+    println!("stack {:#03x}:", emulator.sp().into_low());
+    for nib in emulator.stack() {
+        println!("   {:#03x}", nib);
     }
+}
+
+const DEBUG_HELP: &str = "\
+return         step forward
+r              continue execution
+back [N]       step back N cycles (default 1)
+rc             step back to the previous breakpoint or write to a watched cell
+b ADDR         toggle a breakpoint
+w ADDR         toggle a watch on a memory cell
+save FILE      save the state to a file
+load FILE      load a state from a file";
 
-    out
+///Returns false when execution should continue without stepping
+fn debug_prompt(session: &mut Session, instruct: Option<Instruction>) -> bool {
+    print_state(&session.emulator, instruct);
+    loop {
+        println!("Press return to step forward or r to continue execution (h for more commands)");
+        let mut buf = String::new();
+        if std::io::stdin().read_line(&mut buf).unwrap() == 0 {
+            // nobody left to type commands
+            return false;
+        }
+        let buf = buf.to_lowercase();
+        let mut args = buf.split_whitespace();
+        let command = args.next().unwrap_or("");
+        let arg = args.next();
+        match command {
+            "" => return true,
+            "r" => return false,
+            "h" => println!("{}", DEBUG_HELP),
+            "back" | "rc" => {
+                let undone = if command == "rc" {
+                    session.reverse_continue()
+                } else {
+                    match arg.map(|arg| arg.parse::<usize>()).unwrap_or(Ok(1)) {
+                        Ok(count) => session.back(count),
+                        Err(err) => {
+                            println!("{}", err);
+                            continue;
+                        }
+                    }
+                };
+                println!("stepped back {} cycles", undone);
+                print_state(&session.emulator, None);
+            }
+            "b" | "w" => {
+                let addr = match arg.map(parse_addr) {
+                    Some(Ok(addr)) => addr,
+                    Some(Err(err)) => {
+                        println!("{}", err);
+                        continue;
+                    }
+                    None => {
+                        println!("missing address");
+                        continue;
+                    }
+                };
+                let (set, name) = if command == "b" {
+                    (&mut session.debugger.breakpoints, "breakpoint")
+                } else {
+                    (&mut session.debugger.watches, "watch")
+                };
+                if set.remove(&addr) {
+                    println!("removed {} {:#04x}", name, addr);
+                } else {
+                    set.insert(addr);
+                    println!("added {} {:#04x}", name, addr);
+                }
+            }
+            "save" | "load" => {
+                let Some(path) = arg else {
+                    println!("missing file");
+                    continue;
+                };
+                if command == "save" {
+                    if let Err(err) = session.snapshot().save(path, session.halt_reason) {
+                        println!("Failed to save state\n{}", err);
+                    }
+                } else {
+                    match Snapshot::load(path) {
+                        Ok(snapshot) => {
+                            session.restore(&snapshot);
+                            print_state(&session.emulator, None);
+                        }
+                        Err(err) => println!("Failed to load state\n{}", err),
+                    }
+                }
+            }
+            _ => println!("unknown command '{}' (h for help)", command),
+        }
+    }
 }
 
 fn main() {
     let mut cli = Cli::parse();
-    let input_data = get_input_data(&cli.input).unwrap_or_else(|err: io::Error| {
-        die(&format!(
-            "Failed to read input '{}'\n{}",
-            cli.input,
-            &err.to_string()
-        ));
-        Vec::new()
-    });
-    if input_data.len() != 128 {
-        die("Input data was not the size of the memory (128 bytes). Are you sure your data isn't in ubin or hex format?")
-    }
-
-    let memory = from_bin_packed(input_data);
-
-    let extmgr = ext::ExtManager::new(cli.ext);
-    let mut emulator = Emulator::new(memory, move |addr, value, write, emulator| {
-        if write {
-            extmgr.on_mem_write(addr, value, emulator);
-            None
-        } else {
-            extmgr.on_mem_read(addr, emulator)
+
+    if cli.dap {
+        dap::serve(io::BufReader::new(io::stdin()), io::stdout()).unwrap_or_else(|err| {
+            die(&format!("Debug adapter failed\n{}", err));
+        });
+        return;
+    }
+
+    let tui_output = SharedBuf::default();
+    let chardev_output = cli
+        .tui
+        .then(|| Box::new(tui_output.clone()) as Box<dyn Write>);
+    let extmgr = match ext::ExtManager::new(cli.ext, &cli.chardev, chardev_output) {
+        Ok(extmgr) => Rc::new(extmgr),
+        Err(err) => die(&format!("Failed to load extensions\n{}", err)),
+    };
+
+    let emulator = if let Some(path) = &cli.load_state {
+        let snapshot = match Snapshot::load(path) {
+            Ok(snapshot) => snapshot,
+            Err(err) => die(&format!("Failed to load state '{}'\n{}", path, err)),
+        };
+        let mut emulator = mccemu::new_emulator(snapshot.mem, extmgr.clone());
+        snapshot.restore(&mut emulator, &extmgr);
+        emulator
+    } else {
+        let input_data = get_input_data(&cli.input).unwrap_or_else(|err: io::Error| {
+            die(&format!(
+                "Failed to read input '{}'\n{}",
+                cli.input,
+                &err.to_string()
+            ))
+        });
+        let memory = from_bin_packed(&input_data).unwrap_or_else(|| {
+            die("Input data was not the size of the memory (128 bytes). Are you sure your data isn't in ubin or hex format?")
+        });
+        let mut emulator = mccemu::new_emulator(memory, extmgr.clone());
+        emulator.start();
+        emulator
+    };
+
+    let mut session = Session::new(emulator, extmgr);
+    session.trace = match cli.trace.as_ref().map(File::create).transpose() {
+        Ok(file) => file.map(|file| Box::new(BufWriter::new(file)) as Box<dyn Write>),
+        Err(err) => die(&format!("Failed to create trace file\n{}", err)),
+    };
+
+    let symbols = match cli.symbols.as_deref().map(SymbolMap::load).transpose() {
+        Ok(symbols) => symbols,
+        Err(err) => die(&format!("Failed to read symbols\n{}", err)),
+    };
+    let lines = match cli.lines.as_deref().map(LineMap::load).transpose() {
+        Ok(lines) => lines,
+        Err(err) => die(&format!("Failed to read lines\n{}", err)),
+    };
+    session.strict = cli
+        .strict
+        .then(|| Strict::new(symbols.as_ref(), lines.as_ref()));
+    session.profile = cli.profile.then(Profile::default);
+    session.halt = HaltConditions::new(cli.max_cycles, cli.halt_at, cli.halt_on_self_jump);
+    let debugging = cli.step
+        || cli.nop_break
+        || cli.tui
+        || cli.gdb.is_some()
+        || !cli.breakpoints.is_empty()
+        || !cli.watches.is_empty();
+    session.set_history(cli.history.unwrap_or(match debugging {
+        true => DEFAULT_HISTORY,
+        false => 0,
+    }));
+    session.debugger.breakpoints.extend(cli.breakpoints);
+    session.debugger.watches.extend(cli.watches);
+
+    if cli.tui {
+        let term = Term::stdout();
+        if !term.is_term() {
+            die("--tui needs a terminal");
         }
-    });
+        Tui::new(tui_output)
+            .run(&term, &mut session, symbols.as_ref())
+            .unwrap_or_else(|err| {
+                die(&format!("Terminal error\n{}", err));
+            });
+    }
 
-    emulator.start();
+    if let Some(addr) = &cli.gdb {
+        let listener = match TcpListener::bind(addr) {
+            Ok(listener) => listener,
+            Err(err) => die(&format!("Failed to listen on {}\n{}", addr, err)),
+        };
+        if let Ok(addr) = listener.local_addr() {
+            eprintln!("gdb: listening on {}", addr);
+        }
+        let killed = listener
+            .accept()
+            .and_then(|(stream, _)| gdb::serve(stream, &mut session))
+            .unwrap_or_else(|err| die(&format!("gdb connection failed\n{}", err)));
+        if killed {
+            return;
+        }
+    }
 
     let mut last_was_nop = false;
     loop {
-        if !emulator.is_running {
+        if cli.tui || !session.is_running() {
             break;
         }
-        let instruct = emulator.tick();
+        let instruct = session.tick().unwrap_or_else(|err| die(&err.to_string()));
+        let emulator = &session.emulator;
         if instruct == Some(Instruction::Nop) {
             if !last_was_nop
                 && cli.nop_break
@@ -109,30 +402,48 @@ fn main() {
         } else {
             last_was_nop = false;
         }
+        if session.debugger.should_break(emulator) {
+            cli.step = true;
+        }
 
         if cli.step {
-            println!("VM BREAK");
-            if let Some(instruct) = instruct {
-                println!("instruct: {:#03x} {:?}", instruct.into_u4(), instruct);
-            }
-            println!("ip: {:#04x}", emulator.ip());
-            println!("dp: {:#04x}", emulator.dp());
-            println!("stack {:#03x}:", emulator.sp().into_low());
-            for i in
-                (emulator::STACK_START + 1)..emulator::STACK_START + emulator.sp().into_low() + 1
-            {
-                println!("   {:#03x}", emulator.read_mem(i));
-            }
-            println!("Press return to step forward or r to continue execution");
-            let mut buf = String::new();
-            std::io::stdin().read_line(&mut buf).unwrap();
-            if buf.to_lowercase().trim() == "r" {
-                cli.step = false;
-            }
+            cli.step = debug_prompt(&mut session, instruct);
         }
     }
+    if let Some(mut trace) = session.trace.take() {
+        trace.flush().unwrap_or_else(|err| {
+            die(&format!("Failed to write trace\n{}", err));
+        });
+    }
+    if let Some(profile) = &session.profile {
+        println!("VM PROFILE");
+        print!("{}", profile.report(symbols.as_ref()));
+    }
+    let emulator = &session.emulator;
     if cli.print {
         println!("VM EXIT");
-        println!("stack top was {:#03x}", emulator.stack_pop());
+        println!("stack top was {:#03x}", emulator.stack_peek());
+    }
+    if cli.dump_state.is_some() || cli.save_state.is_some() {
+        let snapshot = session.snapshot();
+        if let Some(DumpFormat::Json) = cli.dump_state {
+            println!("{}", snapshot.to_json(session.halt_reason));
+        }
+        if let Some(path) = &cli.save_state {
+            snapshot
+                .save(path, session.halt_reason)
+                .unwrap_or_else(|err| {
+                    die(&format!("Failed to save state\n{}", err));
+                });
+        }
+    }
+    if let Some(trap) = session.strict.as_ref().and_then(|strict| strict.trap) {
+        die(&format!("strict: {}", trap));
+    }
+    if session.halt_reason == Some(HaltReason::MaxCycles) {
+        process::exit(TIMEOUT_EXIT_CODE);
+    }
+    if let Some(exit_code) = cli.exit_code {
+        process::exit(exit_code.get(emulator));
     }
 }
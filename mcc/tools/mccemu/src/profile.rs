@@ -0,0 +1,129 @@
+use std::fmt::{self, Display, Write};
+
+use libmcc::v3::Instruction;
+
+use crate::{
+    emulator::{AccessKind, Emulator},
+    symbols::SymbolMap,
+};
+
+///Execution and access counts collected while the vm runs
+pub struct Profile {
+    pub executed: [u64; 256],
+    pub reads: [u64; 256],
+    pub writes: [u64; 256],
+    pub opcodes: [u64; 16],
+    pub cycles: u64,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            executed: [0; 256],
+            reads: [0; 256],
+            writes: [0; 256],
+            opcodes: [0; 16],
+            cycles: 0,
+        }
+    }
+}
+
+impl Profile {
+    ///Counts the memory accesses of the last tick
+    pub fn record(&mut self, emulator: &Emulator) {
+        self.cycles = emulator.cycles;
+        for access in emulator.accesses().iter() {
+            let addr = access.addr as usize;
+            match access.kind {
+                AccessKind::Fetch => {
+                    self.executed[addr] += 1;
+                    self.opcodes[access.value.into_usize()] += 1;
+                }
+                AccessKind::Read => self.reads[addr] += 1,
+                AccessKind::Write => self.writes[addr] += 1,
+            }
+        }
+    }
+
+    pub fn report<'a>(&'a self, symbols: Option<&'a SymbolMap>) -> Report<'a> {
+        Report {
+            profile: self,
+            symbols,
+        }
+    }
+}
+
+pub struct Report<'a> {
+    profile: &'a Profile,
+    symbols: Option<&'a SymbolMap>,
+}
+
+const HOTTEST_COUNT: usize = 10;
+
+fn write_grid(f: &mut fmt::Formatter<'_>, title: &str, counts: &[u64; 256]) -> fmt::Result {
+    writeln!(f, "{}:", title)?;
+    f.write_str("    ")?;
+    for col in 0..16 {
+        write!(f, "{:>5x}", col)?;
+    }
+    f.write_char('\n')?;
+    for row in 0..16 {
+        write!(f, "  {:x}0", row)?;
+        for count in &counts[row * 16..row * 16 + 16] {
+            if *count == 0 {
+                write!(f, "{:>5}", ".")?;
+            } else {
+                write!(f, "{:>5}", count)?;
+            }
+        }
+        f.write_char('\n')?;
+    }
+    Ok(())
+}
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let profile = self.profile;
+        writeln!(f, "total cycles: {}", profile.cycles)?;
+
+        writeln!(f, "\nopcodes:")?;
+        let mut opcodes: Vec<_> = (0..16)
+            .filter(|opcode| profile.opcodes[*opcode] != 0)
+            .collect();
+        opcodes.sort_by_key(|opcode| std::cmp::Reverse(profile.opcodes[*opcode]));
+        for opcode in opcodes {
+            let count = profile.opcodes[opcode];
+            writeln!(
+                f,
+                "  {:<4} {:>8} {:>6.1}%",
+                Instruction::from_u4(libmcc::u4::from_low(opcode as u8)).as_str(),
+                count,
+                count as f64 * 100.0 / profile.cycles.max(1) as f64
+            )?;
+        }
+
+        writeln!(f, "\nhottest addresses:")?;
+        let mut addrs: Vec<_> = (0..256)
+            .filter(|addr| profile.executed[*addr] != 0)
+            .collect();
+        addrs.sort_by_key(|addr| std::cmp::Reverse(profile.executed[*addr]));
+        for addr in addrs.into_iter().take(HOTTEST_COUNT) {
+            let symbol = self
+                .symbols
+                .and_then(|symbols| symbols.describe(addr as u8))
+                .unwrap_or_default();
+            writeln!(
+                f,
+                "  {:#04x} {:<16} {:>8}",
+                addr, symbol, profile.executed[addr]
+            )?;
+        }
+
+        f.write_char('\n')?;
+        write_grid(f, "executed", &profile.executed)?;
+        f.write_char('\n')?;
+        write_grid(f, "read", &profile.reads)?;
+        f.write_char('\n')?;
+        write_grid(f, "written", &profile.writes)
+    }
+}
@@ -0,0 +1,193 @@
+use std::{collections::VecDeque, fmt::Display};
+
+use libmcc::u4;
+
+use crate::{emulator::Emulator, fuzz::Rng, trace::TraceRecord};
+
+///Executed instructions kept for a failure
+const TRACE_TAIL: usize = 8;
+
+///Where a routine leaves a result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    StackTop,
+    Cell(u8),
+}
+impl Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Output::StackTop => f.write_str("stack_top"),
+            Output::Cell(addr) => write!(f, "mem[{:#04x}]", addr),
+        }
+    }
+}
+
+///The result of running the image with one set of inputs
+#[derive(Debug, Clone)]
+pub struct Run {
+    ///Err when the vm didn't halt
+    pub outputs: Result<Vec<u4>, String>,
+    pub cycles: u64,
+    ///The last executed instructions in the format of `mccemu --trace`
+    pub trace: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    ///Names and values of the inputs
+    pub inputs: Vec<(Box<str>, u4)>,
+    pub outputs: Vec<Output>,
+    pub expected: Vec<u4>,
+    pub actual: Result<Vec<u4>, String>,
+    pub trace: Vec<String>,
+}
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inputs: Vec<_> = self
+            .inputs
+            .iter()
+            .map(|(name, value)| format!("{}={:#03x}", name, value))
+            .collect();
+        writeln!(f, "inputs: {}", inputs.join(" "))?;
+        match &self.actual {
+            Ok(actual) => {
+                for ((output, expected), actual) in
+                    self.outputs.iter().zip(&self.expected).zip(actual)
+                {
+                    let marker = if expected == actual { "" } else { " <-" };
+                    writeln!(
+                        f,
+                        "{}: expected {:#03x} got {:#03x}{}",
+                        output, expected, actual, marker
+                    )?;
+                }
+            }
+            Err(err) => writeln!(f, "{}", err)?,
+        }
+        writeln!(f, "trace:")?;
+        for line in self.trace.iter() {
+            writeln!(f, "    {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+///How many input combinations passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub cases: usize,
+    ///Every combination was checked, otherwise they were sampled
+    pub exhaustive: bool,
+}
+
+///Runs an image with values written into input cells and compares its outputs with a reference
+#[derive(Clone)]
+pub struct Property {
+    pub image: [u4; 256],
+    ///Named nibble cells that get a value before the vm starts
+    pub inputs: Vec<(Box<str>, u8)>,
+    pub outputs: Vec<Output>,
+    ///Stop when ip reaches this address instead of at the end of memory
+    pub halt_at: Option<u8>,
+    pub max_cycles: u64,
+    ///Instructions the symbolic engine runs over all paths before an equivalence check gives up on it
+    pub max_steps: usize,
+    ///Inputs are sampled when there are more combinations than this
+    pub max_cases: usize,
+    pub seed: u64,
+}
+
+impl Property {
+    pub fn new(image: [u4; 256]) -> Self {
+        Self {
+            image,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            halt_at: None,
+            max_cycles: 100000,
+            max_steps: 1 << 20,
+            max_cases: 1 << 16,
+            seed: 1,
+        }
+    }
+
+    pub fn run(&self, values: &[u4]) -> Run {
+        let mut image = self.image;
+        for ((_, addr), value) in self.inputs.iter().zip(values) {
+            image[*addr as usize] = *value;
+        }
+        let mut emulator = Emulator::new(image, |_, _, _, _| None);
+        emulator.start();
+
+        let mut trace = VecDeque::with_capacity(TRACE_TAIL);
+        while emulator.is_running && emulator.cycles < self.max_cycles {
+            let ip = emulator.ip();
+            if self.halt_at == Some(ip) {
+                break;
+            }
+            let Some(instruct) = emulator.tick() else {
+                break;
+            };
+            if trace.len() == TRACE_TAIL {
+                trace.pop_front();
+            }
+            trace.push_back(TraceRecord::capture(&emulator, ip, instruct).to_string());
+        }
+
+        let halted = !emulator.is_running || self.halt_at == Some(emulator.ip());
+        let outputs = if halted {
+            Ok(self
+                .outputs
+                .iter()
+                .map(|output| match output {
+                    Output::StackTop => emulator.stack_peek(),
+                    Output::Cell(addr) => emulator.ghost_read_mem(*addr),
+                })
+                .collect())
+        } else {
+            Err(format!("did not halt within {} cycles", self.max_cycles))
+        };
+        Run {
+            outputs,
+            cycles: emulator.cycles,
+            trace: trace.into(),
+        }
+    }
+
+    ///Checks every combination of input values or `max_cases` random ones,
+    ///`reference` gets the values in the order of `inputs` and returns the expected outputs
+    pub fn check(&self, reference: impl Fn(&[u4]) -> Vec<u4>) -> Result<Report, Box<Failure>> {
+        let combinations = 16usize
+            .checked_pow(self.inputs.len() as u32)
+            .unwrap_or(usize::MAX);
+        let exhaustive = combinations <= self.max_cases;
+        let cases = combinations.min(self.max_cases);
+        let mut rng = Rng::new(self.seed);
+
+        for case in 0..cases {
+            let values: Vec<u4> = (0..self.inputs.len())
+                .map(|i| match exhaustive {
+                    true => u4::from_low((case >> (i * 4)) as u8),
+                    false => u4::from_low(rng.next_u64() as u8),
+                })
+                .collect();
+            let expected = reference(&values);
+            let run = self.run(&values);
+            if run.outputs.as_ref() != Ok(&expected) {
+                return Err(Box::new(Failure {
+                    inputs: self
+                        .inputs
+                        .iter()
+                        .map(|(name, _)| name.clone())
+                        .zip(values)
+                        .collect(),
+                    outputs: self.outputs.clone(),
+                    expected,
+                    actual: run.outputs,
+                    trace: run.trace,
+                }));
+            }
+        }
+        Ok(Report { cases, exhaustive })
+    }
+}
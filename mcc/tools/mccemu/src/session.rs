@@ -0,0 +1,127 @@
+use std::{io, rc::Rc};
+
+use libmcc::v3::Instruction;
+
+use crate::{
+    debugger::Debugger,
+    emulator::Emulator,
+    ext::ExtManager,
+    halt::{HaltConditions, HaltReason},
+    profile::Profile,
+    state::Snapshot,
+    strict::Strict,
+    trace::TraceRecord,
+};
+
+///An emulator together with everything that has to happen on every tick
+pub struct Session {
+    pub emulator: Emulator,
+    pub extmgr: Rc<ExtManager>,
+    pub debugger: Debugger,
+    pub halt: HaltConditions,
+    pub halt_reason: Option<HaltReason>,
+    pub profile: Option<Profile>,
+    pub trace: Option<Box<dyn io::Write>>,
+    pub strict: Option<Strict>,
+}
+
+impl Session {
+    pub fn new(emulator: Emulator, extmgr: Rc<ExtManager>) -> Self {
+        Self {
+            emulator,
+            extmgr,
+            debugger: Debugger::new(0),
+            halt: HaltConditions::default(),
+            halt_reason: None,
+            profile: None,
+            trace: None,
+            strict: None,
+        }
+    }
+
+    ///Keeps the last `limit` ticks (and the input they read) so they can be stepped back,
+    ///the breakpoints and watches stay
+    pub fn set_history(&mut self, limit: usize) {
+        let mut debugger = Debugger::new(limit);
+        debugger.breakpoints = std::mem::take(&mut self.debugger.breakpoints);
+        debugger.watches = std::mem::take(&mut self.debugger.watches);
+        self.debugger = debugger;
+        self.extmgr.set_history_limit(limit);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.emulator.is_running
+    }
+
+    pub fn tick(&mut self) -> io::Result<Option<Instruction>> {
+        let before = self
+            .debugger
+            .is_recording()
+            .then(|| Snapshot::take(&self.emulator, &self.extmgr));
+        let ip = self.emulator.ip();
+        if let Some(strict) = self.strict.as_mut() {
+            if self.emulator.is_running && !strict.before(&self.emulator) {
+                self.halt_reason = Some(HaltReason::Strict);
+                self.emulator.stop();
+                return Ok(None);
+            }
+        }
+        let instruct = self.emulator.tick();
+        if let (Some(strict), Some(instruct)) = (self.strict.as_mut(), instruct) {
+            strict.after(&self.emulator, ip, instruct);
+        }
+        if self
+            .strict
+            .as_ref()
+            .is_some_and(|strict| strict.trap.is_some())
+        {
+            self.halt_reason = Some(HaltReason::Strict);
+        } else if self.extmgr.is_halted() {
+            self.halt_reason = Some(HaltReason::Extension);
+        } else if let Some(reason) = self.halt.check(&self.emulator, ip) {
+            self.halt_reason = Some(reason);
+        }
+        if self.halt_reason.is_some() {
+            self.emulator.stop();
+        }
+        if let Some(before) = before {
+            self.debugger.record(before, &self.emulator);
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(&self.emulator);
+        }
+        if let (Some(trace), Some(instruct)) = (self.trace.as_mut(), instruct) {
+            writeln!(
+                trace,
+                "{}",
+                TraceRecord::capture(&self.emulator, ip, instruct)
+            )
+            .map_err(|err| io::Error::new(err.kind(), format!("failed to write trace: {}", err)))?;
+        }
+        if let Some(err) = self.extmgr.take_error() {
+            return Err(err);
+        }
+        Ok(instruct)
+    }
+
+    ///Undoes up to `count` ticks and returns how many were undone
+    pub fn back(&mut self, count: usize) -> usize {
+        self.halt_reason = None;
+        self.debugger.back(count, &mut self.emulator, &self.extmgr)
+    }
+
+    pub fn reverse_continue(&mut self) -> usize {
+        self.halt_reason = None;
+        self.debugger
+            .reverse_continue(&mut self.emulator, &self.extmgr)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::take(&self.emulator, &self.extmgr)
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.halt_reason = None;
+        snapshot.restore(&mut self.emulator, &self.extmgr);
+    }
+}
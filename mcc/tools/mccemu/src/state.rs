@@ -0,0 +1,98 @@
+use std::{fs, io};
+
+use libmcc::u4;
+use serde_json::{json, Value};
+
+use crate::{
+    emulator::{Emulator, DP_ADDR0, IP_ADDR0, SP_ADDR, STACK_START},
+    ext::ExtManager,
+    halt::HaltReason,
+};
+
+///Everything needed to continue a vm later
+#[derive(Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub mem: [u4; 256],
+    pub is_running: bool,
+    pub cycles: u64,
+    ///State of every loaded extension in load order
+    pub ext: Vec<Vec<u8>>,
+}
+
+impl Snapshot {
+    pub fn take(emulator: &Emulator, extmgr: &ExtManager) -> Self {
+        Self {
+            mem: emulator.mem,
+            is_running: emulator.is_running,
+            cycles: emulator.cycles,
+            ext: extmgr.save_state(),
+        }
+    }
+
+    pub fn restore(&self, emulator: &mut Emulator, extmgr: &ExtManager) {
+        emulator.mem = self.mem;
+        emulator.is_running = self.is_running;
+        emulator.cycles = self.cycles;
+        extmgr.load_state(&self.ext);
+    }
+
+    fn read_mem8(&self, addr: u8) -> u8 {
+        self.mem[addr as usize].into_low() | self.mem[addr as usize + 1].into_high()
+    }
+
+    ///The format used by --dump-state json and --save-state
+    pub fn to_json(&self, halt_reason: Option<HaltReason>) -> Value {
+        let sp = self.mem[SP_ADDR as usize];
+        let stack_start = STACK_START as usize + 1;
+        json!({
+            "cycles": self.cycles,
+            "running": self.is_running,
+            "halt_reason": halt_reason.map(|reason| reason.to_string()),
+            "ip": self.read_mem8(IP_ADDR0),
+            "dp": self.read_mem8(DP_ADDR0),
+            "sp": sp.into_low(),
+            "stack": self.mem[stack_start..stack_start + sp.into_usize()]
+                .iter()
+                .map(|nib| nib.into_low())
+                .collect::<Vec<_>>(),
+            "mem": self.mem.iter().map(|nib| nib.into_low()).collect::<Vec<_>>(),
+            "ext": self.ext,
+        })
+    }
+
+    ///Only reads the mem, running, cycles and ext fields
+    pub fn from_json(value: &Value) -> Result<Self, String> {
+        let mem_values = value["mem"]
+            .as_array()
+            .filter(|mem| mem.len() == 256)
+            .ok_or("'mem' must be an array of 256 nibbles")?;
+        let mut mem = [u4::ZERO; 256];
+        for (nib, value) in mem.iter_mut().zip(mem_values) {
+            *nib = value
+                .as_u64()
+                .filter(|value| *value <= 0xF)
+                .map(|value| u4::from_low(value as u8))
+                .ok_or("'mem' must be an array of 256 nibbles")?;
+        }
+        let ext = match value.get("ext") {
+            None => Vec::new(),
+            Some(ext) => serde_json::from_value(ext.clone())
+                .map_err(|_| "'ext' must be an array of byte arrays")?,
+        };
+        Ok(Self {
+            mem,
+            is_running: value["running"].as_bool().unwrap_or(true),
+            cycles: value["cycles"].as_u64().unwrap_or(0),
+            ext,
+        })
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let value: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Self::from_json(&value).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: &str, halt_reason: Option<HaltReason>) -> io::Result<()> {
+        fs::write(path, self.to_json(halt_reason).to_string())
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt::Display;
+
+use libmcc::v3::Instruction;
+use mccasm::asm::v3::stack;
+
+use crate::{
+    emulator::{AccessKind, Emulator, REG_END},
+    symbols::{LineMap, SymbolMap},
+};
+
+///Something a correct program shouldn't do
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Violation {
+    ///An instruction pops from an empty stack
+    StackUnderflow,
+    ///A push wraps sp past 0xF
+    StackOverflow,
+    ///dp is moved past 0xFF or below 0x00 (ip can't wrap, the vm halts after the instruction at 0xFF)
+    DpWrap,
+    ///poi/pod writes into a register cell
+    RegisterWrite(u8),
+    ///A cell is read that the program never initialized or wrote
+    Uninitialized(u8),
+    ///ip runs into a cell the symbol map marks as data
+    ExecuteData,
+}
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::StackUnderflow => f.write_str("stack underflow"),
+            Violation::StackOverflow => f.write_str("stack overflow"),
+            Violation::DpWrap => f.write_str("dp wraps around"),
+            Violation::RegisterWrite(addr) => write!(f, "write to register cell {:#04x}", addr),
+            Violation::Uninitialized(addr) => write!(f, "read of uninitialized cell {:#04x}", addr),
+            Violation::ExecuteData => f.write_str("executes data"),
+        }
+    }
+}
+
+///A violation together with where it happened
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Trap {
+    pub violation: Violation,
+    pub cycle: u64,
+    pub ip: u8,
+    pub instruction: Instruction,
+}
+impl Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (cycle={} ip={:02x} inst={})",
+            self.violation,
+            self.cycle,
+            self.ip,
+            self.instruction.as_str()
+        )
+    }
+}
+
+///Checks every tick for things that silently continue on the real machine
+pub struct Strict {
+    ///Cells that were loaded by the assembler or written since
+    initialized: [bool; 256],
+    data: [bool; 256],
+    pub trap: Option<Trap>,
+}
+
+impl Strict {
+    ///Without a line map every cell of the image counts as initialized
+    pub fn new(symbols: Option<&SymbolMap>, lines: Option<&LineMap>) -> Self {
+        let mut initialized = [lines.is_none(); 256];
+        initialized[..REG_END as usize].fill(true);
+        let mut data = [false; 256];
+        for addr in 0..=255u8 {
+            if lines.is_some_and(|lines| lines.line_of(addr).is_some()) {
+                initialized[addr as usize] = true;
+            }
+            data[addr as usize] = symbols.is_some_and(|symbols| symbols.is_data(addr));
+        }
+        Self {
+            initialized,
+            data,
+            trap: None,
+        }
+    }
+
+    fn trap(&mut self, violation: Violation, cycle: u64, ip: u8, instruction: Instruction) {
+        self.trap = Some(Trap {
+            violation,
+            cycle,
+            ip,
+            instruction,
+        });
+    }
+
+    ///Checks the instruction at ip before it runs, returns false when it must not run
+    pub fn before(&mut self, emulator: &Emulator) -> bool {
+        let ip = emulator.ip();
+        let inst = Instruction::from_u4(emulator.ghost_read_mem(ip));
+        let sp = emulator.sp().into_usize();
+        let dp = emulator.dp();
+        // jnz reads the value on top of the stack without popping it
+        let (pops, pushes) = stack::effect(inst);
+
+        use Instruction::*;
+        let violation = if self.data[ip as usize] {
+            Some(Violation::ExecuteData)
+        } else if sp < pops {
+            Some(Violation::StackUnderflow)
+        } else if sp - pops + pushes > 0xF {
+            Some(Violation::StackOverflow)
+        } else if matches!(inst, Poi | Pod) && dp < REG_END {
+            Some(Violation::RegisterWrite(dp))
+        } else if (matches!(inst, Psi | Poi | Di) && dp == 0xFF)
+            || (matches!(inst, Psd | Pod | Dd) && dp == 0x00)
+        {
+            Some(Violation::DpWrap)
+        } else {
+            None
+        };
+        match violation {
+            Some(violation) => {
+                self.trap(violation, emulator.cycles + 1, ip, inst);
+                false
+            }
+            None => true,
+        }
+    }
+
+    ///Checks the memory accesses of the tick that ran the instruction at `ip`
+    pub fn after(&mut self, emulator: &Emulator, ip: u8, inst: Instruction) {
+        for access in emulator.accesses().iter() {
+            match access.kind {
+                AccessKind::Write => self.initialized[access.addr as usize] = true,
+                // extensions supply their own values
+                AccessKind::Read if !access.ext && !self.initialized[access.addr as usize] => {
+                    self.trap(
+                        Violation::Uninitialized(access.addr),
+                        emulator.cycles,
+                        ip,
+                        inst,
+                    );
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+}
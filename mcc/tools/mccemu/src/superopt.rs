@@ -0,0 +1,325 @@
+use std::{collections::HashMap, fmt::Display};
+
+use libmcc::{
+    u4,
+    v3::{format_sequence, Instruction},
+};
+
+use crate::{
+    emulator::{Emulator, DP_ADDR0, IP_ADDR0, IP_ADDR1, SP_ADDR, STACK_START},
+    expr::Expr,
+    fuzz::Rng,
+};
+
+///Every instruction except the ones that change ip, nop never makes a sequence shorter
+pub const ALPHABET: [Instruction; 13] = {
+    use Instruction::*;
+    [
+        Psi, Psd, Poi, Pod, Swp, Mdp, Di, Dd, Inc, Dec, Add, Sub, Mul,
+    ]
+};
+
+///Sequences are run at this address
+const CODE_START: u8 = 0x30;
+///Longest sequence that fits in front of the data dp points to
+pub const MAX_LEN: usize = 8;
+///dp starts in this range so moving it doesn't reach the code or the stack
+const DP_MIN: u8 = 0x60;
+const DP_MAX: u8 = 0xDF;
+
+///A start state and what a sequence has to turn it into
+struct Test {
+    start: [u4; 256],
+    expected: [u4; 256],
+    ///Cells that have to match, ip, the code and dead stack cells don't matter
+    care: [bool; 256],
+}
+
+///A stack effect like `a b -- b a`, the rightmost value is the top of the stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Effect {
+    ///Names or values
+    pub inputs: Vec<Expr>,
+    pub outputs: Vec<Expr>,
+}
+
+impl Effect {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (inputs, outputs) = text
+            .split_once("--")
+            .ok_or("expected a stack effect like 'a b -- b a'")?;
+        let inputs = Expr::parse_list(inputs)?;
+        if let Some(input) = inputs
+            .iter()
+            .find(|input| !matches!(input, Expr::Num(_) | Expr::Var(_)))
+        {
+            return Err(format!("input {:?} is neither a name nor a value", input));
+        }
+        Ok(Self {
+            inputs,
+            outputs: Expr::parse_list(outputs)?,
+        })
+    }
+
+    fn names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for input in self.inputs.iter() {
+            if let Expr::Var(name) = input {
+                if !names.contains(&&**name) {
+                    names.push(&**name);
+                }
+            }
+        }
+        names
+    }
+}
+
+///How far a sequence pops below and pushes above the stack it starts with
+fn stack_range(code: &[Instruction]) -> (usize, usize) {
+    use Instruction::*;
+    let (mut depth, mut min, mut max) = (0isize, 0isize, 0isize);
+    for inst in code {
+        let (pops, pushes) = match inst {
+            Nop | Di | Dd | Jnz => (0, 0),
+            Psi | Psd => (0, 1),
+            Poi | Pod => (1, 0),
+            Swp => (2, 2),
+            Mdp | Jmp => (2, 0),
+            Inc | Dec => (1, 1),
+            Add | Sub | Mul => (2, 1),
+        };
+        depth -= pops;
+        min = min.min(depth);
+        depth += pushes;
+        max = max.max(depth);
+    }
+    (-min as usize, max as usize)
+}
+
+fn random_start(rng: &mut Rng) -> [u4; 256] {
+    let mut start = rng.image();
+    let dp = DP_MIN + (rng.next_u64() % (DP_MAX - DP_MIN + 1) as u64) as u8;
+    for (addr, value) in [(IP_ADDR0, CODE_START), (DP_ADDR0, dp)] {
+        start[addr as usize] = u4::from_low(value);
+        start[addr as usize + 1] = u4::from_high(value);
+    }
+    start
+}
+
+fn code_cells() -> impl Iterator<Item = usize> {
+    CODE_START as usize..CODE_START as usize + MAX_LEN
+}
+
+///Runs a sequence from a start state, None when it changed ip
+fn run(start: &[u4; 256], code: &[Instruction]) -> Option<[u4; 256]> {
+    let mut image = *start;
+    for (i, inst) in code.iter().enumerate() {
+        image[CODE_START as usize + i] = inst.into_u4();
+    }
+    let mut emulator = Emulator::new(image, |_, _, _, _| None);
+    emulator.is_running = true;
+    for _ in code {
+        emulator.tick();
+    }
+    (emulator.ip() == CODE_START + code.len() as u8).then_some(emulator.mem)
+}
+
+///Searches for straight-line sequences that pass a set of tests
+pub struct Search {
+    tests: Vec<Test>,
+}
+
+impl Search {
+    ///Sequences that leave the machine in the same state as `code` for `tests` random start states,
+    ///the stack is deep enough that `code` never underflows
+    pub fn reference(code: &[Instruction], tests: usize, seed: u64) -> Result<Self, String> {
+        if code.len() > MAX_LEN {
+            return Err(format!("sequences are at most {} long", MAX_LEN));
+        }
+        let (below, above) = stack_range(code);
+        if below + above > 15 {
+            return Err("the sequence needs more than 15 stack cells".into());
+        }
+        let mut rng = Rng::new(seed);
+        let mut all = Vec::with_capacity(tests);
+        for _ in 0..tests {
+            let mut start = random_start(&mut rng);
+            let sp = below + (rng.next_u64() as usize % (16 - below - above));
+            start[SP_ADDR as usize] = u4::from_low(sp as u8);
+            let expected = run(&start, code).ok_or("the sequence changes ip")?;
+            let mut care = [true; 256];
+            for addr in code_cells().chain([IP_ADDR0 as usize, IP_ADDR1 as usize]) {
+                care[addr] = false;
+            }
+            all.push(Test {
+                start,
+                expected,
+                care,
+            });
+        }
+        Ok(Self { tests: all })
+    }
+
+    ///Sequences with all of the stack effects that leave dp and the rest of memory alone,
+    ///every value of the inputs is tested when there are at most `tests` combinations
+    pub fn effects(effects: &[Effect], tests: usize, seed: u64) -> Result<Self, String> {
+        let mut rng = Rng::new(seed);
+        let mut all = Vec::new();
+        for effect in effects {
+            let names = effect.names();
+            let combinations = 16usize
+                .checked_pow(names.len() as u32)
+                .unwrap_or(usize::MAX);
+            let exhaustive = combinations <= tests;
+            for case in 0..combinations.min(tests) {
+                let vars: HashMap<&str, u64> = names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| match exhaustive {
+                        true => (*name, (case >> (i * 4)) as u64 & 0xF),
+                        false => (*name, rng.next_u64() & 0xF),
+                    })
+                    .collect();
+                if let Some(test) = Self::effect_test(effect, &vars, &mut rng)? {
+                    all.push(test);
+                }
+            }
+        }
+        Ok(Self { tests: all })
+    }
+
+    ///None when the outputs can't be computed for these inputs (like a division by zero)
+    fn effect_test(
+        effect: &Effect,
+        vars: &HashMap<&str, u64>,
+        rng: &mut Rng,
+    ) -> Result<Option<Test>, String> {
+        let eval = |exprs: &[Expr]| -> Result<Vec<u4>, String> {
+            exprs
+                .iter()
+                .map(|expr| expr.eval(vars).map(|value| u4::from_low(value as u8)))
+                .collect()
+        };
+        let inputs = eval(&effect.inputs)?;
+        let Ok(outputs) = eval(&effect.outputs) else {
+            return Ok(None);
+        };
+        // a few values below the inputs that have to stay where they are
+        let filler = rng.next_u64() as usize % 3;
+        let (before, after) = (filler + inputs.len(), filler + outputs.len());
+        if before.max(after) > 15 {
+            return Err("the stack effect needs more than 15 stack cells".into());
+        }
+
+        let mut start = random_start(rng);
+        let stack = STACK_START as usize + 1 + filler;
+        start[SP_ADDR as usize] = u4::from_low(before as u8);
+        start[stack..stack + inputs.len()].copy_from_slice(&inputs);
+        let mut expected = start;
+        expected[SP_ADDR as usize] = u4::from_low(after as u8);
+        expected[stack..stack + outputs.len()].copy_from_slice(&outputs);
+
+        let mut care = [true; 256];
+        let dead = STACK_START as usize + 1 + after..STACK_START as usize + 16;
+        for addr in code_cells()
+            .chain(dead)
+            .chain([IP_ADDR0 as usize, IP_ADDR1 as usize])
+        {
+            care[addr] = false;
+        }
+        Ok(Some(Test {
+            start,
+            expected,
+            care,
+        }))
+    }
+
+    pub fn matches(&self, code: &[Instruction]) -> bool {
+        self.tests.iter().all(|test| {
+            let Some(end) = run(&test.start, code) else {
+                return false;
+            };
+            (0..256).all(|addr| !test.care[addr] || end[addr] == test.expected[addr])
+        })
+    }
+
+    ///Every matching sequence of the shortest length up to `max_len` (at most `limit` of them),
+    ///every instruction takes one cycle so these are also the fastest
+    pub fn shortest(&self, max_len: usize, limit: usize) -> Vec<Vec<Instruction>> {
+        for len in 0..=max_len.min(MAX_LEN) {
+            let found: Vec<_> = sequences(len)
+                .filter(|code| self.matches(code))
+                .take(limit)
+                .collect();
+            if !found.is_empty() {
+                return found;
+            }
+        }
+        Vec::new()
+    }
+}
+
+///Every sequence of `len` instructions from the [ALPHABET]
+pub fn sequences(len: usize) -> impl Iterator<Item = Vec<Instruction>> {
+    let mut digits = vec![0; len];
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let code = digits.iter().map(|digit| ALPHABET[*digit]).collect();
+        // count up like an odometer
+        done = true;
+        for digit in digits.iter_mut().rev() {
+            *digit += 1;
+            if *digit < ALPHABET.len() {
+                done = false;
+                break;
+            }
+            *digit = 0;
+        }
+        Some(code)
+    })
+}
+
+///A sequence that can always be replaced by a shorter one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    pub from: Vec<Instruction>,
+    pub to: Vec<Instruction>,
+}
+impl Display for Rewrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} (saves {})",
+            format_sequence(&self.from),
+            format_sequence(&self.to),
+            self.from.len() - self.to.len()
+        )
+    }
+}
+
+///Every sequence of 2 to `max_len` instructions with a shorter equivalent,
+///sequences that contain a shorter rewrite are left out
+pub fn peephole(max_len: usize, tests: usize, seed: u64) -> Vec<Rewrite> {
+    let mut rewrites: Vec<Rewrite> = Vec::new();
+    for len in 2..=max_len.min(MAX_LEN) {
+        for from in sequences(len) {
+            let covered = rewrites.iter().any(|rewrite| {
+                from.windows(rewrite.from.len())
+                    .any(|window| window == rewrite.from)
+            });
+            if covered {
+                continue;
+            }
+            let Ok(search) = Search::reference(&from, tests, seed) else {
+                continue;
+            };
+            if let Some(to) = search.shortest(len - 1, 1).pop() {
+                rewrites.push(Rewrite { from, to });
+            }
+        }
+    }
+    rewrites
+}
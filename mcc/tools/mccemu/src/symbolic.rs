@@ -0,0 +1,642 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    rc::Rc,
+};
+
+use libmcc::{u4, v3::Instruction};
+
+use crate::{
+    emulator::{DP_ADDR0, DP_ADDR1, IP_ADDR0, IP_ADDR1, REG_END, SP_ADDR, STACK_START},
+    prop::Output,
+};
+
+///Every input is a nibble so a path constraint is a set of at most 16^4 assignments
+pub const MAX_INPUTS: usize = 4;
+
+///A nibble computed from the symbolic inputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sym {
+    Const(u4),
+    Input(usize),
+    Add(Rc<Sym>, Rc<Sym>),
+    Sub(Rc<Sym>, Rc<Sym>),
+    Mul(Rc<Sym>, Rc<Sym>),
+}
+
+impl Sym {
+    pub fn constant(value: u4) -> Rc<Self> {
+        Rc::new(Sym::Const(value))
+    }
+
+    pub fn as_const(&self) -> Option<u4> {
+        match self {
+            Sym::Const(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn binary(
+        a: &Rc<Sym>,
+        b: &Rc<Sym>,
+        op: fn(u4, u4) -> u4,
+        node: fn(Rc<Sym>, Rc<Sym>) -> Sym,
+    ) -> Rc<Sym> {
+        match (a.as_const(), b.as_const()) {
+            (Some(a), Some(b)) => Sym::constant(op(a, b)),
+            _ => Rc::new(node(a.clone(), b.clone())),
+        }
+    }
+
+    pub fn add(a: &Rc<Sym>, b: &Rc<Sym>) -> Rc<Sym> {
+        match (a.as_const(), b.as_const()) {
+            (Some(u4::ZERO), _) => b.clone(),
+            (_, Some(u4::ZERO)) => a.clone(),
+            _ => Self::binary(a, b, u4::overflowing_add, Sym::Add),
+        }
+    }
+
+    pub fn sub(a: &Rc<Sym>, b: &Rc<Sym>) -> Rc<Sym> {
+        match b.as_const() {
+            Some(u4::ZERO) => a.clone(),
+            _ => Self::binary(a, b, u4::overflowing_sub, Sym::Sub),
+        }
+    }
+
+    pub fn mul(a: &Rc<Sym>, b: &Rc<Sym>) -> Rc<Sym> {
+        match (a.as_const(), b.as_const()) {
+            (Some(u4::ZERO), _) | (_, Some(u4::ZERO)) => Sym::constant(u4::ZERO),
+            (Some(u4::ONE), _) => b.clone(),
+            (_, Some(u4::ONE)) => a.clone(),
+            _ => Self::binary(a, b, u4::overflowing_mul, Sym::Mul),
+        }
+    }
+
+    pub fn eval(&self, inputs: &[u4]) -> u4 {
+        match self {
+            Sym::Const(value) => *value,
+            Sym::Input(i) => inputs[*i],
+            Sym::Add(a, b) => a.eval(inputs).overflowing_add(b.eval(inputs)),
+            Sym::Sub(a, b) => a.eval(inputs).overflowing_sub(b.eval(inputs)),
+            Sym::Mul(a, b) => a.eval(inputs).overflowing_mul(b.eval(inputs)),
+        }
+    }
+
+    ///Like `(a + b) * 0x2` with the names of the inputs
+    pub fn render(&self, names: &[Box<str>]) -> String {
+        match self {
+            Sym::Const(value) => format!("{:#03x}", value),
+            Sym::Input(i) => names[*i].to_string(),
+            Sym::Add(a, b) => format!("({} + {})", a.render(names), b.render(names)),
+            Sym::Sub(a, b) => format!("({} - {})", a.render(names), b.render(names)),
+            Sym::Mul(a, b) => format!("({} * {})", a.render(names), b.render(names)),
+        }
+    }
+}
+
+///The input assignments a path is taken for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Domain {
+    inputs: usize,
+    bits: Vec<u64>,
+}
+
+impl Domain {
+    ///Every assignment of `inputs` nibbles
+    pub fn full(inputs: usize) -> Self {
+        let size = 1usize << (inputs * 4);
+        let mut bits = vec![u64::MAX; size.div_ceil(64)];
+        if size < 64 {
+            bits[0] = (1 << size) - 1;
+        }
+        Self { inputs, bits }
+    }
+
+    fn empty(inputs: usize) -> Self {
+        let size = 1usize << (inputs * 4);
+        Self {
+            inputs,
+            bits: vec![0; size.div_ceil(64)],
+        }
+    }
+
+    fn insert(&mut self, index: usize) {
+        self.bits[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn union(&mut self, other: &Domain) {
+        for (bits, other) in self.bits.iter_mut().zip(&other.bits) {
+            *bits |= other;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|bits| *bits == 0)
+    }
+
+    fn assignment(&self, index: usize) -> Vec<u4> {
+        (0..self.inputs)
+            .map(|i| u4::from_low((index >> (i * 4)) as u8))
+            .collect()
+    }
+
+    fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.bits.iter().enumerate().flat_map(|(word, bits)| {
+            (0..64)
+                .filter(move |bit| bits & (1 << bit) != 0)
+                .map(move |bit| word * 64 + bit)
+        })
+    }
+
+    ///Every input assignment in the domain
+    pub fn assignments(&self) -> impl Iterator<Item = Vec<u4>> + '_ {
+        self.indices().map(|index| self.assignment(index))
+    }
+
+    ///The first assignment, None for an empty domain
+    pub fn example(&self) -> Option<Vec<u4>> {
+        self.assignments().next()
+    }
+
+    ///The values the input takes in the domain
+    pub fn values(&self, input: usize) -> BTreeSet<u4> {
+        self.assignments().map(|values| values[input]).collect()
+    }
+
+    ///The part of the domain for every value the symbol takes in it
+    pub fn split(&self, sym: &Sym) -> BTreeMap<u4, Domain> {
+        if let Some(value) = sym.as_const() {
+            return BTreeMap::from([(value, self.clone())]);
+        }
+        let mut parts: BTreeMap<u4, Domain> = BTreeMap::new();
+        for index in self.indices() {
+            let value = sym.eval(&self.assignment(index));
+            parts
+                .entry(value)
+                .or_insert_with(|| Domain::empty(self.inputs))
+                .insert(index);
+        }
+        parts
+    }
+}
+
+///A path that reached the end of memory
+#[derive(Debug, Clone)]
+pub struct Halt {
+    pub domain: Domain,
+    pub cycles: u64,
+    ///Stack from bottom to top
+    pub stack: Vec<Rc<Sym>>,
+    pub mem: Vec<Rc<Sym>>,
+}
+
+impl Halt {
+    pub fn stack_top(&self) -> Rc<Sym> {
+        match self.stack.last() {
+            Some(top) => top.clone(),
+            // an empty stack peeks at the cell below it
+            None => self.mem[STACK_START as usize].clone(),
+        }
+    }
+
+    pub fn output(&self, output: Output) -> Rc<Sym> {
+        match output {
+            Output::StackTop => self.stack_top(),
+            Output::Cell(addr) => self.mem[addr as usize].clone(),
+        }
+    }
+}
+
+///Why the outputs don't match a reference for every input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Disproof {
+    Mismatch {
+        inputs: Vec<u4>,
+        expected: Vec<u4>,
+        actual: Vec<u4>,
+    },
+    ///The vm was still running after `max_cycles` for these inputs
+    Unfinished(Vec<u4>),
+    ///Not every path was followed
+    Incomplete,
+}
+impl Display for Disproof {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let values = |values: &[u4]| {
+            values
+                .iter()
+                .map(|value| format!("{:#03x}", value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        match self {
+            Disproof::Mismatch {
+                inputs,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "inputs {} give {} instead of {}",
+                values(inputs),
+                values(actual),
+                values(expected)
+            ),
+            Disproof::Unfinished(inputs) => {
+                write!(f, "inputs {} did not halt", values(inputs))
+            }
+            Disproof::Incomplete => f.write_str("gave up before following every path"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IssueKind {
+    StackUnderflow,
+    ///dp moves past 0xFF or below 0x00
+    DpWrap,
+    ///poi/pod writes into the register cells
+    RegisterWrite,
+}
+impl Display for IssueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IssueKind::StackUnderflow => f.write_str("stack underflow"),
+            IssueKind::DpWrap => f.write_str("dp wraps around"),
+            IssueKind::RegisterWrite => f.write_str("write to a register cell"),
+        }
+    }
+}
+
+///Everything the exploration found
+#[derive(Debug, Clone)]
+pub struct Exploration {
+    pub halts: Vec<Halt>,
+    ///Every address a jmp or jnz can continue at
+    pub jumps: BTreeMap<u8, BTreeSet<u8>>,
+    ///Inputs that make the instruction at an address misbehave
+    pub issues: BTreeMap<(IssueKind, u8), Domain>,
+    ///Inputs that were still running after `max_cycles`
+    pub unfinished: Domain,
+    ///The exploration stopped after `max_steps` steps, some inputs weren't followed to the end
+    pub incomplete: bool,
+}
+
+impl Exploration {
+    ///Checks the outputs of every halt state against `reference` for every input in its domain,
+    ///returns the number of inputs checked
+    pub fn prove(
+        &self,
+        outputs: &[Output],
+        reference: impl Fn(&[u4]) -> Vec<u4>,
+    ) -> Result<usize, Disproof> {
+        if self.incomplete {
+            return Err(Disproof::Incomplete);
+        }
+        if let Some(inputs) = self.unfinished.example() {
+            return Err(Disproof::Unfinished(inputs));
+        }
+        let mut cases = 0;
+        for halt in self.halts.iter() {
+            let syms: Vec<_> = outputs.iter().map(|output| halt.output(*output)).collect();
+            for inputs in halt.domain.assignments() {
+                let expected = reference(&inputs);
+                let actual: Vec<_> = syms.iter().map(|sym| sym.eval(&inputs)).collect();
+                if actual != expected {
+                    return Err(Disproof::Mismatch {
+                        inputs,
+                        expected,
+                        actual,
+                    });
+                }
+                cases += 1;
+            }
+        }
+        Ok(cases)
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    mem: Vec<Rc<Sym>>,
+    domain: Domain,
+    cycles: u64,
+}
+
+impl State {
+    fn with_domain(&self, domain: Domain) -> State {
+        State {
+            mem: self.mem.clone(),
+            domain,
+            cycles: self.cycles,
+        }
+    }
+
+    fn get(&self, addr: u8) -> u4 {
+        self.mem[addr as usize]
+            .as_const()
+            .expect("registers are concrete")
+    }
+
+    fn set(&mut self, addr: u8, value: u4) {
+        self.mem[addr as usize] = Sym::constant(value);
+    }
+
+    fn get8(&self, addr: u8) -> u8 {
+        self.get(addr).into_low() | self.get(addr.wrapping_add(1)).into_high()
+    }
+
+    fn set8(&mut self, addr: u8, value: u8) {
+        self.set(addr, u4::from_low(value));
+        self.set(addr.wrapping_add(1), u4::from_high(value));
+    }
+
+    ///Forks the state for every value the cells can have, the cells are concrete in every fork
+    fn concretize(self, addrs: &[u8]) -> Vec<State> {
+        let mut states = vec![self];
+        for addr in addrs {
+            states = states
+                .into_iter()
+                .flat_map(|state| {
+                    if state.mem[*addr as usize].as_const().is_some() {
+                        return vec![state];
+                    }
+                    let parts = state.domain.split(&state.mem[*addr as usize]);
+                    parts
+                        .into_iter()
+                        .map(move |(value, domain)| {
+                            let mut state = state.with_domain(domain);
+                            state.set(*addr, value);
+                            state
+                        })
+                        .collect()
+                })
+                .collect();
+        }
+        states
+    }
+}
+
+///Runs an image with some cells holding symbolic nibbles instead of values
+pub struct Engine {
+    pub image: [u4; 256],
+    ///Named cells that hold a symbolic nibble when the vm starts
+    pub inputs: Vec<(Box<str>, u8)>,
+    ///Paths running longer are reported as unfinished
+    pub max_cycles: u64,
+    ///The exploration gives up after running this many instructions over all paths
+    pub max_steps: usize,
+}
+
+struct Explorer<'a> {
+    engine: &'a Engine,
+    result: Exploration,
+    work: Vec<State>,
+}
+
+impl Explorer<'_> {
+    fn issue(&mut self, kind: IssueKind, ip: u8, domain: &Domain) {
+        self.result
+            .issues
+            .entry((kind, ip))
+            .and_modify(|existing| existing.union(domain))
+            .or_insert_with(|| domain.clone());
+    }
+
+    fn push(&mut self, state: &mut State, value: Rc<Sym>) {
+        let sp = state.get(SP_ADDR).overflowing_add(u4::ONE);
+        state.set(SP_ADDR, sp);
+        state.mem[(STACK_START + sp.into_low()) as usize] = value;
+    }
+
+    fn pop(&mut self, state: &mut State, ip: u8) -> Rc<Sym> {
+        let sp = state.get(SP_ADDR);
+        let value = state.mem[(STACK_START + sp.into_low()) as usize].clone();
+        if sp == u4::ZERO {
+            self.issue(IssueKind::StackUnderflow, ip, &state.domain);
+        } else {
+            state.set(SP_ADDR, sp.overflowing_sub(u4::ONE));
+        }
+        value
+    }
+
+    fn move_dp(&mut self, state: &mut State, ip: u8, up: bool) {
+        let dp = state.get8(DP_ADDR0);
+        if (up && dp == 0xFF) || (!up && dp == 0x00) {
+            self.issue(IssueKind::DpWrap, ip, &state.domain);
+        }
+        let dp = if up {
+            dp.wrapping_add(1)
+        } else {
+            dp.wrapping_sub(1)
+        };
+        state.set8(DP_ADDR0, dp);
+    }
+
+    fn write(&mut self, state: &mut State, ip: u8, value: Rc<Sym>) {
+        let dp = state.get8(DP_ADDR0);
+        if dp < REG_END {
+            self.issue(IssueKind::RegisterWrite, ip, &state.domain);
+        }
+        state.mem[dp as usize] = value;
+    }
+
+    ///Runs the instruction at ip, the registers have to be concrete
+    fn step(&mut self, state: State) {
+        let ip = state.get8(IP_ADDR0);
+        let dp = state.get8(DP_ADDR0);
+        // the instruction has to be known and a value written into a register has to be concrete
+        let mut cells = vec![ip];
+        if dp < REG_END {
+            cells.push(STACK_START + state.get(SP_ADDR).into_low());
+        }
+        let mut forks = state.concretize(&cells);
+        if forks.len() > 1 {
+            self.work.append(&mut forks);
+            return;
+        }
+        let Some(mut state) = forks.pop() else {
+            return;
+        };
+        state.cycles += 1;
+        let inst = Instruction::from_u4(state.get(ip));
+        let cell = state.mem[dp as usize].clone();
+
+        let mut jump = false;
+        let mut states = Vec::new();
+        use Instruction::*;
+        match inst {
+            Nop => {}
+            Psi | Psd => {
+                self.push(&mut state, cell);
+                self.move_dp(&mut state, ip, inst == Psi);
+            }
+            Poi | Pod => {
+                let value = self.pop(&mut state, ip);
+                self.write(&mut state, ip, value);
+                self.move_dp(&mut state, ip, inst == Poi);
+            }
+            Swp => {
+                let a = self.pop(&mut state, ip);
+                let b = self.pop(&mut state, ip);
+                self.push(&mut state, a);
+                self.push(&mut state, b);
+            }
+            Mdp => {
+                let low = self.pop(&mut state, ip);
+                state.mem[DP_ADDR0 as usize] = low;
+                let high = self.pop(&mut state, ip);
+                state.mem[DP_ADDR1 as usize] = high;
+            }
+            Di | Dd => self.move_dp(&mut state, ip, inst == Di),
+            Jmp => {
+                let low = self.pop(&mut state, ip);
+                state.mem[IP_ADDR0 as usize] = low;
+                let high = self.pop(&mut state, ip);
+                state.mem[IP_ADDR1 as usize] = high;
+                jump = true;
+            }
+            Jnz => {
+                let sp = state.get(SP_ADDR);
+                let top = state.mem[(STACK_START + sp.into_low()) as usize].clone();
+                // the path only forks on zero and nonzero, not on every value of the top
+                let mut taken: Option<Domain> = None;
+                for (value, domain) in state.domain.split(&top) {
+                    if value == u4::ZERO {
+                        states.push((state.with_domain(domain), false));
+                    } else if let Some(taken) = taken.as_mut() {
+                        taken.union(&domain);
+                    } else {
+                        taken = Some(domain);
+                    }
+                }
+                if let Some(domain) = taken {
+                    let mut fork = state.with_domain(domain);
+                    fork.mem[IP_ADDR0 as usize] = cell.clone();
+                    states.push((fork, true));
+                }
+            }
+            Inc | Dec => {
+                let value = self.pop(&mut state, ip);
+                let one = Sym::constant(u4::ONE);
+                let value = match inst {
+                    Inc => Sym::add(&value, &one),
+                    _ => Sym::sub(&value, &one),
+                };
+                self.push(&mut state, value);
+            }
+            Add | Sub | Mul => {
+                let a = self.pop(&mut state, ip);
+                let b = self.pop(&mut state, ip);
+                let value = match inst {
+                    Add => Sym::add(&a, &b),
+                    Sub => Sym::sub(&a, &b),
+                    _ => Sym::mul(&a, &b),
+                };
+                self.push(&mut state, value);
+            }
+        }
+        if inst != Jnz {
+            states.push((state, jump));
+        }
+
+        for (state, jump) in states {
+            for mut state in state.concretize(&[IP_ADDR0, IP_ADDR1, DP_ADDR0, DP_ADDR1, SP_ADDR]) {
+                let next = state.get8(IP_ADDR0);
+                if jump {
+                    self.result.jumps.entry(ip).or_default().insert(next);
+                }
+                if next == 0xFF {
+                    self.halt(state);
+                    continue;
+                }
+                if !jump {
+                    state.set8(IP_ADDR0, next.wrapping_add(1));
+                }
+                self.work.push(state);
+            }
+        }
+    }
+
+    fn halt(&mut self, state: State) {
+        let sp = state.get(SP_ADDR).into_usize();
+        let start = STACK_START as usize + 1;
+        self.result.halts.push(Halt {
+            stack: state.mem[start..start + sp].to_vec(),
+            domain: state.domain,
+            cycles: state.cycles,
+            mem: state.mem,
+        });
+    }
+
+    fn run(&mut self) {
+        let mut steps = 0;
+        while let Some(state) = self.work.pop() {
+            if state.cycles >= self.engine.max_cycles {
+                self.result.unfinished.union(&state.domain);
+                continue;
+            }
+            steps += 1;
+            if steps > self.engine.max_steps {
+                self.result.incomplete = true;
+                break;
+            }
+            self.step(state);
+        }
+    }
+}
+
+impl Engine {
+    pub fn new(image: [u4; 256]) -> Self {
+        Self {
+            image,
+            inputs: Vec::new(),
+            max_cycles: 10000,
+            max_steps: 1 << 20,
+        }
+    }
+
+    pub fn names(&self) -> Vec<Box<str>> {
+        self.inputs.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    ///Follows every path from the start state, panics with more than [MAX_INPUTS] inputs
+    pub fn explore(&self) -> Exploration {
+        assert!(self.inputs.len() <= MAX_INPUTS, "too many symbolic inputs");
+        let mut mem: Vec<Rc<Sym>> = self.image.iter().map(|nib| Sym::constant(*nib)).collect();
+        for (i, (_, addr)) in self.inputs.iter().enumerate() {
+            mem[*addr as usize] = Rc::new(Sym::Input(i));
+        }
+        let domain = Domain::full(self.inputs.len());
+        let mut state = State {
+            mem,
+            domain: domain.clone(),
+            cycles: 0,
+        };
+        state.set8(IP_ADDR0, 0x30);
+        state.set8(DP_ADDR0, 0x20);
+        state.set(SP_ADDR, u4::ZERO);
+
+        let mut explorer = Explorer {
+            engine: self,
+            result: Exploration {
+                halts: Vec::new(),
+                jumps: BTreeMap::new(),
+                issues: BTreeMap::new(),
+                unfinished: Domain::empty(self.inputs.len()),
+                incomplete: false,
+            },
+            work: Vec::new(),
+        };
+        // an input in a register cell is concretized before the first instruction
+        explorer.work = state.concretize(&[IP_ADDR0, IP_ADDR1, DP_ADDR0, DP_ADDR1, SP_ADDR]);
+        explorer.run();
+        explorer.result
+    }
+}
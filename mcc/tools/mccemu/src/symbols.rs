@@ -0,0 +1,164 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+};
+
+///A hex address like 0x25
+pub fn parse_addr(str: &str) -> Option<u8> {
+    u8::from_str_radix(str.strip_prefix("0x")?, 16).ok()
+}
+
+///Label addresses and data written by `mccasm --symbols`
+#[derive(Default)]
+pub struct SymbolMap {
+    labels: BTreeMap<u8, Vec<Box<str>>>,
+    data: BTreeSet<u8>,
+}
+
+impl SymbolMap {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        for (linenum, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (addr, name) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("line {}: expected '<addr> <label>'", linenum + 1))?;
+            let addr = u8::from_str_radix(addr.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("line {}: invalid address '{}'", linenum + 1, addr))?;
+            if let Some(size) = name.strip_prefix(".data ") {
+                let size: usize = size
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid data size '{}'", linenum + 1, size))?;
+                for offset in 0..size {
+                    map.mark_data(addr.wrapping_add(offset as u8));
+                }
+                continue;
+            }
+            map.insert(addr, name.trim());
+        }
+        Ok(map)
+    }
+
+    pub fn insert(&mut self, addr: u8, name: &str) {
+        self.labels.entry(addr).or_default().push(name.into());
+    }
+
+    pub fn mark_data(&mut self, addr: u8) {
+        self.data.insert(addr);
+    }
+
+    ///The assembler put data and not an instruction at this address
+    pub fn is_data(&self, addr: u8) -> bool {
+        self.data.contains(&addr)
+    }
+
+    ///Labels defined at this address
+    pub fn labels_at(&self, addr: u8) -> &[Box<str>] {
+        self.labels
+            .get(&addr)
+            .map(|names| &names[..])
+            .unwrap_or(&[])
+    }
+
+    ///Every label with its address sorted by address
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &str)> {
+        self.labels
+            .iter()
+            .flat_map(|(addr, names)| names.iter().map(|name| (*addr, &**name)))
+    }
+
+    pub fn addr_of(&self, name: &str) -> Option<u8> {
+        self.labels
+            .iter()
+            .find(|(_, names)| names.iter().any(|label| &**label == name))
+            .map(|(addr, _)| *addr)
+    }
+
+    ///A label or a hex address like 0x25
+    pub fn resolve(&self, str: &str) -> Result<u8, String> {
+        parse_addr(str)
+            .or_else(|| self.addr_of(str))
+            .ok_or_else(|| format!("'{}' is neither a label nor an address like 0x25", str))
+    }
+
+    ///The closest label at or before the address as `label` or `label+offset`
+    pub fn describe(&self, addr: u8) -> Option<String> {
+        let (label_addr, names) = self.labels.range(..=addr).next_back()?;
+        let name = names.last()?;
+        if *label_addr == addr {
+            Some(name.to_string())
+        } else {
+            Some(format!("{}+{}", name, addr - label_addr))
+        }
+    }
+}
+
+///Source line of every address written by `mccasm --lines`
+#[derive(Default)]
+pub struct LineMap {
+    lines: BTreeMap<u8, usize>,
+}
+
+impl From<BTreeMap<u8, usize>> for LineMap {
+    fn from(lines: BTreeMap<u8, usize>) -> Self {
+        Self { lines }
+    }
+}
+
+impl LineMap {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        for (linenum, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(addr, source_line)| {
+                let addr = u8::from_str_radix(addr.trim_start_matches("0x"), 16).ok()?;
+                Some((addr, source_line.trim().parse().ok()?))
+            });
+            let (addr, source_line) =
+                parsed.ok_or_else(|| format!("line {}: expected '<addr> <line>'", linenum + 1))?;
+            map.lines.insert(addr, source_line);
+        }
+        Ok(map)
+    }
+
+    pub fn line_of(&self, addr: u8) -> Option<usize> {
+        self.lines.get(&addr).copied()
+    }
+
+    ///Addresses that start a run of cells generated by this line
+    pub fn addrs_of(&self, line: usize) -> Vec<u8> {
+        self.lines
+            .iter()
+            .filter(|(addr, source_line)| {
+                **source_line == line && (**addr == 0 || self.line_of(**addr - 1) != Some(line))
+            })
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    ///The first line at or after `line` that generated code
+    pub fn next_code_line(&self, line: usize) -> Option<usize> {
+        self.lines
+            .values()
+            .filter(|source_line| **source_line >= line)
+            .min()
+            .copied()
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::{self, Display, Write};
+
+use libmcc::{u4, v3::Instruction};
+
+use crate::emulator::{AccessKind, Emulator};
+
+///State of the vm after executing one instruction
+///
+///Written as a single line of `key=value` fields:
+///`cycle=3 ip=32 inst=mdp dp=f0 sp=0 stack=- writes=- io=-`
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub ip: u8,
+    pub instruction: Instruction,
+    pub dp: u8,
+    pub sp: u4,
+    pub stack: Vec<u4>,
+    pub writes: Vec<(u8, u4)>,
+    ///Values read from extensions
+    pub io: Vec<(u8, u4)>,
+}
+
+impl TraceRecord {
+    ///Records the last tick, `ip` is the address of the instruction that was executed
+    pub fn capture(emulator: &Emulator, ip: u8, instruction: Instruction) -> Self {
+        let accesses = emulator.accesses();
+        Self {
+            cycle: emulator.cycles,
+            ip,
+            instruction,
+            dp: emulator.dp(),
+            sp: emulator.sp(),
+            stack: emulator.stack().to_vec(),
+            writes: accesses
+                .iter()
+                .filter(|access| access.kind == AccessKind::Write)
+                .map(|access| (access.addr, access.value))
+                .collect(),
+            io: accesses
+                .iter()
+                .filter(|access| access.kind != AccessKind::Write && access.ext)
+                .map(|access| (access.addr, access.value))
+                .collect(),
+        }
+    }
+}
+
+fn write_cells(f: &mut fmt::Formatter<'_>, cells: &[(u8, u4)]) -> fmt::Result {
+    if cells.is_empty() {
+        return f.write_char('-');
+    }
+    for (i, (addr, value)) in cells.iter().enumerate() {
+        if i != 0 {
+            f.write_char(',')?;
+        }
+        write!(f, "{:02x}:{:x}", addr, value)?;
+    }
+    Ok(())
+}
+
+impl Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cycle={} ip={:02x} inst={} dp={:02x} sp={:x} stack=",
+            self.cycle,
+            self.ip,
+            self.instruction.as_str(),
+            self.dp,
+            self.sp
+        )?;
+        if self.stack.is_empty() {
+            f.write_char('-')?;
+        }
+        for nib in self.stack.iter() {
+            write!(f, "{:x}", nib)?;
+        }
+        f.write_str(" writes=")?;
+        write_cells(f, &self.writes)?;
+        f.write_str(" io=")?;
+        write_cells(f, &self.io)
+    }
+}
+
+///Splits a trace line into its fields
+pub fn parse_fields(line: &str) -> Vec<(&str, &str)> {
+    line.split_whitespace()
+        .map(|field| field.split_once('=').unwrap_or((field, "")))
+        .collect()
+}
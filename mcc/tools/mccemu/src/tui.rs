@@ -0,0 +1,291 @@
+use std::io;
+
+use console::{pad_str, style, Alignment, Key, Term};
+use libmcc::v3::Instruction;
+
+use crate::{
+    emulator::{AccessKind, Emulator},
+    ext::chardev::SharedBuf,
+    session::Session,
+    symbols::SymbolMap,
+};
+
+///A write is highlighted for this many cycles
+const RECENT_WRITE_CYCLES: u64 = 8;
+///Amount of cycles the run key executes before drawing again
+const RUN_BATCH: u64 = 100_000;
+const LEFT_WIDTH: usize = 56;
+const OUTPUT_LINES: usize = 4;
+const HELP: &str =
+    "s step  r run  u back  U reverse-continue  arrows move  b breakpoint  w watch  g goto ip  q quit";
+
+///Full screen front end, `render` only builds lines so it can be used without a terminal
+pub struct Tui {
+    pub cursor: u8,
+    pub message: String,
+    ///Cycle of the last write to every cell
+    last_write: [Option<u64>; 256],
+    output: SharedBuf,
+}
+
+impl Tui {
+    ///`output` is the buffer the chardev writes to
+    pub fn new(output: SharedBuf) -> Self {
+        Self {
+            cursor: 0,
+            message: String::new(),
+            last_write: [None; 256],
+            output,
+        }
+    }
+
+    ///Call after every tick
+    pub fn record(&mut self, emulator: &Emulator) {
+        for access in emulator.accesses().iter() {
+            if access.kind == AccessKind::Write {
+                self.last_write[access.addr as usize] = Some(emulator.cycles);
+            }
+        }
+    }
+
+    fn is_recent_write(&self, addr: u8, cycles: u64) -> bool {
+        self.last_write[addr as usize]
+            .is_some_and(|cycle| cycle <= cycles && cycles - cycle < RECENT_WRITE_CYCLES)
+    }
+
+    fn render_memory(&self, session: &Session) -> Vec<String> {
+        let emulator = &session.emulator;
+        let debugger = &session.debugger;
+        let (ip, dp) = (emulator.ip(), emulator.dp());
+
+        let mut lines = vec![style(" memory").bold().to_string()];
+        let mut header = String::from("    ");
+        for col in 0..16 {
+            header.push_str(&format!(" {:x} ", col));
+        }
+        lines.push(header);
+        for row in 0..16u8 {
+            let mut line = format!(" {:x}0 ", row);
+            for col in 0..16u8 {
+                let addr = row * 16 + col;
+                let mut cell = style(format!("{:x}", emulator.mem[addr as usize]));
+                if addr == ip {
+                    cell = cell.green().reverse();
+                } else if addr == dp {
+                    cell = cell.yellow().reverse();
+                } else if self.is_recent_write(addr, emulator.cycles) {
+                    cell = cell.red().bold();
+                }
+                if debugger.breakpoints.contains(&addr) || debugger.watches.contains(&addr) {
+                    cell = cell.underlined();
+                }
+                if addr == self.cursor {
+                    line.push_str(&format!("[{}]", cell));
+                } else {
+                    line.push_str(&format!(" {} ", cell));
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn render_side(&self, session: &Session, symbols: Option<&SymbolMap>) -> Vec<String> {
+        let emulator = &session.emulator;
+        let ip = emulator.ip();
+        let describe = |addr: u8| {
+            symbols
+                .and_then(|symbols| symbols.describe(addr))
+                .unwrap_or_default()
+        };
+
+        let mut lines = vec![style(" registers").bold().to_string()];
+        lines.push(format!("  ip {:#04x} {}", ip, describe(ip)));
+        lines.push(format!(
+            "  dp {:#04x} {}",
+            emulator.dp(),
+            describe(emulator.dp())
+        ));
+        lines.push(format!("  sp {:#03x}", emulator.sp()));
+        lines.push(format!("  cycle {}", emulator.cycles));
+        lines.push(match (emulator.is_running, session.halt_reason) {
+            (true, _) => "  running".to_string(),
+            (false, Some(reason)) => format!("  halted: {}", reason),
+            (false, None) => "  halted".to_string(),
+        });
+
+        lines.push(String::new());
+        lines.push(style(" stack").bold().to_string());
+        let stack: Vec<_> = emulator
+            .stack()
+            .iter()
+            .map(|nib| format!("{:x}", nib))
+            .collect();
+        lines.push(format!("  [{}]", stack.join(" ")));
+
+        lines.push(String::new());
+        lines.push(style(" disassembly").bold().to_string());
+        for offset in -3i16..8 {
+            let addr = (ip as i16 + offset).rem_euclid(256) as u8;
+            let marker = if addr == ip { '>' } else { ' ' };
+            let breakpoint = if session.debugger.breakpoints.contains(&addr) {
+                '*'
+            } else {
+                ' '
+            };
+            let instruct = Instruction::from_u4(emulator.mem[addr as usize]);
+            let label = symbols
+                .map(|symbols| symbols.labels_at(addr).join(" "))
+                .unwrap_or_default();
+            let line = format!(
+                " {}{}{:#04x} {:<4} {}",
+                marker,
+                breakpoint,
+                addr,
+                instruct.as_str(),
+                label
+            );
+            if addr == ip {
+                lines.push(style(line).green().to_string());
+            } else {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    pub fn render(&self, session: &Session, symbols: Option<&SymbolMap>) -> Vec<String> {
+        let left = self.render_memory(session);
+        let right = self.render_side(session, symbols);
+
+        let mut lines = Vec::new();
+        for i in 0..left.len().max(right.len()) {
+            let left = left.get(i).map(|line| line.as_str()).unwrap_or("");
+            let right = right.get(i).map(|line| line.as_str()).unwrap_or("");
+            lines.push(format!(
+                "{}{}",
+                pad_str(left, LEFT_WIDTH, Alignment::Left, None),
+                right
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push(style(" output").bold().to_string());
+        let output = String::from_utf8_lossy(&self.output.0.borrow()).into_owned();
+        let output_lines: Vec<_> = output.split('\n').collect();
+        for line in &output_lines[output_lines.len().saturating_sub(OUTPUT_LINES)..] {
+            lines.push(format!("  {}", line.escape_debug()));
+        }
+        for _ in output_lines.len()..OUTPUT_LINES {
+            lines.push(String::new());
+        }
+
+        lines.push(String::new());
+        lines.push(self.message.clone());
+        lines.push(style(HELP).dim().to_string());
+        lines
+    }
+
+    fn step(&mut self, session: &mut Session) -> io::Result<()> {
+        session.tick()?;
+        self.record(&session.emulator);
+        Ok(())
+    }
+
+    fn run_until_break(&mut self, session: &mut Session) -> io::Result<()> {
+        for _ in 0..RUN_BATCH {
+            self.step(session)?;
+            if !session.is_running() {
+                self.message = "vm halted".into();
+                return Ok(());
+            }
+            if session.debugger.should_break(&session.emulator) {
+                self.message = format!("break at {:#04x}", session.emulator.ip());
+                return Ok(());
+            }
+        }
+        self.message = format!("paused after {} cycles", RUN_BATCH);
+        Ok(())
+    }
+
+    ///Returns false when the user wants to quit
+    pub fn handle_key(&mut self, key: Key, session: &mut Session) -> io::Result<bool> {
+        self.message.clear();
+        match key {
+            Key::Char('s') | Key::Char(' ') | Key::Enter => {
+                if session.is_running() {
+                    self.step(session)?;
+                } else {
+                    self.message = "vm halted".into();
+                }
+            }
+            Key::Char('r') => {
+                if session.is_running() {
+                    self.run_until_break(session)?;
+                } else {
+                    self.message = "vm halted".into();
+                }
+            }
+            Key::Char('u') | Key::Backspace => {
+                let undone = session.back(1);
+                self.message = format!("stepped back {} cycles", undone);
+            }
+            Key::Char('U') => {
+                let undone = session.reverse_continue();
+                self.message = format!("stepped back {} cycles", undone);
+            }
+            Key::ArrowLeft => self.cursor = self.cursor.wrapping_sub(1),
+            Key::ArrowRight => self.cursor = self.cursor.wrapping_add(1),
+            Key::ArrowUp => self.cursor = self.cursor.wrapping_sub(16),
+            Key::ArrowDown => self.cursor = self.cursor.wrapping_add(16),
+            Key::Char('g') => self.cursor = session.emulator.ip(),
+            Key::Char('b') | Key::Char('w') => {
+                let (set, name) = if key == Key::Char('b') {
+                    (&mut session.debugger.breakpoints, "breakpoint")
+                } else {
+                    (&mut session.debugger.watches, "watch")
+                };
+                if set.remove(&self.cursor) {
+                    self.message = format!("removed {} {:#04x}", name, self.cursor);
+                } else {
+                    set.insert(self.cursor);
+                    self.message = format!("added {} {:#04x}", name, self.cursor);
+                }
+            }
+            Key::Char('q') | Key::Escape | Key::CtrlC => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    pub fn run(
+        &mut self,
+        term: &Term,
+        session: &mut Session,
+        symbols: Option<&SymbolMap>,
+    ) -> io::Result<()> {
+        self.cursor = session.emulator.ip();
+        term.hide_cursor()?;
+        let _cursor = ShowCursor(term);
+        term.clear_screen()?;
+        loop {
+            term.move_cursor_to(0, 0)?;
+            for line in self.render(session, symbols) {
+                term.clear_line()?;
+                term.write_line(&line)?;
+            }
+            term.clear_to_end_of_screen()?;
+            if !self.handle_key(term.read_key()?, session)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+///Shows the cursor again however the terminal interface is left
+struct ShowCursor<'a>(&'a Term);
+impl Drop for ShowCursor<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.show_cursor();
+    }
+}
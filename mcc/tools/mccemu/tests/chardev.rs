@@ -0,0 +1,115 @@
+use std::{
+    io::{self, BufRead, Read, Write},
+    rc::Rc,
+};
+
+use libmcc::{
+    u4,
+    v3::Instruction::{self, *},
+};
+use mccemu::{
+    ext::{
+        chardev::{CharDev, EofMode, InputMode},
+        ExtManager,
+    },
+    session::Session,
+};
+
+///Fails every read and write like a closed pipe
+struct Broken;
+impl Read for Broken {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+}
+impl BufRead for Broken {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+    fn consume(&mut self, _amt: usize) {}
+}
+impl Write for Broken {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+///Points dp at the chardev (0xF1) and runs `code`
+fn session(code: &[Instruction], mode: InputMode) -> Session {
+    let chardev = CharDev::from_reader(Box::new(Broken), Box::new(Broken), EofMode::Zero, mode);
+    session_with(code, chardev)
+}
+
+fn session_with(code: &[Instruction], chardev: CharDev) -> Session {
+    let mut mem = [u4::ZERO; 256];
+    mem[0x20] = u4::from_low(0xF);
+    mem[0x21] = u4::ONE;
+    for (i, inst) in [Psi, Psi, Mdp].iter().chain(code).enumerate() {
+        mem[0x30 + i] = inst.into_u4();
+    }
+    let extmgr = Rc::new(ExtManager::from_extensions(vec![Box::new(chardev)]));
+    let mut emulator = mccemu::new_emulator(mem, extmgr.clone());
+    emulator.start();
+    Session::new(emulator, extmgr)
+}
+
+fn scripted(input: &str) -> CharDev {
+    CharDev::from_reader(
+        Box::new(io::Cursor::new(input.as_bytes().to_vec())),
+        Box::new(io::sink()),
+        EofMode::Zero,
+        InputMode::Raw,
+    )
+}
+
+fn run(session: &mut Session) -> io::Result<()> {
+    while session.is_running() {
+        session.tick()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn write_errors_stop_the_vm() {
+    let mut session = session(&[Pod], InputMode::Raw);
+    let err = run(&mut session).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    assert!(err
+        .to_string()
+        .starts_with("chardev: failed to write output"));
+    assert!(!session.is_running());
+}
+
+#[test]
+fn read_errors_are_not_the_end_of_the_input() {
+    for mode in [InputMode::Raw, InputMode::Line] {
+        let mut session = session(&[Dd, Psi], mode);
+        let err = run(&mut session).unwrap_err();
+        assert!(err.to_string().starts_with("chardev: failed to read input"));
+        assert!(!session.is_running());
+    }
+}
+
+#[test]
+fn stepping_back_reads_the_same_input_again() {
+    let mut session = session_with(&[Dd, Psi, Dd, Psi, Dd, Psi], scripted("abc"));
+    session.set_history(2);
+    run(&mut session).unwrap();
+    let stack = session.emulator.stack().to_vec();
+    assert_eq!(stack, [u4::from_low(1), u4::from_low(2), u4::from_low(3)]);
+    assert_eq!(session.debugger.history_len(), 2);
+
+    assert_eq!(session.back(2), 2);
+    run(&mut session).unwrap();
+    assert_eq!(session.emulator.stack(), stack);
+}
+
+#[test]
+fn no_snapshots_without_history() {
+    let mut session = session_with(&[Dd, Psi], scripted("a"));
+    run(&mut session).unwrap();
+    assert_eq!(session.debugger.history_len(), 0);
+}
@@ -0,0 +1,22 @@
+//! Fixtures shared by the integration tests
+
+use std::path::Path;
+
+use mccemu::{
+    asm::load_image,
+    prop::{Output, Property},
+};
+
+///programs/v3/mul.asm with the inputs a and b and the product on top of the stack
+pub fn mul() -> Property {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../programs/v3/mul.asm");
+    let (image, symbols) = load_image(path.to_str().unwrap(), None).unwrap();
+    let mut property = Property::new(image);
+    for name in ["a", "b"] {
+        property
+            .inputs
+            .push((name.into(), symbols.addr_of(name).unwrap()));
+    }
+    property.outputs.push(Output::StackTop);
+    property
+}
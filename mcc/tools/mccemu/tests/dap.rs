@@ -0,0 +1,120 @@
+//! Replays the transcripts in tests/dap against `mccemu --dap`
+//!
+//! `-> {...}` lines are requests sent to the adapter (seq is added automatically)
+//! and `<- {...}` lines are the messages expected back, fields that are left out are not checked.
+//! `$FIXTURES` is replaced with the path of tests/dap, `$PROGRAMS` with programs/v3 and `$BUILD` with a directory
+//! that holds the images, symbols and lines of every source in both.
+
+use std::{
+    fs,
+    io::BufReader,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use mccasm::{
+    emiting::{emit_bin_packed, emit_lines, emit_symbols},
+    framing::{read_message, write_message},
+    Options,
+};
+use serde_json::{json, Value};
+
+///Returns the path of the first field in `got` that doesn't match `expected`
+fn mismatch(expected: &Value, got: &Value, path: &str) -> Option<String> {
+    match (expected, got) {
+        (Value::Object(expected), Value::Object(got)) => {
+            expected.iter().find_map(|(key, value)| {
+                let path = format!("{}.{}", path, key);
+                match got.get(key) {
+                    Some(got) => mismatch(value, got, &path),
+                    None => Some(path),
+                }
+            })
+        }
+        (Value::Array(expected), Value::Array(got)) if expected.len() == got.len() => expected
+            .iter()
+            .zip(got)
+            .enumerate()
+            .find_map(|(i, (expected, got))| mismatch(expected, got, &format!("{}[{}]", path, i))),
+        _ if expected == got => None,
+        _ => Some(path.to_string()),
+    }
+}
+
+///Assembles every source in `dir` into `build` like `mccasm --symbols --lines` would
+fn assemble(dir: &Path, build: &Path) {
+    fs::create_dir_all(build).unwrap();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "asm") {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        let assembly = mccasm::assemble(&source, &Options::default()).unwrap();
+        let stem = build.join(path.file_stem().unwrap());
+        for (ext, content) in [
+            ("bin", emit_bin_packed(assembly.image)),
+            ("sym", emit_symbols(&assembly.symbols, &assembly.data)),
+            ("lines", emit_lines(&assembly.lines)),
+        ] {
+            fs::write(stem.with_extension(ext), content).unwrap();
+        }
+    }
+}
+
+fn replay(name: &str) {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dap");
+    let programs = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../programs/v3");
+    let build = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("dap-{}", name));
+    assemble(&fixtures, &build);
+    assemble(&programs, &build);
+    let transcript = fs::read_to_string(fixtures.join(name))
+        .unwrap()
+        .replace("$FIXTURES", fixtures.to_str().unwrap())
+        .replace("$PROGRAMS", programs.to_str().unwrap())
+        .replace("$BUILD", build.to_str().unwrap());
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mccemu"))
+        .arg("--dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+    let mut seq = 0;
+    for (linenum, line) in transcript.lines().enumerate() {
+        let location = format!("{}:{}", name, linenum + 1);
+        if let Some(request) = line.strip_prefix("->") {
+            let mut request: Value = serde_json::from_str(request).unwrap();
+            seq += 1;
+            request["seq"] = json!(seq);
+            request["type"] = json!("request");
+            write_message(&mut stdin, &request).unwrap();
+        } else if let Some(expected) = line.strip_prefix("<-") {
+            let expected: Value = serde_json::from_str(expected).unwrap();
+            let got = read_message(&mut stdout)
+                .unwrap()
+                .unwrap_or_else(|| panic!("{}: adapter closed the connection", location));
+            if let Some(path) = mismatch(&expected, &got, "") {
+                panic!(
+                    "{}: field '{}' differs\nexpected {}\ngot      {}",
+                    location, path, expected, got
+                );
+            }
+        }
+    }
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    replay("add.dap");
+}
+
+#[test]
+fn chardev_output() {
+    replay("echo.dap");
+}
@@ -0,0 +1,10 @@
+# adds a and b and leaves the result on the stack
+.org 20
+a: 0x2
+b: 0x3
+
+.org 30
+start:
+psi
+psi
+add
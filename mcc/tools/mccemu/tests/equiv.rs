@@ -0,0 +1,117 @@
+//! Compares small images and programs/v3/mul.asm with the equivalence checker
+
+use libmcc::{
+    u4,
+    v3::Instruction::{self, *},
+};
+use mccemu::{
+    equiv::{self, Counterexample, Failure, Method},
+    fuzz,
+    prop::{Output, Property},
+};
+mod common;
+
+fn counterexample(a: &Property, b: &Property, method: Method) -> Box<Counterexample> {
+    match equiv::check(a, b, method) {
+        Err(Failure::Different(counterexample)) => counterexample,
+        result => panic!("expected a counterexample, got {:?}", result),
+    }
+}
+
+///Code at 0x30 with the inputs at 0x20 and 0x21
+fn program(code: &[Instruction]) -> Property {
+    let mut image = fuzz::image_from_bytes(&[]);
+    for (i, inst) in code.iter().enumerate() {
+        image[0x30 + i] = inst.into_u4();
+    }
+    let mut property = Property::new(image);
+    property.inputs = vec![("x".into(), 0x20), ("y".into(), 0x21)];
+    property.outputs.push(Output::StackTop);
+    property
+}
+
+#[test]
+fn swapped_operands_of_add_are_equivalent() {
+    let a = program(&[Psi, Psi, Add]);
+    let b = program(&[Psi, Psi, Swp, Add]);
+    for method in [Method::Symbolic, Method::Exhaustive] {
+        let report = equiv::check(&a, &b, method).unwrap();
+        assert_eq!(report.cases, 256);
+        assert_eq!(report.method, method);
+        assert_eq!(report.unfinished, 0);
+    }
+}
+
+#[test]
+fn swapped_operands_of_sub_differ() {
+    let a = program(&[Psi, Psi, Sub]);
+    let b = program(&[Psi, Psi, Swp, Sub]);
+    for method in [Method::Symbolic, Method::Exhaustive] {
+        let counterexample = counterexample(&a, &b, method);
+        // 0 - 0 is the same both ways, x=1 y=0 is the first input that isn't
+        assert_eq!(
+            counterexample.inputs,
+            vec![("x".into(), u4::ONE), ("y".into(), u4::ZERO)]
+        );
+        assert_ne!(counterexample.a.outputs, counterexample.b.outputs);
+        assert!(!counterexample.a.trace.is_empty());
+    }
+}
+
+#[test]
+fn halting_differs_from_looping() {
+    // jnz at 0x32 jumps back to itself (mem[0x22] is 0x2) while x is nonzero
+    let mut a = program(&[Psi, Di, Jnz]);
+    a.image[0x22] = u4::from_low(0x2);
+    a.max_cycles = 1000;
+    let mut b = program(&[Psi, Di, Nop]);
+    b.max_cycles = 1000;
+    let counterexample = counterexample(&a, &b, Method::Auto);
+    assert_eq!(counterexample.inputs[0].1, u4::ONE);
+    assert!(counterexample.a.outputs.is_err());
+    assert!(counterexample.b.outputs.is_ok());
+}
+
+#[test]
+fn mul_is_equivalent_to_itself() {
+    let property = common::mul();
+    let report = equiv::check(&property, &property, Method::Auto).unwrap();
+    assert_eq!(report.method, Method::Symbolic);
+}
+
+#[test]
+fn halt_at_is_ignored() {
+    // stopping after the first psi would leave x on the stack instead of x + y
+    let a = program(&[Psi, Psi, Add]);
+    let mut b = program(&[Psi, Psi, Add]);
+    b.halt_at = Some(0x31);
+    for method in [Method::Symbolic, Method::Exhaustive] {
+        assert!(equiv::check(&a, &b, method).is_ok());
+    }
+}
+
+#[test]
+fn symbolic_does_not_fall_back() {
+    // the loop runs past the step limit of the symbolic engine while x is nonzero,
+    // only auto falls back to running every input
+    let mut a = program(&[Psi, Di, Jnz]);
+    a.image[0x22] = u4::from_low(0x2);
+    a.inputs.truncate(1);
+    a.max_cycles = 1 << 12;
+    a.max_steps = 1 << 10;
+    assert!(matches!(
+        equiv::check(&a, &a, Method::Symbolic),
+        Err(Failure::Incomplete)
+    ));
+    let report = equiv::check(&a, &a, Method::Auto).unwrap();
+    assert_eq!(report.method, Method::Exhaustive);
+
+    let mut b = program(&[Psi, Psi, Add]);
+    b.inputs = (0..5)
+        .map(|i| (format!("x{}", i).into(), 0x20 + i))
+        .collect();
+    assert!(matches!(
+        equiv::check(&b, &b, Method::Symbolic),
+        Err(Failure::TooManyInputs)
+    ));
+}
@@ -0,0 +1,39 @@
+//! Runs the fuzz harness (fuzz/fuzz_targets/emulator.rs) on random images from a fixed seed
+
+use mccemu::{
+    emulator::Emulator,
+    fuzz::{self, Rng},
+};
+
+const SEED: u64 = 0x6D63_6365_6D75;
+const IMAGES: usize = 2000;
+
+#[test]
+fn random_images_dont_panic() {
+    let mut rng = Rng::new(SEED);
+    for _ in 0..IMAGES {
+        fuzz::run(rng.image(), fuzz::CYCLES);
+    }
+}
+
+#[test]
+fn dp_wraps_around() {
+    use libmcc::v3::Instruction::*;
+    let cases = [
+        (Psi, 0xFF, 0x00),
+        (Poi, 0xFF, 0x00),
+        (Di, 0xFF, 0x00),
+        (Psd, 0x00, 0xFF),
+        (Pod, 0x00, 0xFF),
+        (Dd, 0x00, 0xFF),
+    ];
+    for (inst, dp, expected) in cases {
+        let mut image = fuzz::image_from_bytes(&[]);
+        image[0x30] = inst.into_u4();
+        let mut emulator = Emulator::new(image, |_, _, _, _| None);
+        emulator.start();
+        emulator.set_dp(dp);
+        emulator.tick();
+        assert_eq!(emulator.dp(), expected, "{:?} at dp {:#04x}", inst, dp);
+    }
+}
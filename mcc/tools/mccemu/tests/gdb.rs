@@ -0,0 +1,99 @@
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+};
+
+///Scripted gdb client talking to `mccemu --gdb`
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn send(&mut self, packet: &str) -> String {
+        let sum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", packet, sum).unwrap();
+
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+', "packet '{}' was not acked", packet);
+        self.stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'$');
+        let mut reply = Vec::new();
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut sum = [0; 2];
+        self.stream.read_exact(&mut sum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+///psi psi add with 2 and 3 as data
+fn add_image() -> Vec<u8> {
+    let mut mem = [0u8; 256];
+    mem[0x20] = 0x2;
+    mem[0x21] = 0x3;
+    mem[0x30..0x33].copy_from_slice(&[0x1, 0x1, 0xd]);
+    mem.chunks(2).map(|pair| pair[0] | pair[1] << 4).collect()
+}
+
+#[test]
+fn scripted_session() {
+    let image = env::temp_dir().join(format!("mccemu-gdb-{}.bin", std::process::id()));
+    fs::write(&image, add_image()).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mccemu"))
+        .arg(&image)
+        .args(["--gdb", "127.0.0.1:0"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line.trim().strip_prefix("gdb: listening on ").unwrap();
+    let mut client = Client {
+        stream: TcpStream::connect(addr).unwrap(),
+    };
+
+    assert!(client.send("qSupported:swbreak+").contains("PacketSize"));
+    assert_eq!(client.send("?"), "S05");
+    assert_eq!(client.send("g"), "302000");
+    assert_eq!(client.send("m30,3"), "01010d");
+
+    assert_eq!(client.send("Z0,32,1"), "OK");
+    assert_eq!(client.send("c"), "S05");
+    assert_eq!(client.send("p0"), "32");
+    assert_eq!(client.send("p2"), "02");
+    assert_eq!(client.send("m11,2"), "0203");
+
+    assert_eq!(client.send("s"), "S05");
+    assert_eq!(client.send("g"), "332201");
+    assert_eq!(client.send("m11,1"), "05");
+
+    assert_eq!(client.send("M11,1:07"), "OK");
+    assert_eq!(client.send("m11,1"), "07");
+    // nothing is written when one of the bytes doesn't fit in a cell
+    assert_eq!(client.send("M11,2:0312"), "E01");
+    assert_eq!(client.send("m11,2"), "0703");
+    assert_eq!(client.send("P1=25"), "OK");
+    assert_eq!(client.send("p1"), "25");
+    assert_eq!(client.send("m100,1"), "E01");
+
+    assert_eq!(client.send("z0,32,1"), "OK");
+    assert_eq!(client.send("c"), "W07");
+
+    write!(client.stream, "$k#6b").unwrap();
+    assert!(child.wait().unwrap().success());
+    let _ = fs::remove_file(&image);
+}
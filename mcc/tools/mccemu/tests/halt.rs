@@ -0,0 +1,153 @@
+//! Runs the mccemu binary with the halt conditions, exit codes and the json state dump
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+use mccasm::{emiting::emit_bin_packed, Options};
+use serde_json::Value;
+
+///Pushes 7 and runs to the end
+const PUSH: &str = ".org 20\n0x7\n.org 30\npsi\n";
+
+///Jumps back to loop forever without changing anything
+const LOOP: &str = "
+.org 20
+&&loop
+.org 30
+loop:
+psi
+psi
+dd
+dd
+jmp
+";
+
+///Adds the character read from the chardev to the top of the stack forever
+const POLL: &str = "
+.org 20
+&&chardev
+.org 30
+psi
+psi
+mdp
+psi
+dd
+loop:
+psi
+dd
+add
+di
+di
+psi
+psi
+dd
+dd
+dd
+dd
+jmp
+.org F0
+chardev:
+0x0 0x0
+&&loop
+";
+
+fn mccemu(name: &str, source: &str, args: &[&str]) -> Output {
+    let assembly = mccasm::assemble(source, &Options::default()).unwrap();
+    let image = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("halt-{}.bin", name));
+    fs::write(&image, emit_bin_packed(assembly.image)).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_mccemu"))
+        .arg(&image)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn dump(output: &Output) -> Value {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    serde_json::from_str(stdout.lines().last().unwrap()).unwrap()
+}
+
+#[test]
+fn dump_state_json() {
+    let output = mccemu("dump", PUSH, &["--dump-state", "json"]);
+    assert_eq!(output.status.code(), Some(0));
+    let state = dump(&output);
+    assert_eq!(state["halt_reason"], "end");
+    assert_eq!(state["running"], false);
+    assert_eq!(state["sp"], 1);
+    assert_eq!(state["stack"], serde_json::json!([7]));
+    assert_eq!(state["dp"], 0x21);
+    assert_eq!(state["mem"].as_array().unwrap().len(), 256);
+    assert_eq!(state["mem"][0x30], 1);
+    assert!(state["cycles"].as_u64().unwrap() > 0);
+    assert!(state["ext"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn max_cycles_exits_with_124() {
+    let output = mccemu(
+        "max_cycles",
+        LOOP,
+        &["--max-cycles", "50", "--dump-state", "json"],
+    );
+    assert_eq!(output.status.code(), Some(124));
+    let state = dump(&output);
+    assert_eq!(state["halt_reason"], "max_cycles");
+    assert_eq!(state["cycles"], 50);
+}
+
+#[test]
+fn halt_at() {
+    let output = mccemu(
+        "halt_at",
+        LOOP,
+        &["--halt-at", "33", "--dump-state", "json"],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let state = dump(&output);
+    assert_eq!(state["halt_reason"], "address 0x33");
+    assert_eq!(state["ip"], 0x33);
+    assert_eq!(state["cycles"], 3);
+}
+
+#[test]
+fn halt_on_self_jump() {
+    let args = [
+        "--halt-on-self-jump",
+        "--max-cycles",
+        "1000",
+        "--dump-state",
+        "json",
+    ];
+    let output = mccemu("self_jump", LOOP, &args);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(dump(&output)["halt_reason"], "self_jump");
+
+    // without the chardev the reads at 0xf0 always see the same cell
+    let output = mccemu("poll", POLL, &args);
+    assert_eq!(dump(&output)["halt_reason"], "self_jump");
+    // every read from the chardev could change what the loop does next
+    let args = [&args[..], &["-x", "chardev-ascii", "--chardev-eof", "zero"]].concat();
+    let output = mccemu("poll_chardev", POLL, &args);
+    assert_eq!(output.status.code(), Some(124));
+    assert_eq!(dump(&output)["halt_reason"], "max_cycles");
+}
+
+#[test]
+fn exit_codes() {
+    let output = mccemu("exit_stack", PUSH, &["--exit-code", "stack"]);
+    assert_eq!(output.status.code(), Some(7));
+    let output = mccemu("exit_mem", PUSH, &["--exit-code", "30"]);
+    assert_eq!(output.status.code(), Some(1));
+    // a timeout wins over the exit code
+    let output = mccemu(
+        "exit_timeout",
+        LOOP,
+        &["--exit-code", "stack", "--max-cycles", "10"],
+    );
+    assert_eq!(output.status.code(), Some(124));
+}
@@ -0,0 +1,187 @@
+//! Checks mccasm -O with the emulator, the equivalence checker and the programs/v3 suite
+
+use std::{path::Path, process::Command};
+
+use libmcc::u4;
+use mccasm::{Assembly, Options};
+use mccemu::{
+    equiv::{self, Method},
+    prop::{Output, Property},
+};
+
+const SOURCE: &str = "
+.org 20
+x:
+0x0
+y:
+0x0
+&&done
+.org 30
+psi
+di
+dd
+psi
+swp
+swp
+inc
+dec
+add
+psi
+psi
+jmp
+done:
+dd
+di
+";
+
+fn assemble(source: &str, optimize: bool) -> Assembly {
+    let mut options = Options::default();
+    options.optimize = optimize;
+    mccasm::assemble(source, &options).unwrap()
+}
+
+fn property(assembly: &Assembly) -> Property {
+    let mut property = Property::new(assembly.image);
+    property.inputs = vec![("x".into(), 0x20), ("y".into(), 0x21)];
+    property.outputs.push(Output::StackTop);
+    property
+}
+
+#[test]
+fn rewrites_keep_the_behavior() {
+    let original = assemble(SOURCE, false);
+    let optimized = assemble(SOURCE, true);
+    let applied: Vec<_> = optimized
+        .optimizations
+        .iter()
+        .map(|change| (change.addr, change.saved(), change.skipped.is_none()))
+        .collect();
+    assert_eq!(
+        applied,
+        [
+            (0x31, 2, true),
+            (0x34, 2, true),
+            (0x36, 2, true),
+            (0x3c, 2, true)
+        ]
+    );
+    // the jump target moved with the code
+    assert_eq!(original.symbols["done"], 0x3c);
+    assert_eq!(optimized.symbols["done"], 0x36);
+    assert_eq!(optimized.image[0x22], u4::from_high(0x36));
+    assert_eq!(optimized.image[0x23], u4::from_low(0x36));
+
+    let report = equiv::check(&property(&original), &property(&optimized), Method::Auto).unwrap();
+    assert_eq!(report.cases, 256);
+}
+
+#[test]
+fn data_after_a_rewrite_is_not_moved() {
+    let source = ".org 30\npsi\ndi\ndd\npsi\n0x5\n";
+    let optimized = assemble(source, true);
+    let change = &optimized.optimizations[0];
+    assert_eq!(change.skipped.as_deref(), Some("it moves data"));
+    assert_eq!(optimized.image, assemble(source, false).image);
+}
+
+#[test]
+fn labels_stay_on_their_page() {
+    // loop would move from 0x40 to 0x3e and the jnz couldn't reach it anymore
+    let source = ".org 3c\ndi\ndd\nnop\nnop\nloop:\nnop\njnz\n";
+    let optimized = assemble(source, true);
+    assert_eq!(
+        optimized.optimizations[0].skipped.as_deref(),
+        Some("it moves 'loop' to another page")
+    );
+    assert_eq!(optimized.symbols["loop"], 0x40);
+}
+
+#[test]
+fn anonymous_labels_stay_on_their_page() {
+    // the jnz goes to + at 0x40, without di dd it would be at 0x3e and the jnz would go to 0x4e
+    let source = ".org 20\n0x1\n&+\n.org 3c\ndi\ndd\nnop\nnop\n+:\nnop\npsi\njnz\n";
+    let original = assemble(source, false);
+    let optimized = assemble(source, true);
+    assert_eq!(
+        optimized.optimizations[0].skipped.as_deref(),
+        Some("it moves '+' to another page")
+    );
+    assert_eq!(optimized.image, original.image);
+    let mut a = Property::new(original.image);
+    a.inputs.push(("x".into(), 0x20));
+    a.outputs.push(Output::StackTop);
+    let mut b = Property::new(optimized.image);
+    b.inputs = a.inputs.clone();
+    b.outputs = a.outputs.clone();
+    assert!(equiv::check(&a, &b, Method::Auto).is_ok());
+}
+
+#[test]
+fn jumps_to_addresses_without_a_label() {
+    // 0x3 0x5 is the address of the pod, it would move to 0x33
+    let source = ".org 20\n0x3\n0x5\n.org 30\npsi\npsi\ndi\ndd\njmp\npod\n";
+    let optimized = assemble(source, true);
+    assert_eq!(
+        optimized.optimizations[0].skipped.as_deref(),
+        Some("it changes where the jump at 0x34 goes")
+    );
+
+    // nothing is known about the cells at 0x10, the jmp could go anywhere
+    let source = ".org 30\n.dp 10\npsi\npsi\ndi\ndd\njmp\n";
+    let optimized = assemble(source, true);
+    assert_eq!(
+        optimized.optimizations[0].skipped.as_deref(),
+        Some("the jump at 0x34 has a target that isn't known")
+    );
+}
+
+#[test]
+fn rewrites_need_what_they_pop_on_the_stack() {
+    // swp swp with a single nibble on the stack pops past the bottom and changes sp
+    assert_eq!(assemble(".org 30\npsi\nswp\nswp\n", true).optimizations, []);
+    assert_eq!(assemble(".org 30\ninc\ndec\n", true).optimizations, []);
+    let source = ".org 30\npsi\npsi\nswp\nswp\ninc\ndec\n";
+    let changes: Vec<_> = assemble(source, true)
+        .optimizations
+        .iter()
+        .map(|change| change.addr)
+        .collect();
+    assert_eq!(changes, [0x32, 0x34]);
+    // nothing is known about the stack after a jmp, only rules that pop nothing apply
+    let source = ".org 30\njmp\nswp\nswp\ndi\ndd\n";
+    let changes: Vec<_> = assemble(source, true)
+        .optimizations
+        .iter()
+        .map(|change| change.addr)
+        .collect();
+    assert_eq!(changes, [0x33]);
+}
+
+#[test]
+fn programs_pass_their_expectations_when_optimized() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../programs/v3");
+    let programs = ["mul", "test", "add_test", "hello_world", "echo"];
+    let mut saved = 0;
+    for name in programs {
+        let source = std::fs::read_to_string(dir.join(format!("{}.asm", name))).unwrap();
+        saved += assemble(&source, true)
+            .optimizations
+            .iter()
+            .filter(|change| change.skipped.is_none())
+            .map(|change| change.saved())
+            .sum::<usize>();
+    }
+    assert!(saved > 0);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mcctest"))
+        .arg("-O")
+        .args(programs.map(|name| format!("{}.asm", name)))
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
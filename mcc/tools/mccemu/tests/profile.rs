@@ -0,0 +1,52 @@
+//! Profiles programs/v3/mul.asm
+
+use std::{path::Path, rc::Rc};
+
+use libmcc::v3::Instruction::{self, *};
+use mccemu::{asm::load_image, ext::ExtManager, profile::Profile, session::Session};
+
+#[test]
+fn mul() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../programs/v3/mul.asm");
+    let (image, symbols) = load_image(path.to_str().unwrap(), None).unwrap();
+    let extmgr = Rc::new(ExtManager::from_extensions(Vec::new()));
+    let mut emulator = mccemu::new_emulator(image, extmgr.clone());
+    emulator.start();
+    let mut session = Session::new(emulator, extmgr);
+    session.profile = Some(Profile::default());
+    while session.is_running() {
+        session.tick().unwrap();
+    }
+    let profile = session.profile.unwrap();
+
+    assert_eq!(profile.cycles, 65);
+    assert_eq!(profile.opcodes.iter().sum::<u64>(), profile.cycles);
+    let opcode = |inst: Instruction| profile.opcodes[inst.into_u4().into_usize()];
+    // 10 pushes in main, 2 more for &&end and 2 in each of the 3 loops
+    assert_eq!(opcode(Psi), 18);
+    assert_eq!(opcode(Dd), 11);
+    assert_eq!(opcode(Jnz), 3);
+    assert_eq!(opcode(Jmp), 3);
+    assert_eq!(opcode(Sub), 0);
+
+    // a and b are 3 so the loop runs 3 times
+    assert_eq!(profile.executed[0x50], 3);
+    assert_eq!(profile.executed[0x59], 1);
+    let report = profile.report(Some(&symbols)).to_string();
+    let mut lines = report
+        .lines()
+        .skip_while(|line| *line != "hottest addresses:");
+    let hottest: Vec<_> = lines.nth(1).unwrap().split_whitespace().collect();
+    assert_eq!(hottest, ["0x50", "loop", "3"]);
+
+    // the accesses to ip, dp and sp aren't counted
+    for addr in 0x00..0x05 {
+        assert_eq!((profile.reads[addr], profile.writes[addr]), (0, 0));
+    }
+    // main pushes 10 nibbles of data
+    assert_eq!(profile.reads[0x20..0x2a], [1; 10]);
+    // b, iv and &loop in mul_data
+    assert_eq!(profile.reads[0x60..0x63], [3, 5, 2]);
+    assert_eq!(profile.writes[0x60..0x63], [2, 4, 0]);
+    assert_eq!(profile.writes.iter().sum::<u64>(), 36);
+}
@@ -0,0 +1,55 @@
+//! Checks programs/v3/mul.asm with the property API
+
+use std::collections::HashMap;
+
+use libmcc::u4;
+use mccemu::expr::Expr;
+
+mod common;
+
+#[test]
+fn mul_multiplies_every_pair() {
+    let report = common::mul()
+        .check(|values| vec![values[0].overflowing_mul(values[1])])
+        .unwrap();
+    assert_eq!(report.cases, 256);
+    assert!(report.exhaustive);
+}
+
+#[test]
+fn wrong_reference_reports_the_first_failing_input() {
+    let failure = common::mul()
+        .check(|values| vec![values[0].overflowing_add(values[1])])
+        .unwrap_err();
+    // a=0 b=0 passes, 0x1 * 0x0 is the first case where a + b differs
+    assert_eq!(
+        failure.inputs,
+        vec![("a".into(), u4::ONE), ("b".into(), u4::ZERO)]
+    );
+    assert_eq!(failure.expected, vec![u4::ONE]);
+    assert_eq!(failure.actual, Ok(vec![u4::ZERO]));
+    assert!(!failure.trace.is_empty());
+}
+
+#[test]
+fn sampled_when_there_are_too_many_combinations() {
+    let mut property = common::mul();
+    property.max_cases = 100;
+    let report = property
+        .check(|values| vec![values[0].overflowing_mul(values[1])])
+        .unwrap();
+    assert_eq!(report.cases, 100);
+    assert!(!report.exhaustive);
+}
+
+#[test]
+fn expressions() {
+    let vars = HashMap::from([("a", 3), ("b", 5)]);
+    let eval = |text| Expr::parse(text).unwrap().eval(&vars).unwrap();
+    assert_eq!(eval("a * b"), 15);
+    assert_eq!(eval("a + b * 2"), 13);
+    assert_eq!(eval("(a + b) * 2 & 0xF"), 0);
+    assert_eq!(eval("b << 1 | 1"), 11);
+    assert!(Expr::parse("a +").is_err());
+    assert!(Expr::parse("c").unwrap().eval(&vars).is_err());
+}
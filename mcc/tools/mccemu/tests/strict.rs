@@ -0,0 +1,101 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use libmcc::{
+    u4,
+    v3::Instruction::{self, *},
+};
+use mccemu::{
+    ext::ExtManager,
+    halt::HaltReason,
+    session::Session,
+    strict::{Strict, Violation},
+    symbols::{LineMap, SymbolMap},
+};
+
+///`code` at 0x30 and 3 at dp, only those cells count as initialized
+fn session(code: &[Instruction], symbols: &SymbolMap) -> Session {
+    let mut mem = [u4::ZERO; 256];
+    mem[0x20] = u4::from_low(3);
+    let mut lines = BTreeMap::from([(0x20, 1)]);
+    for (i, inst) in code.iter().enumerate() {
+        mem[0x30 + i] = inst.into_u4();
+        lines.insert(0x30 + i as u8, 2 + i);
+    }
+    let lines = LineMap::from(lines);
+    let extmgr = Rc::new(ExtManager::from_extensions(Vec::new()));
+    let mut emulator = mccemu::new_emulator(mem, extmgr.clone());
+    emulator.start();
+    let mut session = Session::new(emulator, extmgr);
+    session.strict = Some(Strict::new(Some(symbols), Some(&lines)));
+    session
+}
+
+///Runs until the vm halts and returns the violation and the ip it happened at
+fn trap(code: &[Instruction]) -> Option<(Violation, u8)> {
+    trap_with(code, &SymbolMap::default())
+}
+
+fn trap_with(code: &[Instruction], symbols: &SymbolMap) -> Option<(Violation, u8)> {
+    let mut session = session(code, symbols);
+    while session.is_running() {
+        session.tick().unwrap();
+    }
+    let trap = session.strict.unwrap().trap?;
+    assert_eq!(session.halt_reason, Some(HaltReason::Strict));
+    Some((trap.violation, trap.ip))
+}
+
+#[test]
+fn correct_programs_run_to_the_end() {
+    assert_eq!(trap(&[Psi, Dd, Psi, Add, Pod]), None);
+}
+
+#[test]
+fn stack_underflow() {
+    assert_eq!(trap(&[Psi, Add]), Some((Violation::StackUnderflow, 0x31)));
+    // jnz reads the top of the stack even though it doesn't pop it
+    assert_eq!(trap(&[Jnz]), Some((Violation::StackUnderflow, 0x30)));
+}
+
+#[test]
+fn stack_overflow() {
+    let mut code = vec![Psi, Dd];
+    code.extend(std::iter::repeat_n([Psd, Di], 15).flatten());
+    assert_eq!(trap(&code), Some((Violation::StackOverflow, 0x4E)));
+}
+
+#[test]
+fn dp_wrap() {
+    // dp moves down from 0x20 to 0x00 and then wraps
+    let code = vec![Dd; 0x21];
+    assert_eq!(trap(&code), Some((Violation::DpWrap, 0x50)));
+}
+
+#[test]
+fn register_write() {
+    // dp moves down from 0x21 to 0x02, the low nibble of dp
+    let mut code = vec![Psi];
+    code.extend([Dd; 0x1F]);
+    code.push(Pod);
+    assert_eq!(trap(&code), Some((Violation::RegisterWrite(0x02), 0x50)));
+}
+
+#[test]
+fn uninitialized_read() {
+    assert_eq!(
+        trap(&[Psi, Psi]),
+        Some((Violation::Uninitialized(0x21), 0x31))
+    );
+    // a cell the program wrote first can be read
+    assert_eq!(trap(&[Psi, Poi, Dd, Psi]), None);
+}
+
+#[test]
+fn executing_data() {
+    let mut symbols = SymbolMap::default();
+    symbols.mark_data(0x32);
+    assert_eq!(
+        trap_with(&[Psi, Pod], &symbols),
+        Some((Violation::ExecuteData, 0x32))
+    );
+}
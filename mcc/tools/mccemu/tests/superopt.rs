@@ -0,0 +1,71 @@
+//! Searches short sequences with the superoptimizer
+
+use libmcc::v3::{
+    parse_sequence,
+    Instruction::{self, *},
+};
+use mccasm::asm::v3::peephole::RULES;
+use mccemu::superopt::{self, Effect, Rewrite, Search};
+
+fn shortest_for(effects: &[&str]) -> Vec<Vec<Instruction>> {
+    let effects: Vec<_> = effects.iter().map(|e| Effect::parse(e).unwrap()).collect();
+    Search::effects(&effects, 256, 1).unwrap().shortest(3, 16)
+}
+
+#[test]
+fn swp_swp_is_nothing() {
+    let search = Search::reference(&[Swp, Swp], 256, 1).unwrap();
+    assert_eq!(search.shortest(2, 16), vec![Vec::<Instruction>::new()]);
+}
+
+#[test]
+fn stack_effects_find_single_instructions() {
+    assert_eq!(shortest_for(&["a b -- b a"]), vec![vec![Swp]]);
+    assert_eq!(shortest_for(&["a b -- a*b"]), vec![vec![Mul]]);
+    assert_eq!(shortest_for(&["a -- a+2"]), vec![vec![Inc, Inc]]);
+    // sub computes top - second
+    assert_eq!(shortest_for(&["a b -- b-a"]), vec![vec![Sub]]);
+}
+
+#[test]
+fn test_vectors_narrow_the_search() {
+    // every vector has to hold for the same sequence
+    assert_eq!(shortest_for(&["1 2 -- 3", "2 0 -- 2"]), vec![vec![Add]]);
+}
+
+#[test]
+fn dup_needs_memory() {
+    // the only way to copy a value is through a memory cell
+    assert!(shortest_for(&["a -- a a"]).is_empty());
+}
+
+#[test]
+fn peephole_finds_the_obvious_pairs() {
+    let rewrites = superopt::peephole(2, 64, 1);
+    for (from, to) in [
+        (vec![Swp, Swp], vec![]),
+        (vec![Di, Dd], vec![]),
+        (vec![Inc, Dec], vec![]),
+        (vec![Di, Mdp], vec![Mdp]),
+    ] {
+        assert!(rewrites.contains(&Rewrite { from, to }));
+    }
+    // add is commutative but swp add leaves a different value in the dead stack cell
+    assert!(!rewrites.iter().any(|rewrite| rewrite.from == [Swp, Add]));
+}
+
+#[test]
+fn assembler_rules_hold() {
+    // mccasm -O only applies these where the stack holds what they pop, like the tests of a reference
+    for (from, to) in RULES {
+        let search = Search::reference(from, 256, 1).unwrap();
+        assert!(search.matches(to), "{:?} -> {:?}", from, to);
+    }
+}
+
+#[test]
+fn parse_errors() {
+    assert!(Effect::parse("a b").is_err());
+    assert!(Effect::parse("a+1 -- a").is_err());
+    assert!(parse_sequence("swp nope").is_err());
+}
@@ -0,0 +1,117 @@
+//! Checks the symbolic engine on programs/v3/mul.asm, small images and against the emulator
+
+use libmcc::{u4, v3::Instruction::*};
+use mccemu::{
+    fuzz::{self, Rng},
+    prop::{Output, Property},
+    symbolic::{Disproof, Engine, IssueKind},
+};
+mod common;
+
+fn mul() -> Engine {
+    let property = common::mul();
+    let mut engine = Engine::new(property.image);
+    engine.inputs = property.inputs;
+    engine
+}
+
+#[test]
+fn mul_is_proven_for_every_pair() {
+    let exploration = mul().explore();
+    // one path for every number of loop iterations
+    assert_eq!(exploration.halts.len(), 16);
+    assert!(exploration.issues.is_empty());
+    assert_eq!(exploration.jumps[&0x58].len(), 1);
+    let cases = exploration
+        .prove(&[Output::StackTop], |values| {
+            vec![values[0].overflowing_mul(values[1])]
+        })
+        .unwrap();
+    assert_eq!(cases, 256);
+}
+
+#[test]
+fn wrong_reference_is_disproven() {
+    let disproof = mul()
+        .explore()
+        .prove(&[Output::StackTop], |values| {
+            vec![values[0].overflowing_add(values[1])]
+        })
+        .unwrap_err();
+    let Disproof::Mismatch {
+        inputs,
+        expected,
+        actual,
+    } = disproof
+    else {
+        panic!("{:?}", disproof);
+    };
+    assert_ne!(expected, actual);
+    assert_eq!(actual, vec![inputs[0].overflowing_mul(inputs[1])]);
+}
+
+#[test]
+fn underflow_is_reported_for_the_inputs_that_reach_it() {
+    let mut image = fuzz::image_from_bytes(&[]);
+    image[0x21] = u4::from_low(0x4);
+    // psi x, jnz to 0x34 unless x is zero, pod pod underflows
+    for (i, inst) in [Psi, Jnz, Pod, Pod].into_iter().enumerate() {
+        image[0x30 + i] = inst.into_u4();
+    }
+    let mut engine = Engine::new(image);
+    engine.inputs.push(("x".into(), 0x20));
+    let exploration = engine.explore();
+
+    assert_eq!(exploration.halts.len(), 2);
+    assert_eq!(
+        exploration.jumps[&0x31].iter().copied().collect::<Vec<_>>(),
+        [0x34]
+    );
+    let underflow = &exploration.issues[&(IssueKind::StackUnderflow, 0x33)];
+    assert_eq!(underflow.example(), Some(vec![u4::ZERO]));
+    assert_eq!(underflow.len(), 1);
+}
+
+#[test]
+fn symbolic_jump_reaches_every_target() {
+    let mut image = fuzz::image_from_bytes(&[]);
+    image[0x20] = u4::from_low(0xF);
+    // push 0xF and x, jmp to 0xF0 + x
+    for (i, inst) in [Psi, Psi, Jmp].into_iter().enumerate() {
+        image[0x30 + i] = inst.into_u4();
+    }
+    let mut engine = Engine::new(image);
+    engine.inputs.push(("x".into(), 0x21));
+    let exploration = engine.explore();
+
+    assert_eq!(exploration.jumps[&0x32].len(), 16);
+    assert_eq!(exploration.halts.len(), 16);
+    assert!(exploration.unfinished.is_empty());
+}
+
+#[test]
+fn random_images_agree_with_the_emulator() {
+    let mut rng = Rng::new(0x73796D);
+    for _ in 0..200 {
+        let image = rng.image();
+        let mut engine = Engine::new(image);
+        engine.inputs.push(("x".into(), 0x20));
+        engine.max_cycles = 300;
+        let mut property = Property::new(image);
+        property.inputs = engine.inputs.clone();
+        property.outputs.push(Output::StackTop);
+        property.max_cycles = engine.max_cycles;
+
+        let exploration = engine.explore();
+        for halt in exploration.halts.iter() {
+            for values in halt.domain.assignments() {
+                let run = property.run(&values);
+                assert_eq!(run.outputs, Ok(vec![halt.stack_top().eval(&values)]));
+                assert_eq!(run.cycles, halt.cycles);
+            }
+        }
+        for values in exploration.unfinished.assignments() {
+            assert!(property.run(&values).outputs.is_err());
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Traces programs/v3/mul.asm with mccemu --trace and compares the traces with mcctrace diff
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use libmcc::u4;
+use mccasm::emiting::emit_bin_packed;
+use mccemu::{asm::load_image, trace::parse_fields};
+
+///Runs mul.asm with `a` and returns the trace file
+fn trace(name: &str, a: u8) -> PathBuf {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../programs/v3/mul.asm");
+    let (mut image, symbols) = load_image(path.to_str().unwrap(), None).unwrap();
+    image[symbols.addr_of("a").unwrap() as usize] = u4::from_low(a);
+    let stem = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("trace-{}", name));
+    fs::write(stem.with_extension("bin"), emit_bin_packed(image)).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_mccemu"))
+        .arg(stem.with_extension("bin"))
+        .arg("--trace")
+        .arg(stem.with_extension("trace"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    stem.with_extension("trace")
+}
+
+fn diff(a: &Path, b: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mcctrace"))
+        .arg("diff")
+        .args([a, b])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn record_format() {
+    let trace = fs::read_to_string(trace("format", 3)).unwrap();
+    let lines: Vec<_> = trace.lines().collect();
+    // 65 cycles, the first psi pushes the high nibble of &&data_ret (0x2b)
+    assert_eq!(lines.len(), 65);
+    assert_eq!(
+        lines[0],
+        "cycle=1 ip=30 inst=psi dp=21 sp=1 stack=2 writes=11:2 io=-"
+    );
+    let fields = parse_fields(lines[10]);
+    assert_eq!(
+        fields[..3],
+        [("cycle", "11"), ("ip", "3a"), ("inst", "mdp")]
+    );
+    assert_eq!(fields[4], ("sp", "8"));
+    assert_eq!(parse_fields(lines[64])[1], ("ip", "ff"));
+}
+
+#[test]
+fn diff_reports_the_first_divergence() {
+    let three = trace("three", 3);
+    let two = trace("two", 2);
+    let output = diff(&three, &two);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines[0], "traces diverge");
+    // psi at 0x34 pushes a, which is the first thing that differs
+    assert!(lines[1].starts_with("  last common: cycle=4 ip=33 "));
+    assert!(lines[2].contains(": cycle=5 ip=34 inst=psi"));
+    assert!(lines[3].contains(": cycle=5 ip=34 inst=psi"));
+    assert_eq!(
+        lines[4..],
+        ["  stack: 2b3c3 != 2b3c2", "  writes: 15:3 != 15:2"]
+    );
+
+    let output = diff(&three, &trace("three_again", 3));
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,110 @@
+use std::rc::Rc;
+
+use console::{strip_ansi_codes, Key};
+use libmcc::{
+    u4,
+    v3::Instruction::{self, *},
+};
+use mccemu::{
+    ext::{chardev::SharedBuf, ExtManager},
+    session::Session,
+    symbols::SymbolMap,
+    tui::Tui,
+};
+
+///3 and 4 at dp and `code` at 0x30
+fn session(code: &[Instruction]) -> Session {
+    let mut mem = [u4::ZERO; 256];
+    mem[0x20] = u4::from_low(3);
+    mem[0x21] = u4::from_low(4);
+    for (i, inst) in code.iter().enumerate() {
+        mem[0x30 + i] = inst.into_u4();
+    }
+    let extmgr = Rc::new(ExtManager::from_extensions(Vec::new()));
+    let mut emulator = mccemu::new_emulator(mem, extmgr.clone());
+    emulator.start();
+    Session::new(emulator, extmgr)
+}
+
+fn render(tui: &Tui, session: &Session, symbols: Option<&SymbolMap>) -> Vec<String> {
+    tui.render(session, symbols)
+        .iter()
+        .map(|line| strip_ansi_codes(line).trim_end().to_string())
+        .collect()
+}
+
+fn line<'a>(lines: &'a [String], prefix: &str) -> &'a str {
+    lines
+        .iter()
+        .find(|line| line.starts_with(prefix))
+        .unwrap_or_else(|| panic!("no line starts with {:?} in {:#?}", prefix, lines))
+}
+
+#[test]
+fn registers_and_stack() {
+    let mut session = session(&[Psi, Psi, Add]);
+    let mut symbols = SymbolMap::default();
+    symbols.insert(0x20, "values");
+    symbols.insert(0x30, "start");
+    let mut tui = Tui::new(SharedBuf::default());
+    for _ in 0..2 {
+        tui.handle_key(Key::Char('s'), &mut session).unwrap();
+    }
+
+    let lines = render(&tui, &session, Some(&symbols));
+    assert!(line(&lines, " memory").ends_with(" registers"));
+    assert!(line(&lines, "     0  1  2").ends_with("ip 0x32 start+2"));
+    assert!(line(&lines, " 00").ends_with("dp 0x22 values+2"));
+    assert!(line(&lines, " 10").ends_with("sp 0x2"));
+    assert!(line(&lines, " 20").ends_with("cycle 2"));
+    assert!(line(&lines, " 30").ends_with("running"));
+    assert!(line(&lines, " 60").ends_with("[3 4]"));
+
+    tui.handle_key(Key::Char('s'), &mut session).unwrap();
+    let lines = render(&tui, &session, Some(&symbols));
+    assert!(line(&lines, " 60").ends_with("[7]"));
+}
+
+#[test]
+fn memory_shows_cells_and_the_cursor() {
+    let mut session = session(&[Psi, Psi, Add]);
+    let mut tui = Tui::new(SharedBuf::default());
+    tui.cursor = 0x21;
+    let lines = render(&tui, &session, None);
+    assert!(line(&lines, " 20").starts_with(" 20  3 [4] 0  0 "));
+    let code = format!(
+        " 30  {:x}  {:x}  {:x}  0 ",
+        Psi.into_u4(),
+        Psi.into_u4(),
+        Add.into_u4()
+    );
+    assert!(line(&lines, " 30").starts_with(&code));
+
+    // moving the cursor onto a cell and setting a breakpoint marks the disassembly
+    tui.handle_key(Key::ArrowDown, &mut session).unwrap();
+    tui.handle_key(Key::ArrowLeft, &mut session).unwrap();
+    tui.handle_key(Key::Char('b'), &mut session).unwrap();
+    assert_eq!(tui.message, "added breakpoint 0x30");
+    let lines = render(&tui, &session, None);
+    assert!(lines.iter().any(|line| line.ends_with(">*0x30 psi")));
+}
+
+#[test]
+fn halted_and_stepped_back() {
+    let mut session = session(&[Psi]);
+    session.set_history(16);
+    let mut tui = Tui::new(SharedBuf::default());
+    tui.handle_key(Key::Char('s'), &mut session).unwrap();
+    let lines = render(&tui, &session, None);
+    assert!(line(&lines, " 60").ends_with("[3]"));
+
+    tui.handle_key(Key::Char('u'), &mut session).unwrap();
+    assert_eq!(tui.message, "stepped back 1 cycles");
+    let lines = render(&tui, &session, None);
+    assert!(line(&lines, " 60").ends_with("[]"));
+    assert!(line(&lines, "     0  1  2").ends_with("ip 0x30"));
+
+    session.emulator.stop();
+    let lines = render(&tui, &session, None);
+    assert!(line(&lines, " 30").ends_with("halted"));
+}